@@ -1,136 +1,672 @@
-use super::{ApiProvider, Config};
-use thiserror::Error;
-
-#[derive(Error, Debug)]
-pub enum ApiError {
-    #[error("Empty API response")]
-    EmptyResponse,
-    #[error("API request failed: {0}")]
-    RequestFailed(String),
-    #[error("Response parsing failed: {0}")]
-    ParseFailure(String),
-    #[error("Configuration error: {0}")]
-    ConfigError(String),
-}
-use reqwest::blocking::Client;
-use serde_json::Value;
-use anyhow::Result;
-
-pub trait ApiClient {
-    fn generate_comment(&self, system_prompt: &str, diff: &str) -> Result<String>;
-}
-
-pub struct OpenAIClient {
-    client: Client,
-    api_key: String,
-    endpoint: String,
-    model: String,
-}
-
-pub struct ClaudeClient {
-    client: Client,
-    api_key: String,
-    endpoint: String,
-    model: String,
-}
-
-impl OpenAIClient {
-    pub fn new(config: &Config) -> Result<Self> {
-        Ok(Self {
-            client: Client::new(),
-            api_key: config.openai_api_key.clone().ok_or(ApiError::ConfigError("Missing OpenAI API key".into()))?,
-            endpoint: config.openai_endpoint.clone().unwrap_or_else(|| "https://api.openai.com/v1/chat/completions".into()),
-            model: config.openai_model.clone().unwrap_or_else(|| "gpt-4-turbo".into()),
-        })
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use reqwest::blocking::{multipart, Client, RequestBuilder, Response};
+use reqwest::header::HeaderValue;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader};
+use std::time::Instant;
+
+use crate::schema::Schema;
+use crate::{
+    api_error, audit, estimate_tokens, max_output_tokens, models, record_usage, warn, ApiProvider, Failure, Length,
+    ModelSettings,
+};
+
+// Times a reply that hit the output limit is continued before it is used as it is
+const MAX_CONTINUATIONS: usize = 3;
+
+const CONTINUE_PROMPT: &str = "Your reply was cut off by the output limit. Continue exactly where it stopped, \
+without repeating anything and without any preamble.";
+
+// Beta that enables the Claude Files API and file_id document sources
+const CLAUDE_FILES_BETA: &str = "files-api-2025-04-14";
+
+// Provider-neutral conversation: prompts, continuations and structured output are built once, as a
+// ChatRequest, and each provider only translates it to its request body and its reply to a ChatResponse
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+#[derive(Clone)]
+pub struct ChatRequest<'a> {
+    pub system: &'a str,
+    pub messages: Vec<ChatMessage>,
+    // Structured output: OpenAI structured outputs or a Claude tool call with this input schema
+    pub schema: Option<&'a Schema>,
+    // Uploaded file sent along with the first user message
+    pub attachment: Option<Attachment>,
+}
+
+// A text file uploaded to the provider (OpenAI files / Claude Files API)
+#[derive(Clone)]
+pub struct Attachment {
+    pub id: String,
+    pub name: String,
+}
+
+pub struct ChatResponse {
+    pub text: String,
+    // Cut off by the output limit (OpenAI finish_reason "length", Claude stop_reason "max_tokens")
+    pub truncated: bool,
+    pub usage: Usage,
+}
+
+// The whole reply to a request and the model that wrote it: the fallback model when the configured
+// one didn't answer in time
+pub struct Reply {
+    pub text: String,
+    pub model: String,
+}
+
+// Tokens of one request as the provider reports them; reasoning is part of output
+#[derive(Default)]
+pub struct Usage {
+    pub input: usize,
+    pub output: usize,
+    pub reasoning: usize,
+}
+
+impl<'a> ChatRequest<'a> {
+    pub fn new(system: &'a str, user: &str) -> Self {
+        ChatRequest {
+            system,
+            messages: vec![ChatMessage {
+                role: Role::User,
+                content: user.to_string(),
+            }],
+            schema: None,
+            attachment: None,
+        }
+    }
+
+    pub fn with_attachment(mut self, attachment: Option<Attachment>) -> Self {
+        self.attachment = attachment;
+        self
+    }
+
+    pub fn with_schema(mut self, schema: &'a Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    // The same conversation followed by the reply so far and a request to continue it
+    fn continuation(&self, partial: &str) -> Self {
+        let mut request = self.clone();
+        request.messages.push(ChatMessage {
+            role: Role::Assistant,
+            content: partial.to_string(),
+        });
+        request.messages.push(ChatMessage {
+            role: Role::User,
+            content: CONTINUE_PROMPT.to_string(),
+        });
+        request
     }
 }
 
-impl ClaudeClient {
-    pub fn new(config: &Config) -> Result<Self> {
-        Ok(Self {
-            client: Client::new(),
-            api_key: config.claude_api_key.clone().ok_or(ApiError::ConfigError("Missing Claude API key".into()))?,
-            endpoint: config.claude_endpoint.clone().unwrap_or_else(|| "https://api.anthropic.com/v1/messages".into()),
-            model: config.claude_model.clone().unwrap_or_else(|| "claude-3-7-sonnet-20250219".into()),
-        })
+// Send the request and return the whole reply, continuing it while the provider cuts it off. With a
+// fallback model, a model that hasn't answered by the fallback time is given up on and the request is
+// sent again to the fallback model, which has until the deadline.
+pub fn send(settings: &ModelSettings, request: &ChatRequest) -> Result<Reply> {
+    let reply = |text| Reply {
+        text,
+        model: settings.model.clone(),
+    };
+    let Some((fallback_model, fallback_at)) = &settings.fallback else {
+        return send_until(settings, request, settings.deadline).map(reply);
+    };
+    match send_until(settings, request, Some(*fallback_at)) {
+        Err(err) if Instant::now() >= *fallback_at => {
+            warn(&format!(
+                "{} didn't answer in time, retrying with {}: {:#}",
+                settings.model, fallback_model, err
+            ));
+            // Fallback models are picked for speed, and small ones often don't support reasoning
+            let fallback = ModelSettings {
+                model: fallback_model.clone(),
+                reasoning: None,
+                fallback: None,
+                ..settings.clone()
+            };
+            send_until(&fallback, request, settings.deadline).map(|text| Reply {
+                text,
+                model: fallback.model,
+            })
+        }
+        result => result.map(reply),
     }
 }
 
-impl ApiClient for OpenAIClient {
-    fn generate_comment(&self, system_prompt: &str, diff: &str) -> Result<String> {
-        let request_body = json!({
-            "model": &self.model,
-            "messages": [
-                {
-                    "role": "system",
-                    "content": system_prompt
-                },
-                {
-                    "role": "user",
-                    "content": format!("Git diff:\n\n{}", diff)
-                }
-            ],
-            "temperature": 0.7
+fn send_until(settings: &ModelSettings, request: &ChatRequest, deadline: Option<Instant>) -> Result<String> {
+    online(settings)?;
+    let mut client = Client::builder();
+    if let Some(deadline) = deadline {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            anyhow::bail!("Deadline exceeded before the request was sent");
+        }
+        client = client.timeout(remaining);
+    }
+    let client = client.build().context("Failed to create HTTP client")?;
+
+    with_continuations(request, |request| {
+        audited(settings, &body(settings, request, false), |body| {
+            parse_reply(settings, post(&client, settings, request, body)?, request.schema.is_some())
+        })
+    })
+}
+
+// Like send, but hands each piece of the reply to on_text as the provider streams it. Continuations
+// stream on from where the cut-off reply stopped.
+pub fn stream(settings: &ModelSettings, request: &ChatRequest, mut on_text: impl FnMut(&str) -> Result<()>) -> Result<String> {
+    online(settings)?;
+    let client = Client::builder().timeout(None).build().context("Failed to create HTTP client")?;
+
+    with_continuations(request, |request| {
+        audited(settings, &body(settings, request, true), |body| {
+            parse_stream(settings, post(&client, settings, request, body)?, &mut on_text)
+        })
+    })
+}
+
+// Add the provider's authentication and the configured headers, which replace built-in ones of the same name
+pub fn authorize(request: RequestBuilder, settings: &ModelSettings) -> RequestBuilder {
+    let request = match settings.provider {
+        ApiProvider::OpenAi => request.header("Authorization", format!("Bearer {}", settings.api_key)),
+        ApiProvider::Claude => request
+            .header("x-api-key", &settings.api_key)
+            .header("anthropic-version", "2023-06-01"),
+    };
+    request.headers(settings.headers.clone())
+}
+
+// The provider's files endpoint, next to its chat endpoint. None when the endpoint isn't one whose
+// provider accepts file inputs, such as a gateway with its own paths.
+pub fn files_url(settings: &ModelSettings) -> Option<String> {
+    let chat_path = match settings.provider {
+        ApiProvider::OpenAi => "/chat/completions",
+        ApiProvider::Claude => "/messages",
+    };
+    let base = settings.endpoint.trim_end_matches('/').strip_suffix(chat_path)?;
+    Some(format!("{}/files", base))
+}
+
+// Upload text as a file the next requests can refer to; the upload is audited like a request
+pub fn upload(settings: &ModelSettings, name: &str, text: &str) -> Result<Attachment> {
+    online(settings)?;
+    let provider = provider_name(settings);
+    let url = files_url(settings).with_context(|| format!("{} endpoint doesn't accept file uploads", provider))?;
+    let record = audit::start(settings, &url, &json!({ "file": name, "content": text }))?;
+
+    let file = multipart::Part::text(text.to_string()).file_name(name.to_string()).mime_str("text/plain")?;
+    let form = match settings.provider {
+        // user_data is the purpose for files given to a model as input
+        ApiProvider::OpenAi => multipart::Form::new().text("purpose", "user_data").part("file", file),
+        ApiProvider::Claude => multipart::Form::new().part("file", file),
+    };
+    let result = files_request(Client::new().post(&url), settings)
+        .multipart(form)
+        .send()
+        .with_context(|| format!("Failed to upload file to {} API", provider))
+        .and_then(|response| {
+            if !response.status().is_success() {
+                return Err(api_error(provider, response));
+            }
+            let body: Value = response.json().with_context(|| format!("Failed to parse {} upload response", provider))?;
+            body["id"]
+                .as_str()
+                .map(str::to_string)
+                .with_context(|| format!("{} upload response contained no file id", provider))
         });
+    if let Some(record) = record {
+        record.finish(result.as_deref().map_err(|err| format!("{:#}", err)));
+    }
+    Ok(Attachment {
+        id: result?,
+        name: name.to_string(),
+    })
+}
 
-        let response = self.client
-            .post(&self.endpoint)
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request_body)
-            .send()
-            .context("Failed to call OpenAI API")?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().unwrap_or_else(|_| "Could not read error response".to_string());
-            return Err(ApiError::RequestFailed(format!("OpenAI API error: {}", error_text)).into());
+// Delete an uploaded file once the requests that use it are done; a failure only leaves it to expire
+pub fn delete(settings: &ModelSettings, attachment: &Attachment) {
+    let Some(url) = files_url(settings) else {
+        return;
+    };
+    let url = format!("{}/{}", url, attachment.id);
+    let result = files_request(Client::new().delete(&url), settings).send();
+    match result {
+        Ok(response) if response.status().is_success() => {}
+        Ok(response) => warn(&format!("could not delete uploaded file {}: {}", attachment.id, response.status())),
+        Err(err) => warn(&format!("could not delete uploaded file {}: {}", attachment.id, err)),
+    }
+}
+
+// Authorized like other requests; Claude also needs the files beta, added to any configured betas
+fn files_request(request: RequestBuilder, settings: &ModelSettings) -> RequestBuilder {
+    if let ApiProvider::OpenAi = settings.provider {
+        return authorize(request, settings);
+    }
+    let beta = match settings.headers.get("anthropic-beta").and_then(|value| value.to_str().ok()) {
+        Some(betas) => format!("{},{}", betas, CLAUDE_FILES_BETA),
+        None => CLAUDE_FILES_BETA.to_string(),
+    };
+    let mut settings = settings.clone();
+    if let Ok(beta) = HeaderValue::from_str(&beta) {
+        settings.headers.insert("anthropic-beta", beta);
+    }
+    authorize(request, &settings)
+}
+
+// Offline runs never reach the provider; anything that needs the model says so
+fn online(settings: &ModelSettings) -> Result<()> {
+    if settings.offline {
+        anyhow::bail!("This needs the model, and the run is offline (--offline, or no API key is configured)");
+    }
+    Ok(())
+}
+
+// Provider name for messages
+pub fn provider_name(settings: &ModelSettings) -> &'static str {
+    match settings.provider {
+        ApiProvider::OpenAi => "OpenAI",
+        ApiProvider::Claude => "Claude",
+    }
+}
+
+fn with_continuations(
+    request: &ChatRequest,
+    mut send_part: impl FnMut(&ChatRequest) -> Result<ChatResponse>,
+) -> Result<String> {
+    let mut reply = String::new();
+    for _ in 0..=MAX_CONTINUATIONS {
+        let part = if reply.is_empty() { send_part(request)? } else { send_part(&request.continuation(&reply))? };
+        record_usage(part.usage.input, part.usage.output, part.usage.reasoning);
+        reply += &part.text;
+        if !part.truncated {
+            return Ok(reply);
+        }
+        // A cut-off tool call or JSON object can't be continued as text
+        if request.schema.is_some() {
+            anyhow::bail!("Structured response was cut off at the output limit; try --format markdown or a smaller diff");
         }
+    }
 
-        let response_body: Value = response.json()
-            .context("Failed to parse OpenAI API response")?;
+    warn(&format!("the reply is still cut off after {} continuations", MAX_CONTINUATIONS));
+    Ok(reply)
+}
 
-        response_body["choices"][0]["message"]["content"]
-            .as_str()
-            .map(|s| s.to_string())
-            .ok_or(ApiError::EmptyResponse.into())
+// Send one request body with the audit log, if configured, recording it first and the reply after
+fn audited(
+    settings: &ModelSettings,
+    body: &Value,
+    send_body: impl FnOnce(&Value) -> Result<ChatResponse>,
+) -> Result<ChatResponse> {
+    let record = audit::start(settings, &settings.endpoint, body)?;
+    let reply = send_body(body);
+    if let Some(record) = record {
+        record.finish(reply.as_ref().map(|reply| reply.text.as_str()).map_err(|err| format!("{:#}", err)));
     }
+    reply
 }
 
-impl ApiClient for ClaudeClient {
-    fn generate_comment(&self, system_prompt: &str, diff: &str) -> Result<String> {
-        let request_body = json!({
-            "model": &self.model,
-            "system": system_prompt,
-            "messages": [
-                {
-                    "role": "user",
-                    "content": format!("Git diff:\n\n{}", diff)
-                }
-            ],
+fn post(client: &Client, settings: &ModelSettings, request: &ChatRequest, body: &Value) -> Result<Response> {
+    let provider = provider_name(settings);
+    let post = client.post(&settings.endpoint);
+    let post = if request.attachment.is_some() { files_request(post, settings) } else { authorize(post, settings) };
+    let response = post
+        .json(body)
+        .send()
+        .with_context(|| format!("Failed to call {} API", provider))?;
+    if !response.status().is_success() {
+        return Err(api_error(provider, response));
+    }
+    Ok(response)
+}
+
+// The provider's request body for the conversation
+fn body(settings: &ModelSettings, request: &ChatRequest, stream: bool) -> Value {
+    let mut messages: Vec<Value> = request
+        .messages
+        .iter()
+        .map(|message| {
+            let role = match message.role {
+                Role::User => "user",
+                Role::Assistant => "assistant",
+            };
+            json!({ "role": role, "content": message.content })
+        })
+        .collect();
+    // OpenAI models without a system role (o1-mini, o1-preview) get the instructions at the top of the first message
+    let system_role = matches!(settings.provider, ApiProvider::Claude) || models::supports_system_role(&settings.model);
+    if !system_role && !request.system.is_empty() {
+        if let Some(first) = messages.first_mut() {
+            let text = format!("{}\n\n{}", request.system, first["content"].as_str().unwrap_or_default());
+            first["content"] = json!(text);
+        }
+    }
+    // The file goes before the text of the first message, which refers to it
+    if let Some(attachment) = &request.attachment {
+        let file = match settings.provider {
+            ApiProvider::OpenAi => json!({ "type": "file", "file": { "file_id": attachment.id } }),
+            ApiProvider::Claude => json!({
+                "type": "document",
+                "source": { "type": "file", "file_id": attachment.id },
+                "title": attachment.name
+            }),
+        };
+        let text = json!({ "type": "text", "text": messages[0]["content"] });
+        messages[0]["content"] = json!([file, text]);
+    }
+
+    let mut body = match settings.provider {
+        ApiProvider::OpenAi => {
+            let mut all_messages = Vec::new();
+            if system_role {
+                all_messages.push(json!({ "role": "system", "content": request.system }));
+            }
+            all_messages.extend(messages);
+            json!({
+                "model": settings.model,
+                "messages": all_messages,
+                "temperature": 0.7
+            })
+        }
+        ApiProvider::Claude => json!({
+            "model": settings.model,
+            "system": request.system,
+            "messages": messages,
             "temperature": 0.7,
-            "max_tokens": 4000
-        });
+            "max_tokens": models::output_tokens(settings.length)
+        }),
+    };
+    if stream {
+        body["stream"] = json!(true);
+        if let ApiProvider::OpenAi = settings.provider {
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+    }
+    apply_reasoning(settings, &mut body);
+    apply_length(settings, &mut body);
+
+    if let Some(schema) = request.schema {
+        match settings.provider {
+            ApiProvider::OpenAi => {
+                body["response_format"] = json!({
+                    "type": "json_schema",
+                    "json_schema": {
+                        "name": schema.name,
+                        "strict": true,
+                        "schema": schema.definition
+                    }
+                });
+            }
+            ApiProvider::Claude => {
+                body["tools"] = json!([{
+                    "name": schema.name,
+                    "description": schema.description,
+                    "input_schema": schema.definition
+                }]);
+                // Extended thinking can't be combined with forcing a tool, so the model is only offered it
+                body["tool_choice"] = if settings.reasoning.is_some() {
+                    json!({ "type": "auto" })
+                } else {
+                    json!({ "type": "tool", "name": schema.name })
+                };
+            }
+        }
+    }
+    body
+}
+
+// Request changes for --reasoning and for OpenAI reasoning models, which reject a custom temperature
+fn apply_reasoning(settings: &ModelSettings, body: &mut Value) {
+    let reasoning_model = matches!(settings.provider, ApiProvider::OpenAi) && models::is_reasoning_model(&settings.model);
+    if settings.reasoning.is_none() && !reasoning_model {
+        return;
+    }
+    if let Some(body) = body.as_object_mut() {
+        body.remove("temperature");
+    }
+
+    let Some(reasoning) = settings.reasoning else {
+        return;
+    };
+    match settings.provider {
+        ApiProvider::OpenAi => {
+            body["reasoning_effort"] = json!(reasoning.to_possible_value().map(|value| value.get_name().to_string()));
+        }
+        ApiProvider::Claude => {
+            // The thinking budget counts towards max_tokens, so the reply keeps its own room
+            let budget = models::thinking_budget(reasoning);
+            body["thinking"] = json!({ "type": "enabled", "budget_tokens": budget });
+            body["max_tokens"] = json!(max_output_tokens(settings));
+        }
+    }
+}
+
+// OpenAI requests have no output limit unless --length asks for a shorter or longer comment than usual.
+// Claude requests always carry max_tokens.
+fn apply_length(settings: &ModelSettings, body: &mut Value) {
+    if matches!(settings.provider, ApiProvider::OpenAi) && settings.length != Length::Medium {
+        body["max_completion_tokens"] = json!(max_output_tokens(settings));
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAIResponse {
+    choices: Vec<OpenAIChoice>,
+    #[serde(default)]
+    usage: Option<OpenAIUsage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAIUsage {
+    prompt_tokens: usize,
+    completion_tokens: usize,
+    #[serde(default)]
+    completion_tokens_details: Option<OpenAICompletionDetails>,
+}
 
-        let response = self.client
-            .post(&self.endpoint)
-            .header("Content-Type", "application/json")
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .json(&request_body)
-            .send()
-            .context("Failed to call Claude API")?;
-
-        if !response.status().is_success() {
-            let error_text = response.text().unwrap_or_else(|_| "Could not read error response".to_string());
-            return Err(ApiError::RequestFailed(format!("Claude API error: {}", error_text)).into());
+#[derive(Deserialize, Debug)]
+struct OpenAICompletionDetails {
+    #[serde(default)]
+    reasoning_tokens: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAIChoice {
+    message: OpenAIMessage,
+    // "length" when the reply hit the output limit
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAIMessage {
+    // Null when a reasoning model spends its whole output budget on reasoning
+    content: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ClaudeResponse {
+    content: Vec<ClaudeContent>,
+    // "max_tokens" when the reply hit the output limit
+    #[serde(default)]
+    stop_reason: Option<String>,
+    #[serde(default)]
+    usage: Option<ClaudeUsage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ClaudeUsage {
+    input_tokens: usize,
+    output_tokens: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct ClaudeContent {
+    #[serde(default)]
+    text: String,
+    // Reasoning of a thinking block (redacted_thinking blocks have none)
+    #[serde(default)]
+    thinking: String,
+    // Arguments of a tool_use block, used for structured output
+    #[serde(default)]
+    input: Option<Value>,
+    #[serde(rename = "type")]
+    content_type: String,
+}
+
+fn parse_reply(settings: &ModelSettings, response: Response, structured: bool) -> Result<ChatResponse> {
+    match settings.provider {
+        ApiProvider::OpenAi => {
+            let body: OpenAIResponse =
+                response.json().context("Failed to parse OpenAI API response").context(Failure::Parse)?;
+            let usage = body.usage.map_or_else(Usage::default, |usage| Usage {
+                input: usage.prompt_tokens,
+                output: usage.completion_tokens,
+                reasoning: usage.completion_tokens_details.map_or(0, |details| details.reasoning_tokens),
+            });
+
+            let Some(choice) = body.choices.into_iter().next() else {
+                return Err(unanswered(&usage, "OpenAI API response contained no choices"));
+            };
+            let Some(text) = choice.message.content.filter(|content| !content.is_empty()) else {
+                return Err(unanswered(
+                    &usage,
+                    "OpenAI API response contained no content (a reasoning model may have used up its output tokens)",
+                ));
+            };
+            Ok(ChatResponse {
+                text,
+                truncated: choice.finish_reason.as_deref() == Some("length"),
+                usage,
+            })
+        }
+        ApiProvider::Claude => {
+            let body: ClaudeResponse =
+                response.json().context("Failed to parse Claude API response").context(Failure::Parse)?;
+            let usage = body.usage.as_ref().map_or_else(Usage::default, |usage| {
+                let thinking: String = body.content.iter().map(|content| content.thinking.as_str()).collect();
+                Usage {
+                    input: usage.input_tokens,
+                    output: usage.output_tokens,
+                    reasoning: estimate_tokens(&thinking),
+                }
+            });
+
+            if body.content.is_empty() {
+                return Err(unanswered(&usage, "Claude API response contained no content"));
+            }
+            let truncated = body.stop_reason.as_deref() == Some("max_tokens");
+
+            // Structured output arrives as the tool call's input
+            let text = if structured {
+                body.content
+                    .into_iter()
+                    .find(|content| content.content_type == "tool_use")
+                    .and_then(|content| content.input)
+                    .map(|input| input.to_string())
+                    .ok_or_else(|| unanswered(&usage, "Claude API response contained no tool use"))?
+            } else {
+                body.content
+                    .into_iter()
+                    .find(|content| content.content_type == "text")
+                    .map(|content| content.text)
+                    .ok_or_else(|| unanswered(&usage, "Claude API response contained no text content"))?
+            };
+            Ok(ChatResponse { text, truncated, usage })
+        }
+    }
+}
+
+// Server-sent events: one JSON object per "data:" line
+fn parse_stream(
+    settings: &ModelSettings,
+    response: Response,
+    on_text: &mut impl FnMut(&str) -> Result<()>,
+) -> Result<ChatResponse> {
+    let provider = provider_name(settings);
+    let mut reply = String::new();
+    let mut truncated = false;
+    let mut usage = Usage::default();
+    let mut thinking = String::new();
+    for line in BufReader::new(response).lines() {
+        let line = line.with_context(|| format!("Failed to read {} API stream", provider))?;
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            continue;
+        };
+        if data == "[DONE]" {
+            break;
         }
+        let event: Value = serde_json::from_str(data)
+            .with_context(|| format!("Failed to parse {} API stream event", provider))
+            .context(Failure::Parse)?;
+
+        let text = match settings.provider {
+            ApiProvider::OpenAi => {
+                if let Some(stats) = event.get("usage").filter(|stats| !stats.is_null()) {
+                    usage.input = stats["prompt_tokens"].as_u64().unwrap_or(0) as usize;
+                    usage.output = stats["completion_tokens"].as_u64().unwrap_or(0) as usize;
+                    usage.reasoning =
+                        stats["completion_tokens_details"]["reasoning_tokens"].as_u64().unwrap_or(0) as usize;
+                }
+                if event["choices"][0]["finish_reason"] == "length" {
+                    truncated = true;
+                }
+                event["choices"][0]["delta"]["content"].as_str()
+            }
+            ApiProvider::Claude => match event["type"].as_str() {
+                Some("message_start") => {
+                    usage.input = event["message"]["usage"]["input_tokens"].as_u64().unwrap_or(0) as usize;
+                    None
+                }
+                Some("message_delta") => {
+                    usage.output = event["usage"]["output_tokens"].as_u64().unwrap_or(0) as usize;
+                    truncated = event["delta"]["stop_reason"] == "max_tokens";
+                    None
+                }
+                Some("error") => anyhow::bail!("Claude API stream failed: {}", event["error"]),
+                // Thinking arrives as thinking_delta events before the text and isn't part of the reply
+                Some("content_block_delta") if event["delta"]["type"] == "thinking_delta" => {
+                    thinking += event["delta"]["thinking"].as_str().unwrap_or_default();
+                    None
+                }
+                _ => event["delta"]["text"].as_str(),
+            },
+        };
 
-        let response_body: Value = response.json()
-            .context("Failed to parse Claude API response")?;
+        if let Some(text) = text.filter(|text| !text.is_empty()) {
+            reply += text;
+            on_text(text)?;
+        }
+    }
+    if !thinking.is_empty() {
+        usage.reasoning = estimate_tokens(&thinking);
+    }
 
-        response_body["content"][0]["text"]
-            .as_str()
-            .map(|s| s.to_string())
-            .ok_or(ApiError::EmptyResponse.into())
+    if reply.is_empty() {
+        return Err(unanswered(&usage, &format!("{} API stream contained no text", provider)));
     }
+    Ok(ChatResponse {
+        text: reply,
+        truncated,
+        usage,
+    })
+}
+
+// Error for a reply without usable text; the tokens it used are still counted
+fn unanswered(usage: &Usage, message: &str) -> anyhow::Error {
+    record_usage(usage.input, usage.output, usage.reasoning);
+    anyhow::anyhow!(message.to_string())
 }
@@ -0,0 +1,216 @@
+use anyhow::{Context, Result};
+use clap::{Args, Subcommand};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::history::utc_timestamp;
+use crate::{warn, Config, ModelSettings};
+
+// Opt-in record of every prompt and reply sent to a provider, for compliance review
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AuditConfig {
+    // Directory of the audit files (default: "audit" in the mr-comment data directory)
+    dir: Option<String>,
+    // Audit files older than this many days are deleted (default: 90)
+    retention_days: Option<u64>,
+    // Regexes whose matches are redacted, on top of the built-in secret patterns
+    redact: Option<Vec<String>>,
+}
+
+#[derive(Args, Clone)]
+pub struct AuditArgs {
+    #[command(subcommand)]
+    command: AuditCommand,
+}
+
+#[derive(Subcommand, Clone)]
+enum AuditCommand {
+    /// Delete audit files older than the retention period
+    Purge {
+        /// Delete every audit file, whatever its age
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+const DEFAULT_RETENTION_DAYS: u64 = 90;
+
+const REDACTED: &str = "[REDACTED]";
+
+// Shorter API keys (placeholders in tests and local gateways) would redact ordinary words
+const MIN_KEY_LENGTH: usize = 8;
+
+// Secrets that can turn up in a diff, with what replaces them; assignments keep their name
+const SECRET_PATTERNS: &[(&str, &str)] = &[
+    (r"-----BEGIN [A-Z ]*PRIVATE KEY-----[\s\S]*?(?:-----END [A-Z ]*PRIVATE KEY-----|$)", REDACTED),
+    (r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b", REDACTED),
+    (r"\bgh[pousr]_[A-Za-z0-9]{30,}", REDACTED),
+    (r"\bgithub_pat_[A-Za-z0-9_]{20,}", REDACTED),
+    (r"\bglpat-[A-Za-z0-9_-]{20,}", REDACTED),
+    (r"\bxox[abposr]-[A-Za-z0-9-]{10,}", REDACTED),
+    (r"\bsk-(?:ant-|proj-)?[A-Za-z0-9_-]{20,}", REDACTED),
+    (r"(?i)\b(bearer\s+)[A-Za-z0-9._~+/-]{16,}=*", "${1}[REDACTED]"),
+    (
+        r#"(?i)\b((?:password|passwd|secret|token|api[_-]?key|access[_-]?key|client[_-]?secret)\w*["']?\s*[:=]\s*["']?)[^\s"',;]{6,}"#,
+        "${1}[REDACTED]",
+    ),
+];
+
+// Files written by this process, to keep their names unique
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+// Retention is applied once per run, before the first file is written
+static PURGED: AtomicBool = AtomicBool::new(false);
+
+// An audit file written before its request is sent, completed with the reply
+pub struct Record {
+    path: PathBuf,
+    entry: Value,
+    redactions: Vec<(Regex, String)>,
+}
+
+// Write the redacted request body before anything is sent; an error means the request must not be sent,
+// so nothing leaves the machine unaudited. None when auditing is off.
+pub fn start(settings: &ModelSettings, url: &str, body: &Value) -> Result<Option<Record>> {
+    let Some(config) = &settings.audit else {
+        return Ok(None);
+    };
+    let dir = directory(config)?;
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create audit directory: {}", dir.display()))?;
+    if !PURGED.swap(true, Ordering::Relaxed) {
+        if let Err(err) = purge(&dir, Some(retention(config))) {
+            warn(&format!("could not delete expired audit files: {:#}", err));
+        }
+    }
+
+    let mut redactions = redactions(config)?;
+    // The key itself is only sent as a header, but a diff may contain it too
+    if settings.api_key.len() >= MIN_KEY_LENGTH {
+        redactions.push((Regex::new(&regex::escape(&settings.api_key))?, REDACTED.to_string()));
+    }
+
+    let timestamp = utc_timestamp(SystemTime::now());
+    let name = format!(
+        "{}-{}-{}.json",
+        timestamp.replace(':', "-"),
+        std::process::id(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    );
+    let record = Record {
+        path: dir.join(name),
+        entry: json!({
+            "timestamp": timestamp,
+            "url": url,
+            "model": settings.model,
+            "request": redact_value(body, &redactions),
+            "reply": null
+        }),
+        redactions,
+    };
+    record.write()?;
+    Ok(Some(record))
+}
+
+impl Record {
+    // Add the reply text, or the error in its place
+    pub fn finish(mut self, reply: Result<&str, String>) {
+        match reply {
+            Ok(text) => self.entry["reply"] = json!(redact(text, &self.redactions)),
+            Err(err) => self.entry["error"] = json!(redact(&err, &self.redactions)),
+        }
+        // The request is already on record, so a failure here doesn't stop the run
+        if let Err(err) = self.write() {
+            warn(&format!("could not write the reply to the audit file: {:#}", err));
+        }
+    }
+
+    fn write(&self) -> Result<()> {
+        let text = serde_json::to_string_pretty(&self.entry)?;
+        fs::write(&self.path, text).with_context(|| format!("Failed to write audit file: {}", self.path.display()))
+    }
+}
+
+pub fn run(config: &Config, args: &AuditArgs) -> Result<()> {
+    let AuditCommand::Purge { all } = &args.command;
+    let audit = config.audit.clone().unwrap_or_default();
+    let dir = directory(&audit)?;
+    if !dir.exists() {
+        println!("No audit files in {}", dir.display());
+        return Ok(());
+    }
+
+    let deleted = purge(&dir, (!all).then(|| retention(&audit)))?;
+    println!("Deleted {} audit files from {}", deleted, dir.display());
+    Ok(())
+}
+
+fn directory(config: &AuditConfig) -> Result<PathBuf> {
+    match &config.dir {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => Ok(dirs::data_dir().context("Could not find data directory")?.join("mr-comment").join("audit")),
+    }
+}
+
+fn retention(config: &AuditConfig) -> Duration {
+    Duration::from_secs(config.retention_days.unwrap_or(DEFAULT_RETENTION_DAYS) * 86_400)
+}
+
+// Delete the audit files (and only those) last written longer ago than max_age, or all of them
+fn purge(dir: &Path, max_age: Option<Duration>) -> Result<usize> {
+    let name = Regex::new(r"^\d{4}-\d{2}-\d{2}T\d{2}-\d{2}-\d{2}Z-\d+-\d+\.json$").unwrap();
+    let mut deleted = 0;
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read audit directory: {}", dir.display()))? {
+        let entry = entry?;
+        if !name.is_match(&entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        if let Some(max_age) = max_age {
+            let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+            if age < max_age {
+                continue;
+            }
+        }
+        fs::remove_file(entry.path())
+            .with_context(|| format!("Failed to delete audit file: {}", entry.path().display()))?;
+        deleted += 1;
+    }
+    Ok(deleted)
+}
+
+fn redactions(config: &AuditConfig) -> Result<Vec<(Regex, String)>> {
+    let mut redactions: Vec<(Regex, String)> = SECRET_PATTERNS
+        .iter()
+        .map(|(pattern, replacement)| (Regex::new(pattern).unwrap(), replacement.to_string()))
+        .collect();
+    for pattern in config.redact.iter().flatten() {
+        let regex = Regex::new(pattern).with_context(|| format!("Invalid audit redact pattern: {}", pattern))?;
+        redactions.push((regex, REDACTED.to_string()));
+    }
+    Ok(redactions)
+}
+
+fn redact(text: &str, redactions: &[(Regex, String)]) -> String {
+    redactions
+        .iter()
+        .fold(text.to_string(), |text, (regex, replacement)| regex.replace_all(&text, replacement.as_str()).into_owned())
+}
+
+// Every string in the request body, so prompts, diffs and continuations are covered alike
+fn redact_value(value: &Value, redactions: &[(Regex, String)]) -> Value {
+    match value {
+        Value::String(text) => Value::String(redact(text, redactions)),
+        Value::Array(items) => Value::Array(items.iter().map(|item| redact_value(item, redactions)).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), redact_value(value, redactions)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::Result;
+use clap::{Args, Subcommand, ValueEnum};
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use std::time::Duration;
+
+use crate::{models, resolve_settings, ApiProvider, Cli, Config, Failure, ModelSettings};
+
+// Long enough for a slow gateway, short enough not to hang a CI job on an unreachable endpoint
+const CHECK_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Args, Clone)]
+pub struct AuthArgs {
+    #[command(subcommand)]
+    command: AuthCommand,
+}
+
+#[derive(Subcommand, Clone)]
+enum AuthCommand {
+    /// Check the API key and endpoint of each configured provider by listing its models (nothing is generated)
+    Check {
+        /// Only check this provider, even if it has no key configured
+        #[arg(long, value_enum)]
+        provider: Option<ApiProvider>,
+    },
+}
+
+pub fn run(cli: &Cli, config: &Config, args: &AuthArgs) -> Result<()> {
+    let AuthCommand::Check { provider } = &args.command;
+
+    let providers = provider_settings(cli, config, provider.clone());
+    if providers.is_empty() {
+        anyhow::bail!(
+            "No provider has an API key; set ANTHROPIC_API_KEY or OPENAI_API_KEY, add one to the config or run `mr-comment init`"
+        );
+    }
+
+    let mut failed = 0;
+    for (name, settings) in providers {
+        let result = settings.map_err(|err| format!("{:#}", err)).and_then(|settings| check(&settings));
+        match result {
+            Ok(message) => println!("{}: ok - {}", name, message),
+            Err(message) => {
+                println!("{}: failed - {}", name, message);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of the providers failed the check", failed).context(Failure::Auth));
+    }
+    Ok(())
+}
+
+// Name and settings of each provider to check: the one named by --provider, or every provider with a key
+// configured. --api-key, --endpoint and --model are for one provider, so they only apply when it is named.
+pub fn provider_settings(cli: &Cli, config: &Config, provider: Option<ApiProvider>) -> Vec<(String, Result<ModelSettings>)> {
+    let providers = match provider.or(cli.provider.clone()) {
+        Some(provider) => vec![(provider, true)],
+        None => ApiProvider::value_variants().iter().map(|provider| (provider.clone(), false)).collect(),
+    };
+
+    let mut settings = Vec::new();
+    for (provider, named) in providers {
+        let name = provider.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default();
+        // A key in the config that doesn't decrypt is reported rather than taken for a missing one
+        let in_config = match provider {
+            ApiProvider::OpenAi => config.openai_api_key.is_some(),
+            ApiProvider::Claude => config.claude_api_key.is_some(),
+        };
+        let provider_cli = if named {
+            Cli {
+                provider: Some(provider),
+                ..cli.clone()
+            }
+        } else {
+            Cli {
+                provider: Some(provider),
+                api_key: None,
+                endpoint: None,
+                model: None,
+                ..cli.clone()
+            }
+        };
+
+        match resolve_settings(&provider_cli, config) {
+            // Providers without a key aren't configured, unless asked for by name
+            Err(err) if err.downcast_ref::<Failure>().is_some() && !named && !in_config => {}
+            result => settings.push((name, result)),
+        }
+    }
+    settings
+}
+
+// The model list needs the same key and headers as a completion but generates nothing
+pub fn check(settings: &ModelSettings) -> Result<String, String> {
+    let url = models::list_url(settings);
+    let client = Client::builder()
+        .timeout(CHECK_TIMEOUT)
+        .build()
+        .map_err(|err| format!("could not create HTTP client: {}", err))?;
+    let response = models::list_request(&client, settings)
+        .send()
+        .map_err(|err| format!("{} is unreachable: {}", url, err))?;
+
+    match response.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            return Err(format!("the API key was rejected by {} ({})", url, response.status()))
+        }
+        // Some OpenAI-compatible gateways only serve completions
+        StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED => {
+            return Ok(format!("{} has no models endpoint, so the key could not be checked", url))
+        }
+        status if !status.is_success() => return Err(format!("{} answered {}", url, status)),
+        _ => {}
+    }
+
+    let models = response.json::<models::ModelList>().map(|list| list.data).unwrap_or_default();
+    let model = if models.is_empty() {
+        String::new()
+    } else if models.iter().any(|model| model.id == settings.model) {
+        format!("; model {} is available", settings.model)
+    } else {
+        format!("; model {} is not in the list of {} models", settings.model, models.len())
+    };
+    Ok(format!("key accepted by {}{}", url, model))
+}
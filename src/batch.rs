@@ -0,0 +1,195 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::{
+    build_prompt, comment_for, finish_comment, generate_text, git, prepare_diff, read_diff, warn, Cli, Config,
+    Generated, ModelSettings, PromptTemplate,
+};
+
+#[derive(Args, Clone)]
+pub struct BatchArgs {
+    /// Branch names or patterns (e.g. "feature/*") to describe against --base
+    #[arg(long, num_args = 1.., value_name = "PATTERN")]
+    branches: Vec<String>,
+
+    /// File with one branch or commit range per line
+    #[arg(long, value_name = "FILE")]
+    list: Option<PathBuf>,
+
+    /// Branch the listed branches are compared against
+    #[arg(long, default_value = "main")]
+    base: String,
+
+    /// Directory the per-branch comments are written to
+    #[arg(long, value_name = "DIR", default_value = "mr-comments")]
+    pub output_dir: PathBuf,
+
+    /// Number of comments generated at the same time
+    #[arg(short, long, default_value_t = 4)]
+    jobs: usize,
+}
+
+// One branch or range to describe
+struct Item {
+    name: String,
+    range: String,
+}
+
+pub fn run(cli: &Cli, config: &Config, settings: &ModelSettings, args: &BatchArgs) -> Result<()> {
+    // The branches have a merge request each, and there is no telling which from here
+    if cli.post {
+        anyhow::bail!(
+            "batch writes the comments to --output-dir and can't --post them; post one with \
+             mr-comment --commit <RANGE> --post --mr <IID>"
+        );
+    }
+    let items = collect_items(args)?;
+    if items.is_empty() {
+        anyhow::bail!("No branches or ranges matched");
+    }
+
+    fs::create_dir_all(&args.output_dir).with_context(|| {
+        format!("Failed to create output directory: {}", args.output_dir.display())
+    })?;
+
+    // The prompt only depends on the repository, so it is built (and any style guide distilled) once
+    let prompt = build_prompt(cli, config, settings)?;
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..args.jobs.clamp(1, items.len()) {
+            scope.spawn(|| {
+                while let Some(item) = items.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    match generate_item(cli, config, settings, &prompt, item, &args.output_dir) {
+                        Ok(path) if !cli.quiet => {
+                            println!("{}: written to {}", item.name, path.display())
+                        }
+                        Ok(_) => {}
+                        Err(err) => {
+                            eprintln!("{}: {:#}", item.name, err);
+                            failed.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let failed = failed.into_inner();
+    if failed > 0 {
+        anyhow::bail!("{} of {} batch items failed", failed, items.len());
+    }
+
+    Ok(())
+}
+
+fn generate_item(
+    cli: &Cli,
+    config: &Config,
+    settings: &ModelSettings,
+    prompt: &PromptTemplate,
+    item: &Item,
+    output_dir: &Path,
+) -> Result<PathBuf> {
+    let item_cli = Cli {
+        commit: Some(item.range.clone()),
+        file: None,
+        stash: None,
+        ..cli.clone()
+    };
+
+    let raw_diff = read_diff(&item_cli, config)?;
+    let prepared = prepare_diff(&item_cli, config, raw_diff)?;
+    let prompt = prompt.for_diff(&item_cli, &prepared.diff, &prepared.changed_files)?;
+    let comment = comment_for(&item_cli, config, settings, &prompt, &prepared, false, |prompt, budget| {
+        generate_text(&item_cli, &prepared.diff, settings, prompt, budget).map(|reply| Generated::Text(reply.text))
+    })?
+    .into_markdown();
+    let comment = finish_comment(cli, config, comment, &prepared)?;
+
+    let path = output_dir.join(format!("{}.md", file_name(&item.name)));
+    fs::write(&path, comment)
+        .with_context(|| format!("Failed to write to file: {}", path.display()))?;
+
+    Ok(path)
+}
+
+// The name with characters other than letters, digits and "._-" replaced. A name that changed gets a
+// hash of the original, so feature/a-b and feature-a-b don't overwrite each other.
+fn file_name(name: &str) -> String {
+    let file_name: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || "._-".contains(c) { c } else { '-' })
+        .collect();
+    if file_name == name {
+        return file_name;
+    }
+    let hash = format!("{:x}", Sha256::digest(name.as_bytes()));
+    format!("{}-{}", file_name, &hash[..8])
+}
+
+// Branches from the patterns and entries from the list file, in order and without duplicates
+fn collect_items(args: &BatchArgs) -> Result<Vec<Item>> {
+    let mut names = Vec::new();
+
+    for pattern in &args.branches {
+        let matches = matching_branches(pattern)?;
+        if matches.is_empty() {
+            warn(&format!("no branches match {}", pattern));
+        }
+        names.extend(matches);
+    }
+
+    if let Some(list) = &args.list {
+        let content = fs::read_to_string(list)
+            .with_context(|| format!("Failed to read file: {}", list.display()))?;
+        names.extend(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string),
+        );
+    }
+
+    let mut items: Vec<Item> = Vec::new();
+    for name in names {
+        if items.iter().any(|item| item.name == name) {
+            continue;
+        }
+        // Branches are compared from their merge base with the base branch
+        let range = if name.contains("..") {
+            name.clone()
+        } else {
+            format!("{}...{}", args.base, name)
+        };
+        items.push(Item { name, range });
+    }
+
+    Ok(items)
+}
+
+fn matching_branches(pattern: &str) -> Result<Vec<String>> {
+    let output = git::command()
+        .args(["for-each-ref", "--format=%(refname:short)"])
+        .arg(format!("refs/heads/{}", pattern))
+        .arg(format!("refs/remotes/{}", pattern))
+        .output()
+        .context("Failed to execute git for-each-ref command")?;
+
+    if !output.status.success() {
+        return Err(git::error("for-each-ref", &output.stderr));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.to_string())
+        .collect())
+}
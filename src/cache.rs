@@ -0,0 +1,76 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::api_client;
+use crate::provenance::{self, Provenance};
+use crate::{get_cache_dir, schema, Generated, ModelSettings};
+
+// A cached reply with what wrote it, under cache/responses/<key>.json
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    provenance: Provenance,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    structured: Option<schema::MrComment>,
+}
+
+// Everything that shapes the reply goes into the key: the tool version, provider, model and reasoning
+// effort, the effective prompt template, the shape of the output and the diff as sent. A template
+// upgrade or a model switch therefore misses the cache instead of serving the old comment.
+pub fn key(settings: &ModelSettings, system_message: &str, shape: &str, diff_message: &str) -> String {
+    let reasoning = format!("{:?}", settings.reasoning);
+    let template = provenance::template_hash(system_message);
+    let parts = [
+        env!("CARGO_PKG_VERSION"),
+        api_client::provider_name(settings),
+        &settings.model,
+        &reasoning,
+        &template,
+        shape,
+        diff_message,
+    ];
+
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        // Separator, so moving text from one part to the next changes the key
+        hasher.update([0]);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+// The cached reply and its provenance; None when there is none or it can't be read
+pub fn load(key: &str) -> Option<(Generated, Provenance)> {
+    let entry: Entry = serde_json::from_str(&fs::read_to_string(path(key).ok()?).ok()?).ok()?;
+    match (entry.text, entry.structured) {
+        (Some(text), None) => Some((Generated::Text(text), entry.provenance)),
+        (None, Some(structured)) => Some((Generated::Structured(Box::new(structured)), entry.provenance)),
+        _ => None,
+    }
+}
+
+pub fn store(key: &str, generated: &Generated, provenance: &Provenance) -> Result<()> {
+    let (text, structured) = match generated {
+        Generated::Text(text) => (Some(text.clone()), None),
+        Generated::Structured(comment) => (None, Some((**comment).clone())),
+    };
+    let entry = Entry {
+        provenance: provenance.clone(),
+        text,
+        structured,
+    };
+    let path = path(key)?;
+    let json = serde_json::to_string(&entry).context("Failed to serialize cached comment")?;
+    fs::write(&path, json).with_context(|| format!("Failed to write to file: {}", path.display()))
+}
+
+fn path(key: &str) -> Result<PathBuf> {
+    let dir = get_cache_dir()?.join("responses");
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create cache directory: {}", dir.display()))?;
+    Ok(dir.join(format!("{}.json", key)))
+}
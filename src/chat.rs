@@ -0,0 +1,128 @@
+use regex::Regex;
+use serde_json::{json, Value};
+
+use crate::comment;
+
+// Slack mrkdwn: bold headings, bullet characters and <url|text> links
+pub fn slack_message(markdown: &str) -> String {
+    let parsed = comment::parse(markdown);
+    let bold = Regex::new(r"\*\*(.+?)\*\*|__(.+?)__").unwrap();
+    let link = Regex::new(r"\[([^\]]+)\]\(([^)\s]+)\)").unwrap();
+
+    // The title is bold as a whole, so emphasis inside it is dropped
+    let title = parsed.title.replace("**", "").replace("__", "");
+    let mut lines = vec![format!("*{}*", escape(&title)), String::new()];
+    let mut in_fence = false;
+    for line in parsed.description.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+            lines.push(line.to_string());
+            continue;
+        }
+        if in_fence {
+            lines.push(escape(line));
+            continue;
+        }
+
+        if let Some(heading) = heading_text(line) {
+            lines.push(format!("*{}*", escape(heading)));
+            continue;
+        }
+
+        let (indent, rest) = line.split_at(line.len() - line.trim_start().len());
+        let rest = if let Some(item) = rest.strip_prefix("- [ ] ") {
+            format!("☐ {}", item)
+        } else if let Some(item) = rest.strip_prefix("- [x] ").or_else(|| rest.strip_prefix("- [X] ")) {
+            format!("☑ {}", item)
+        } else if let Some(item) = rest.strip_prefix("- ").or_else(|| rest.strip_prefix("* ")) {
+            format!("• {}", item)
+        } else {
+            rest.to_string()
+        };
+
+        // Odd segments between backticks are inline code and keep their markup
+        let rest = rest
+            .split('`')
+            .enumerate()
+            .map(|(i, segment)| {
+                let segment = escape(segment);
+                if i % 2 == 1 {
+                    return segment;
+                }
+                let segment = bold.replace_all(&segment, |caps: &regex::Captures| {
+                    format!("*{}*", caps.get(1).or(caps.get(2)).map_or("", |m| m.as_str()))
+                });
+                link.replace_all(&segment, "<$2|$1>").into_owned()
+            })
+            .collect::<Vec<_>>()
+            .join("`");
+        lines.push(format!("{}{}", indent, rest));
+    }
+
+    lines.join("\n").trim().to_string()
+}
+
+// Adaptive Card with the title and one heading/text block pair per section
+pub fn teams_card(markdown: &str) -> Value {
+    let parsed = comment::parse(markdown);
+
+    let mut body = vec![json!({
+        "type": "TextBlock",
+        "text": parsed.title,
+        "size": "Large",
+        "weight": "Bolder",
+        "wrap": true
+    })];
+
+    let mut text: Vec<String> = Vec::new();
+    let flush = |text: &mut Vec<String>, body: &mut Vec<Value>| {
+        let block = text.join("\n").trim().to_string();
+        if !block.is_empty() {
+            body.push(json!({ "type": "TextBlock", "text": block, "wrap": true }));
+        }
+        text.clear();
+    };
+
+    for line in parsed.description.lines() {
+        if let Some(heading) = heading_text(line) {
+            flush(&mut text, &mut body);
+            body.push(json!({
+                "type": "TextBlock",
+                "text": heading,
+                "size": "Medium",
+                "weight": "Bolder",
+                "spacing": "Medium",
+                "wrap": true
+            }));
+            continue;
+        }
+        // Adaptive Card markdown has no task lists
+        let line = line
+            .replacen("- [ ] ", "- ☐ ", 1)
+            .replacen("- [x] ", "- ☑ ", 1)
+            .replacen("- [X] ", "- ☑ ", 1);
+        text.push(line);
+    }
+    flush(&mut text, &mut body);
+
+    json!({
+        "type": "AdaptiveCard",
+        "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+        "version": "1.4",
+        "body": body
+    })
+}
+
+// Text of a markdown heading line, without the trailing colon the prompt format uses
+fn heading_text(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    if !trimmed.starts_with('#') {
+        return None;
+    }
+    Some(trimmed.trim_start_matches('#').trim().trim_end_matches(':').trim())
+}
+
+// Slack requires &, < and > to be escaped in message text
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
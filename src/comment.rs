@@ -0,0 +1,372 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+
+use crate::flavor;
+
+// Generated comment split into the parts CI templating consumes separately
+pub struct ParsedComment {
+    pub title: String,
+    pub description: String,
+    pub checklist: String,
+}
+
+// Normalize a markdown heading ("## Key Changes:") to its name ("key changes")
+fn heading_name(line: &str) -> Option<String> {
+    let trimmed = line.trim_start();
+    if !trimmed.starts_with('#') {
+        return None;
+    }
+    let name = trimmed
+        .trim_start_matches('#')
+        .trim()
+        .trim_matches('*')
+        .trim_end_matches(':')
+        .trim();
+    Some(name.to_lowercase())
+}
+
+// Body of the section under the given heading, up to the next heading
+pub fn find_section(comment: &str, name: &str) -> Option<String> {
+    let name = name.to_lowercase();
+    let mut lines = comment.lines();
+    lines.find(|line| heading_name(line).is_some_and(|heading| heading == name))?;
+
+    let body: Vec<&str> = lines.take_while(|line| heading_name(line).is_none()).collect();
+    Some(body.join("\n").trim().to_string())
+}
+
+fn title_label() -> Regex {
+    Regex::new(r"^(?:#+\s*)?(?:\*\*)?(?:MR/PR|MR|PR) Title:?(?:\*\*)?:?\s*").unwrap()
+}
+
+fn summary_label() -> Regex {
+    Regex::new(r"^(?:\*\*)?(?:MR/PR|MR|PR) Summary:?(?:\*\*)?:?\s*").unwrap()
+}
+
+// Prefer the labelled title line, fall back to the first non-empty line
+fn title_index(lines: &[&str], title_label: &Regex) -> Option<usize> {
+    lines
+        .iter()
+        .position(|line| title_label.is_match(line))
+        .or_else(|| lines.iter().position(|line| !line.trim().is_empty()))
+}
+
+pub fn parse(comment: &str) -> ParsedComment {
+    let title_label = title_label();
+    let summary_label = summary_label();
+
+    let lines: Vec<&str> = comment.lines().collect();
+    let title_index = title_index(&lines, &title_label);
+
+    let title = title_index
+        .map(|i| {
+            let line = title_label.replace(lines[i], "");
+            line.trim_start_matches('#').trim().trim_matches('*').trim().to_string()
+        })
+        .unwrap_or_default();
+
+    let description = lines
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| Some(*i) != title_index)
+        .map(|(_, line)| summary_label.replace(line, "").into_owned())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string();
+
+    let checklist = find_section(comment, "Review Checklist").unwrap_or_else(|| {
+        lines
+            .iter()
+            .filter(|line| {
+                let line = line.trim_start();
+                line.starts_with("- [ ]") || line.starts_with("- [x]")
+            })
+            .copied()
+            .collect::<Vec<_>>()
+            .join("\n")
+    });
+
+    ParsedComment {
+        title,
+        description,
+        checklist,
+    }
+}
+
+// Headings of the template that the comment doesn't contain, as written in the template
+pub fn missing_sections(comment: &str, template: &str) -> Vec<String> {
+    let present: Vec<String> = comment.lines().filter_map(heading_name).collect();
+    template
+        .lines()
+        .filter(|line| heading_name(line).is_some_and(|name| !name.is_empty() && !present.contains(&name)))
+        .map(|line| line.trim().trim_start_matches('#').trim().to_string())
+        .collect()
+}
+
+// Prefix the title with an area name ("payments: Add refunds"), keeping any title label
+pub fn prefix_title(comment: &str, prefix: &str) -> String {
+    let title_label = title_label();
+    let lines: Vec<&str> = comment.lines().collect();
+    let Some(index) = title_index(&lines, &title_label) else {
+        return comment.to_string();
+    };
+
+    let line = lines[index];
+    let label_end = title_label
+        .find(line)
+        .map(|label| label.end())
+        .unwrap_or_else(|| line.len() - line.trim_start_matches(['#', ' ', '*']).len());
+    let (label, title) = line.split_at(label_end);
+
+    let marker = format!("{}:", prefix);
+    if title.to_lowercase().starts_with(&marker.to_lowercase()) {
+        return comment.to_string();
+    }
+
+    let mut result: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+    result[index] = format!("{}{} {}", label, marker, title);
+    result.join("\n")
+}
+
+// Add lines at the end of a section, or as a new section at the end when the comment has none
+pub fn append_to_section(comment: &str, name: &str, heading: &str, items: &[String]) -> String {
+    let name = name.to_lowercase();
+    let mut lines: Vec<String> = comment.lines().map(str::to_string).collect();
+    let Some(start) = lines
+        .iter()
+        .position(|line| heading_name(line).is_some_and(|heading| heading == name))
+    else {
+        return format!("{}\n\n{}\n\n{}", comment.trim_end(), heading, items.join("\n"));
+    };
+
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| heading_name(line).is_some())
+        .map_or(lines.len(), |offset| start + 1 + offset);
+    // After the last non-empty line of the section, or below the heading if it's empty
+    let insert_at = (start + 1..end)
+        .rev()
+        .find(|&index| !lines[index].trim().is_empty())
+        .map_or(start + 1, |index| index + 1);
+    if insert_at == start + 1 {
+        lines.insert(insert_at, String::new());
+        lines.splice(insert_at + 1..insert_at + 1, items.iter().cloned());
+    } else {
+        lines.splice(insert_at..insert_at, items.iter().cloned());
+    }
+    lines.join("\n")
+}
+
+// Replace the body of a section, or add it at the end when the comment has none
+pub fn replace_section(comment: &str, name: &str, heading: &str, body: &str) -> String {
+    let name = name.to_lowercase();
+    let mut lines: Vec<String> = comment.lines().map(str::to_string).collect();
+    let Some(start) = lines
+        .iter()
+        .position(|line| heading_name(line).is_some_and(|heading| heading == name))
+    else {
+        return format!("{}\n\n{}\n\n{}", comment.trim_end(), heading, body.trim());
+    };
+
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| heading_name(line).is_some())
+        .map_or(lines.len(), |offset| start + 1 + offset);
+    let mut section = vec![String::new()];
+    section.extend(body.trim().lines().map(str::to_string));
+    if end < lines.len() {
+        section.push(String::new());
+    }
+    lines.splice(start + 1..end, section);
+    lines.join("\n")
+}
+
+// Description with body between the marker's start and end comments, replacing an earlier block or
+// added at the end; the rest is kept as its author wrote it
+pub fn splice_marked(description: &str, body: &str, marker: &str) -> String {
+    let start = format!("<!-- {}:start -->", marker);
+    let end = format!("<!-- {}:end -->", marker);
+    let block = format!("{}\n{}\n{}", start, body.trim(), end);
+    match (description.find(&start), description.find(&end)) {
+        (Some(from), Some(to)) if from < to => {
+            format!("{}{}{}", &description[..from], block, &description[to + end.len()..])
+        }
+        _ if description.trim().is_empty() => block,
+        _ => format!("{}\n\n{}", description.trim_end(), block),
+    }
+}
+
+// Wrap the bodies of the named sections in collapsed <details> blocks below their headings, titled
+// with their number of list items. Bodies already in one, e.g. from an MR template, are left as they
+// are, and the footers after the last section (contributors, quick actions, provenance) stay outside.
+pub fn collapse_sections(comment: &str, names: &[String]) -> String {
+    let names: Vec<String> = names.iter().map(|name| name.trim().trim_end_matches(':').to_lowercase()).collect();
+    let is_footer = |line: &str| {
+        line.trim() == "---" || line.trim_start().starts_with("<!--") || flavor::is_quick_action(line)
+    };
+    let lines: Vec<&str> = comment.lines().collect();
+    let mut result: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        result.push(lines[i].to_string());
+        let collapse = heading_name(lines[i]).is_some_and(|heading| names.contains(&heading));
+        i += 1;
+        if !collapse {
+            continue;
+        }
+
+        let end = lines[i..]
+            .iter()
+            .position(|line| heading_name(line).is_some() || is_footer(line))
+            .map_or(lines.len(), |offset| i + offset);
+        let body: Vec<&str> = lines[i..end]
+            .iter()
+            .copied()
+            .skip_while(|line| line.trim().is_empty())
+            .collect();
+        let blank_tail = body.iter().rev().take_while(|line| line.trim().is_empty()).count();
+        let body = &body[..body.len() - blank_tail];
+        if body.is_empty() || body[0].trim_start().starts_with("<details") {
+            result.extend(lines[i..end].iter().map(|line| line.to_string()));
+            i = end;
+            continue;
+        }
+
+        let summary = match body.iter().filter(|line| line.starts_with("- ") || line.starts_with("* ")).count() {
+            0 => "Details".to_string(),
+            1 => "1 item".to_string(),
+            items => format!("{} items", items),
+        };
+        result.extend([String::new(), "<details>".to_string(), format!("<summary>{}</summary>", summary)]);
+        result.push(String::new());
+        result.extend(body.iter().map(|line| line.to_string()));
+        result.extend([String::new(), "</details>".to_string()]);
+        if end < lines.len() {
+            result.push(String::new());
+        }
+        i = end;
+    }
+    result.join("\n")
+}
+
+// The markdown without its <details> and <summary> lines, for output that can't collapse them
+pub fn expand_sections(markdown: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    let mut in_fence = false;
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if !in_fence {
+            // A removed tag leaves no blank line twice
+            let double_blank = line.trim().is_empty() && lines.last().is_some_and(|last| last.trim().is_empty());
+            if is_details_tag(line) || double_blank {
+                continue;
+            }
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+// "<details>", "</details>" and "<summary>...</summary>" lines
+pub fn is_details_tag(line: &str) -> bool {
+    let line = line.trim();
+    matches!(line, "<details>" | "<details open>" | "</details>")
+        || line.starts_with("<summary>") && line.ends_with("</summary>")
+}
+
+// Replace the title, keeping its label ("MR Title: ...")
+pub fn replace_title(comment: &str, title: &str) -> String {
+    let title_label = title_label();
+    let lines: Vec<&str> = comment.lines().collect();
+    let Some(index) = title_index(&lines, &title_label) else {
+        return format!("{}\n\n{}", title, comment);
+    };
+
+    let line = lines[index];
+    let label_end = title_label
+        .find(line)
+        .map(|label| label.end())
+        .unwrap_or_else(|| line.len() - line.trim_start_matches(['#', ' ', '*']).len());
+
+    let mut result: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+    result[index] = format!("{}{}", &line[..label_end], title);
+    result.join("\n")
+}
+
+// Replace the summary paragraph after its label, or add it below the title as `line` when missing
+pub fn replace_summary(comment: &str, summary: &str, line: &str) -> String {
+    let summary_label = summary_label();
+    let mut lines: Vec<String> = comment.lines().map(str::to_string).collect();
+
+    let Some(start) = lines.iter().position(|line| summary_label.is_match(line)) else {
+        let refs: Vec<&str> = comment.lines().collect();
+        let at = title_index(&refs, &title_label()).map_or(0, |index| index + 1);
+        lines.splice(at..at, [String::new(), line.to_string()]);
+        return lines.join("\n");
+    };
+
+    // The paragraph ends at a blank line or the next heading
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| line.trim().is_empty() || heading_name(line).is_some())
+        .map_or(lines.len(), |offset| start + 1 + offset);
+    let label = summary_label.find(&lines[start]).map(|label| label.as_str().to_string()).unwrap_or_default();
+    lines.splice(start..end, [format!("{}{}", label, summary.trim())]);
+    lines.join("\n")
+}
+
+// Rewrite configured terms (e.g. "front-end" -> "frontend", "gitlab" -> "GitLab") outside of code
+pub fn apply_terminology(comment: &str, terms: &HashMap<String, String>) -> Result<String> {
+    // Longest terms first so "front end team" wins over "front end"
+    let mut sorted: Vec<(&String, &String)> = terms.iter().collect();
+    sorted.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.0.cmp(b.0)));
+
+    let mut replacements = Vec::new();
+    for (from, to) in sorted {
+        let word_start = from.starts_with(|c: char| c.is_alphanumeric() || c == '_');
+        let word_end = from.ends_with(|c: char| c.is_alphanumeric() || c == '_');
+        let pattern = format!(
+            "(?i){}{}{}",
+            if word_start { r"\b" } else { "" },
+            regex::escape(from),
+            if word_end { r"\b" } else { "" }
+        );
+        let re = Regex::new(&pattern)
+            .with_context(|| format!("Invalid terminology entry: {}", from))?;
+        replacements.push((re, to.as_str()));
+    }
+
+    let mut in_fence = false;
+    let lines: Vec<String> = comment
+        .lines()
+        .map(|line| {
+            if line.trim_start().starts_with("```") {
+                in_fence = !in_fence;
+                return line.to_string();
+            }
+            if in_fence {
+                return line.to_string();
+            }
+
+            // Odd segments between backticks are inline code
+            line.split('`')
+                .enumerate()
+                .map(|(i, segment)| {
+                    if i % 2 == 1 {
+                        return segment.to_string();
+                    }
+                    replacements.iter().fold(segment.to_string(), |text, (re, to)| {
+                        re.replace_all(&text, regex::NoExpand(to)).into_owned()
+                    })
+                })
+                .collect::<Vec<_>>()
+                .join("`")
+        })
+        .collect();
+
+    Ok(lines.join("\n"))
+}
@@ -0,0 +1,184 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::diff::split_files;
+use crate::git;
+
+// Checks the tool can verify itself instead of leaving them to the model
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ComplianceConfig {
+    // Every commit in the range needs a Signed-off-by line (Developer Certificate of Origin)
+    signoff: Option<bool>,
+    // Text new files must contain near the top, e.g. "SPDX-License-Identifier"
+    license_header: Option<String>,
+    // Globs limiting which new files need the header (default: all text files)
+    license_header_paths: Option<Vec<String>>,
+    // License identifier prefixes that must not be added, e.g. ["GPL", "AGPL"]
+    forbidden_licenses: Option<Vec<String>>,
+}
+
+// Result of one check, rendered as a ticked or unticked checklist item
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Check {
+    pub passed: bool,
+    pub text: String,
+}
+
+// Lines from the top of a new file searched for the license header
+const HEADER_LINES: usize = 20;
+
+// Run the configured checks; log_range is the git log arguments of the described commits, None for
+// uncommitted changes
+pub fn run(config: &ComplianceConfig, diff: &str, log_range: Option<&[String]>) -> Result<Vec<Check>> {
+    let mut checks = Vec::new();
+
+    if config.signoff.unwrap_or(false) {
+        if let Some(range) = log_range {
+            checks.push(signoff(range)?);
+        }
+    }
+
+    if let Some(header) = &config.license_header {
+        let patterns = config
+            .license_header_paths
+            .iter()
+            .flatten()
+            .map(|path| {
+                glob::Pattern::new(path).with_context(|| format!("Invalid license header path pattern: {}", path))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(check) = license_headers(diff, header, &patterns) {
+            checks.push(check);
+        }
+    }
+
+    if let Some(forbidden) = config.forbidden_licenses.as_ref().filter(|forbidden| !forbidden.is_empty()) {
+        checks.push(forbidden_licenses(diff, forbidden));
+    }
+
+    Ok(checks)
+}
+
+fn signoff(range: &[String]) -> Result<Check> {
+    let output = git::command()
+        .args(["log", "--format=%h%x00%B%x1e"])
+        .args(range)
+        .output()
+        .context("Failed to execute git log command")?;
+
+    if !output.status.success() {
+        return Err(git::error("log", &output.stderr));
+    }
+
+    let log = String::from_utf8_lossy(&output.stdout);
+    let commits: Vec<(&str, &str)> = log
+        .split('\x1e')
+        .filter_map(|entry| entry.trim_start().split_once('\0'))
+        .collect();
+    let unsigned: Vec<&str> = commits
+        .iter()
+        .filter(|(_, message)| !message.lines().any(|line| line.starts_with("Signed-off-by:")))
+        .map(|(hash, _)| *hash)
+        .collect();
+
+    Ok(if unsigned.is_empty() {
+        Check {
+            passed: true,
+            text: match commits.len() {
+                1 => "The commit is signed off (DCO)".to_string(),
+                count => format!("All {} commits are signed off (DCO)", count),
+            },
+        }
+    } else {
+        Check {
+            passed: false,
+            text: format!("Commits without a Signed-off-by line (DCO): {}", unsigned.join(", ")),
+        }
+    })
+}
+
+// None when the diff adds no files the header applies to
+fn license_headers(diff: &str, header: &str, patterns: &[glob::Pattern]) -> Option<Check> {
+    let (_, files) = split_files(diff);
+    let new_files: Vec<_> = files
+        .iter()
+        .filter(|file| file.is_new && !file.lines.iter().any(|line| line.starts_with("Binary files")))
+        .filter(|file| patterns.is_empty() || patterns.iter().any(|pattern| pattern.matches(&file.path)))
+        .collect();
+
+    if new_files.is_empty() {
+        return None;
+    }
+
+    let missing: Vec<&str> = new_files
+        .iter()
+        .filter(|file| {
+            !file
+                .lines
+                .iter()
+                .skip_while(|line| !line.starts_with("@@"))
+                .filter_map(|line| line.strip_prefix('+'))
+                .take(HEADER_LINES)
+                .any(|line| line.contains(header))
+        })
+        .map(|file| file.path.as_str())
+        .collect();
+
+    Some(if missing.is_empty() {
+        Check {
+            passed: true,
+            text: "New files have the license header".to_string(),
+        }
+    } else {
+        Check {
+            passed: false,
+            text: format!("New files without the license header: {}", missing.join(", ")),
+        }
+    })
+}
+
+// License fields (package.json, package-lock.json, Cargo.toml, ...) and SPDX tags on added lines
+fn forbidden_licenses(diff: &str, forbidden: &[String]) -> Check {
+    let license = Regex::new(r#"(?i)(?:"license"\s*:\s*|^\s*license\s*=\s*)"([^"]+)""#).unwrap();
+    let spdx = Regex::new(r"SPDX-License-Identifier:\s*(.+)").unwrap();
+    let (_, files) = split_files(diff);
+
+    let mut found: Vec<String> = Vec::new();
+    for file in &files {
+        for line in file.lines.iter().skip_while(|line| !line.starts_with("@@")) {
+            let Some(added) = line.strip_prefix('+') else {
+                continue;
+            };
+            let Some(caps) = license.captures(added).or_else(|| spdx.captures(added)) else {
+                continue;
+            };
+            let expression = caps[1].trim();
+            // Identifiers of an SPDX expression such as "(MIT OR GPL-3.0-only)"
+            let is_forbidden = expression
+                .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                .filter(|id| !id.is_empty() && !["OR", "AND", "WITH"].contains(id))
+                .any(|id| {
+                    forbidden
+                        .iter()
+                        .any(|prefix| id.to_lowercase().starts_with(&prefix.to_lowercase()))
+                });
+            let entry = format!("{} ({})", expression, file.path);
+            if is_forbidden && !found.contains(&entry) {
+                found.push(entry);
+            }
+        }
+    }
+
+    if found.is_empty() {
+        Check {
+            passed: true,
+            text: format!("No {} licensed code added", forbidden.join("/")),
+        }
+    } else {
+        Check {
+            passed: false,
+            text: format!("Forbidden licenses added: {}", found.join(", ")),
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::thread;
+
+use crate::api_client::{self, ChatRequest, Reply};
+use crate::{comment, complete, diff_message, note, progress, warn, DiffBudget, ModelSettings, PromptTemplate};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Section {
+    Title,
+    Summary,
+    KeyChanges,
+    Why,
+    Checklist,
+    Notes,
+}
+
+// Sections written from the diff itself, in output order
+const DIFF_SECTIONS: &[Section] = &[Section::KeyChanges, Section::Why, Section::Checklist, Section::Notes];
+
+impl Section {
+    fn instructions(self, artifact: &str) -> String {
+        match self {
+            Section::KeyChanges => "List the major changes in the git diff as a markdown bulleted list, \
+                one change per bullet, most important first. Output only the list."
+                .to_string(),
+            Section::Why => "Explain in one short paragraph the motivation for the changes in the git diff, \
+                as far as the diff shows it. Output only the paragraph."
+                .to_string(),
+            Section::Checklist => "Write a review checklist for the git diff as markdown checkboxes (- [ ] ...), \
+                covering what a reviewer should verify. Output only the checklist."
+                .to_string(),
+            Section::Notes => "Note anything else a reviewer of the git diff should know (migrations, breaking \
+                changes, follow-ups) as short markdown, or reply with \"None.\" if there is nothing. \
+                Output only the notes."
+                .to_string(),
+            Section::Summary => format!(
+                "Write a brief overview (1-3 sentences) of the {} described by these key changes and \
+                 motivation. Output only the overview.",
+                artifact
+            ),
+            Section::Title => format!(
+                "Write a one-sentence {} title for these key changes and motivation, in the imperative \
+                 mood. Output only the title, without quotes, label or trailing period.",
+                artifact
+            ),
+        }
+    }
+
+    // Titles are one line, without the quotes or trailing period models like to add
+    fn tidy(self, text: &str) -> String {
+        if self == Section::Title {
+            let line = text.lines().find(|line| !line.trim().is_empty()).unwrap_or_default();
+            line.trim().trim_matches('"').trim_end_matches('.').to_string()
+        } else {
+            text.trim().to_string()
+        }
+    }
+
+    // Heading name as comment::find_section matches it
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::Title => "title",
+            Section::Summary => "summary",
+            Section::KeyChanges => "key changes",
+            Section::Why => "why these changes",
+            Section::Checklist => "review checklist",
+            Section::Notes => "notes",
+        }
+    }
+
+    // Heading or label in the assembled comment, matching the single-prompt format
+    fn render(self, artifact: &str, text: &str) -> String {
+        match self {
+            Section::Title => format!("{} Title: {}", artifact, text),
+            Section::Summary => format!("{} Summary: {}", artifact, text),
+            Section::KeyChanges => format!("## Key Changes:\n\n{}", text),
+            Section::Why => format!("## Why These Changes:\n\n{}", text),
+            Section::Checklist => format!("## Review Checklist:\n\n{}", text),
+            Section::Notes => format!("## Notes:\n\n{}", text),
+        }
+    }
+}
+
+// Write one section again for the current diff. The title and summary are written from the key
+// changes and motivation of the existing comment, or from freshly generated ones if it has none.
+pub fn regenerate(
+    diff: &str,
+    settings: &ModelSettings,
+    prompt: &PromptTemplate,
+    budget: &DiffBudget,
+    artifact: &str,
+    section: Section,
+    existing: &str,
+) -> Result<String> {
+    let system = prompt.section_message(&section.instructions(artifact));
+    if !matches!(section, Section::Title | Section::Summary) {
+        let text = complete(settings, &system, &diff_message(diff, budget))
+            .with_context(|| format!("Failed to generate the {:?} section", section))?;
+        return Ok(text.trim().to_string());
+    }
+
+    let mut context = Vec::new();
+    for (name, source) in [("Key changes", Section::KeyChanges), ("Motivation", Section::Why)] {
+        let text = match comment::find_section(existing, source.name()).filter(|text| !text.is_empty()) {
+            Some(text) => text,
+            None => regenerate(diff, settings, prompt, budget, artifact, source, existing)?,
+        };
+        context.push(format!("{}:\n{}", name, text));
+    }
+    let text = complete(settings, &system, &context.join("\n\n"))
+        .with_context(|| format!("Failed to generate the {:?} section", section))?;
+
+    Ok(section.tidy(&text))
+}
+
+// System prompt of each request that sends the diff, as compose makes them
+pub fn diff_prompts(prompt: &PromptTemplate, artifact: &str, sections: &[Section]) -> Vec<(Section, String)> {
+    diff_sections(sections)
+        .into_iter()
+        .map(|section| (section, prompt.section_message(&section.instructions(artifact))))
+        .collect()
+}
+
+// Put a regenerated section in place of the old one, keeping everything else verbatim
+pub fn splice(existing: &str, artifact: &str, section: Section, text: &str) -> String {
+    match section {
+        Section::Title => comment::replace_title(existing, text),
+        Section::Summary => comment::replace_summary(existing, text, &section.render(artifact, text)),
+        _ => {
+            let rendered = section.render(artifact, text);
+            let heading = rendered.lines().next().unwrap_or_default();
+            comment::replace_section(existing, section.name(), heading, text)
+        }
+    }
+}
+
+// Sections requested with the diff. Key changes and motivation are the context for the title and
+// summary, so they are generated even when not shown.
+fn diff_sections(sections: &[Section]) -> Vec<Section> {
+    let needs_context = sections.contains(&Section::Title) || sections.contains(&Section::Summary);
+    DIFF_SECTIONS
+        .iter()
+        .copied()
+        .filter(|section| {
+            sections.contains(section) || (needs_context && matches!(section, Section::KeyChanges | Section::Why))
+        })
+        .collect()
+}
+
+// Number of requests that send the diff, for the cost check
+pub fn diff_requests(sections: &[Section]) -> usize {
+    diff_sections(sections).len()
+}
+
+// Replies saved for this run: the keys to forget once it has finished, how many were resumed and how
+// many a rerun with --resume would find, and the models that wrote the replies received
+#[derive(Default)]
+struct Progress {
+    resume: bool,
+    keys: Vec<String>,
+    resumed: usize,
+    saved: usize,
+    models: Vec<String>,
+}
+
+impl Progress {
+    // Point a run that failed part way at --resume
+    fn check<T>(&self, result: Result<T>) -> Result<T> {
+        if result.is_err() && self.saved > 0 {
+            note(&format!(
+                "{} of the sections were saved; run again with --resume to request only the others",
+                self.saved
+            ));
+        }
+        result
+    }
+}
+
+// Generate each section with its own prompt and assemble them. Sections written from the diff are
+// requested in parallel; the title and summary are then written from the key changes and motivation,
+// which is cheaper and more focused than sending the diff again. With resume, sections an interrupted
+// run already received are not requested again.
+pub fn compose(
+    diff: &str,
+    settings: &ModelSettings,
+    prompt: &PromptTemplate,
+    budget: &DiffBudget,
+    artifact: &str,
+    sections: &[Section],
+    resume: bool,
+) -> Result<Reply> {
+    let wants = |section: Section| sections.contains(&section);
+    let diff_sections = diff_sections(sections);
+    let mut progress = Progress {
+        resume,
+        ..Default::default()
+    };
+
+    let user = diff_message(diff, budget);
+    let written = generate(&diff_sections, settings, &mut progress, |section| {
+        (prompt.section_message(&section.instructions(artifact)), user.clone())
+    });
+    let written = progress.check(written)?;
+
+    let text = |section: Section| {
+        written
+            .iter()
+            .find(|(written, _)| *written == section)
+            .map(|(_, text)| text.as_str())
+            .unwrap_or_default()
+    };
+
+    let context = format!(
+        "Key changes:\n{}\n\nMotivation:\n{}",
+        text(Section::KeyChanges),
+        text(Section::Why)
+    );
+    let framing: Vec<Section> = [Section::Title, Section::Summary]
+        .into_iter()
+        .filter(|&section| wants(section))
+        .collect();
+    let framed = generate(&framing, settings, &mut progress, |section| {
+        (prompt.section_message(&section.instructions(artifact)), context.clone())
+    });
+    let framed = progress.check(framed)?;
+
+    if resume {
+        note(&format!(
+            "Resumed {} of {} sections from the interrupted run",
+            progress.resumed,
+            progress.keys.len()
+        ));
+    }
+    progress::clear(&progress.keys);
+
+    let mut parts = Vec::new();
+    for section in [Section::Title, Section::Summary] {
+        if let Some((_, text)) = framed.iter().find(|(framed, _)| *framed == section) {
+            parts.push(section.render(artifact, &section.tidy(text)));
+        }
+    }
+    for &section in DIFF_SECTIONS {
+        if wants(section) {
+            parts.push(section.render(artifact, text(section)));
+        }
+    }
+
+    // Sections written by the fallback model make it the model of the comment
+    let model = progress.models.iter().find(|model| **model != settings.model).unwrap_or(&settings.model);
+    Ok(Reply {
+        text: parts.join("\n\n"),
+        model: model.clone(),
+    })
+}
+
+// Run one request per section at the same time, keeping the section order. Each reply is saved as it
+// arrives, so the sections received before an interruption survive it.
+fn generate(
+    sections: &[Section],
+    settings: &ModelSettings,
+    progress: &mut Progress,
+    request: impl Fn(Section) -> (String, String) + Sync,
+) -> Result<Vec<(Section, String)>> {
+    let mut pending = Vec::new();
+    let mut done = Vec::new();
+    for &section in sections {
+        let (system, user) = request(section);
+        let key = progress::key(settings, &system, &user);
+        match progress.resume.then(|| progress::load(&key)).flatten() {
+            Some(text) => {
+                progress.resumed += 1;
+                progress.saved += 1;
+                done.push((section, text));
+            }
+            None => pending.push((section, system, user, key.clone())),
+        }
+        progress.keys.push(key);
+    }
+
+    let written: Vec<Result<(Section, String, bool, String)>> = thread::scope(|scope| {
+        let handles: Vec<_> = pending
+            .iter()
+            .map(|(section, system, user, key)| {
+                scope.spawn(move || {
+                    let reply = api_client::send(settings, &ChatRequest::new(system, user))
+                        .with_context(|| format!("Failed to generate the {:?} section", section))?;
+                    let text = reply.text.trim().to_string();
+                    let saved = progress::save(key, &text)
+                        .map_err(|err| warn(&format!("could not save the {:?} section for --resume: {:#}", section, err)))
+                        .is_ok();
+                    Ok((*section, text, saved, reply.model))
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|_| anyhow::bail!("Section generation panicked")))
+            .collect()
+    });
+
+    // Every thread has finished, so all replies that arrived count even when another request failed
+    progress.saved += written.iter().filter(|result| matches!(result, Ok((_, _, true, _)))).count();
+    for result in written {
+        let (section, text, _, model) = result?;
+        progress.models.push(model);
+        done.push((section, text));
+    }
+    done.sort_by_key(|(section, _)| sections.iter().position(|other| other == section));
+    Ok(done)
+}
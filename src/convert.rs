@@ -0,0 +1,211 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{diff, git, warn};
+
+// Placeholder in converter commands replaced with the path of the file to convert
+const FILE_PLACEHOLDER: &str = "{file}";
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Temporary file removed again when dropped
+pub struct TempFile(pub PathBuf);
+
+impl TempFile {
+    pub fn create(extension: &str, contents: &[u8]) -> Result<Self> {
+        let name = format!(
+            "mr-comment-{}-{}.{}",
+            process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed),
+            extension
+        );
+        let path = env::temp_dir().join(name);
+        fs::write(&path, contents)
+            .with_context(|| format!("Failed to write temporary file: {}", path.display()))?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+// Replace "Binary files ... differ" entries with text diffs produced by the configured converters
+pub fn convert_binary_files(diff: &str, converters: &HashMap<String, String>) -> Result<String> {
+    if converters.is_empty() {
+        return Ok(diff.to_string());
+    }
+
+    let (preamble, files) = diff::split_files(diff);
+    let mut lines: Vec<String> = preamble.iter().map(|line| line.to_string()).collect();
+    let mut repo_root = None;
+
+    for file in &files {
+        let extension = Path::new(&file.path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        let converter = extension
+            .as_deref()
+            .and_then(|ext| converters.get(ext).map(|cmd| (ext, cmd)));
+        let is_binary = file.lines.iter().any(|line| line.starts_with("Binary files"));
+
+        let text_diff = match converter {
+            Some((ext, command)) if is_binary => {
+                let root = match &repo_root {
+                    Some(root) => root,
+                    None => repo_root.insert(git::repo_root()?),
+                };
+                match convert_file(file, ext, command, root) {
+                    Ok(text_diff) => text_diff,
+                    Err(err) => {
+                        warn(&format!("could not convert {}: {:#}", file.path, err));
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        match text_diff {
+            Some(text_diff) => {
+                lines.extend(
+                    file.lines
+                        .iter()
+                        .filter(|line| !line.starts_with("Binary files"))
+                        .map(|line| line.to_string()),
+                );
+                lines.push(if file.is_new {
+                    "--- /dev/null".to_string()
+                } else {
+                    format!("--- a/{}", file.path)
+                });
+                lines.push(if file.is_deleted {
+                    "+++ /dev/null".to_string()
+                } else {
+                    format!("+++ b/{}", file.path)
+                });
+                lines.extend(text_diff.lines().map(|line| line.to_string()));
+            }
+            None => lines.extend(file.lines.iter().map(|line| line.to_string())),
+        }
+    }
+
+    Ok(lines.join("\n"))
+}
+
+// Convert both sides of a binary change and diff the resulting text
+fn convert_file(
+    file: &diff::FileDiff,
+    extension: &str,
+    command: &str,
+    repo_root: &Path,
+) -> Result<Option<String>> {
+    let (old_blob, new_blob) = file
+        .lines
+        .iter()
+        .find_map(|line| line.strip_prefix("index "))
+        .and_then(|index| index.split(' ').next())
+        .and_then(|range| range.split_once(".."))
+        .context("Missing index line in diff")?;
+
+    let worktree_path = repo_root.join(&file.path);
+    let old_text = match read_blob(old_blob, None)? {
+        Some(contents) => run_converter(command, extension, &contents)?,
+        None => String::new(),
+    };
+    let new_text = match read_blob(new_blob, Some(&worktree_path))? {
+        Some(contents) => run_converter(command, extension, &contents)?,
+        None => String::new(),
+    };
+
+    let old_file = TempFile::create("txt", old_text.as_bytes())?;
+    let new_file = TempFile::create("txt", new_text.as_bytes())?;
+
+    let output = git::command()
+        .args(["diff", "--no-index", "--no-color", "--"])
+        .arg(&old_file.0)
+        .arg(&new_file.0)
+        .output()
+        .context("Failed to execute git diff --no-index")?;
+
+    // Exit code 1 just means the files differ
+    if output.status.code().is_some_and(|code| code > 1) {
+        return Err(git::error("diff --no-index", &output.stderr));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let hunks: Vec<&str> = stdout
+        .lines()
+        .skip_while(|line| !line.starts_with("@@"))
+        .collect();
+
+    if hunks.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(hunks.join("\n")))
+}
+
+// Read a blob from the object database, falling back to the working tree copy
+fn read_blob(hash: &str, worktree_path: Option<&Path>) -> Result<Option<Vec<u8>>> {
+    if hash.chars().all(|c| c == '0') {
+        return Ok(None);
+    }
+
+    let output = git::command()
+        .args(["cat-file", "blob", hash])
+        .stderr(Stdio::null())
+        .output()
+        .context("Failed to execute git cat-file command")?;
+
+    if output.status.success() {
+        return Ok(Some(output.stdout));
+    }
+
+    // Working tree changes aren't in the object database yet
+    match worktree_path {
+        Some(path) => fs::read(path)
+            .map(Some)
+            .with_context(|| format!("Failed to read file: {}", path.display())),
+        None => anyhow::bail!("Blob {} not found", hash),
+    }
+}
+
+fn run_converter(command: &str, extension: &str, contents: &[u8]) -> Result<String> {
+    let input = TempFile::create(extension, contents)?;
+    let quoted_path = shell_quote(&input.0.to_string_lossy());
+
+    let command_line = if command.contains(FILE_PLACEHOLDER) {
+        command.replace(FILE_PLACEHOLDER, &quoted_path)
+    } else {
+        format!("{} {}", command, quoted_path)
+    };
+
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", &command_line]).output()
+    } else {
+        Command::new("sh").args(["-c", &command_line]).output()
+    }
+    .with_context(|| format!("Failed to run converter: {}", command))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("Converter '{}' failed: {}", command, stderr.trim());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn shell_quote(value: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", value)
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
@@ -0,0 +1,137 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use serde_json::json;
+
+use crate::gitlab::GitLab;
+use crate::reviewers::Reviewer;
+use crate::{
+    build_prompt, comment, comment_for, finish_comment, generate_text, git, prepare_diff, read_diff, warn, Cli,
+    Config, Generated, ModelSettings,
+};
+
+#[derive(Args, Clone)]
+pub struct CreateMrArgs {
+    /// Branch the merge request is merged into
+    #[arg(long, default_value = "main")]
+    target: String,
+
+    /// Push the current branch to origin (setting its upstream) before creating the merge request
+    #[arg(long)]
+    push: bool,
+
+    /// Labels to add (comma-separated or repeated), besides --suggest-labels
+    #[arg(long = "label", value_name = "LABEL", value_delimiter = ',')]
+    labels: Vec<String>,
+
+    /// GitLab usernames to request a review from (comma-separated or repeated), besides --suggest-reviewers
+    #[arg(long = "reviewer", value_name = "USERNAME", value_delimiter = ',')]
+    reviewers: Vec<String>,
+
+    /// Create the merge request as a draft
+    #[arg(long)]
+    draft: bool,
+
+    /// GitLab instance URL (default: "gitlab_url" in the config, CI_SERVER_URL, or the host of the origin remote)
+    #[arg(long, value_name = "URL")]
+    gitlab_url: Option<String>,
+}
+
+pub fn run(cli: &Cli, config: &Config, settings: &ModelSettings, args: &CreateMrArgs) -> Result<()> {
+    let branch = git::output(&["symbolic-ref", "--short", "HEAD"])
+        .context("HEAD is detached; check out the branch to create the merge request from")?;
+    if branch == args.target {
+        anyhow::bail!("The current branch is the target branch {}", args.target);
+    }
+
+    let gitlab = GitLab::new(config, args.gitlab_url.as_deref())?;
+    if let Some(url) = gitlab.open_merge_request(&branch)? {
+        anyhow::bail!(
+            "{} already has an open merge request: {} (update its description with --post --mr <IID>)",
+            branch,
+            url
+        );
+    }
+
+    if args.push {
+        let status = git::command()
+            .args(["push", "--set-upstream", "origin", &branch])
+            .status()
+            .context("Failed to execute git push command")?;
+        if !status.success() {
+            anyhow::bail!("Failed to push {} to origin", branch);
+        }
+    }
+
+    // The merge request shows the changes since the branch left the remote's copy of the target
+    let remote_target = format!("origin/{}", args.target);
+    let base = match git::output(&["rev-parse", "--verify", "--quiet", &remote_target]) {
+        Some(_) => remote_target,
+        None => args.target.clone(),
+    };
+    let range_cli = Cli {
+        commit: Some(format!("{}...HEAD", base)),
+        file: None,
+        stash: None,
+        merge_base: false,
+        ..cli.clone()
+    };
+
+    let raw_diff = read_diff(&range_cli, config)?;
+    let prepared = prepare_diff(&range_cli, config, raw_diff)?;
+    let diff = &prepared.diff;
+    let prompt = build_prompt(&range_cli, config, settings)?.for_diff(&range_cli, diff, &prepared.changed_files)?;
+    let comment = comment_for(&range_cli, config, settings, &prompt, &prepared, false, |prompt, budget| {
+        generate_text(&range_cli, diff, settings, prompt, budget).map(|reply| Generated::Text(reply.text))
+    })?
+    .into_markdown();
+    let comment = comment::parse(&finish_comment(&range_cli, config, comment, &prepared)?);
+    let title = if args.draft { format!("Draft: {}", comment.title) } else { comment.title };
+
+    // Reviewers by name first, then the suggested ones; the author can't review their own merge request
+    let me = gitlab.current_user()?;
+    let mut reviewers: Vec<Reviewer> = args
+        .reviewers
+        .iter()
+        .map(|username| {
+            let username = username.trim_start_matches('@');
+            Reviewer {
+                name: username.to_string(),
+                username: Some(username.to_string()),
+                email: None,
+                reason: String::new(),
+            }
+        })
+        .collect();
+    reviewers.extend(prepared.reviewers.iter().cloned());
+    let mut reviewer_ids = Vec::new();
+    let mut unknown = Vec::new();
+    for reviewer in &reviewers {
+        match gitlab.find_user(reviewer)? {
+            Some(id) if id != me && !reviewer_ids.contains(&id) => reviewer_ids.push(id),
+            Some(_) => {}
+            None => unknown.push(reviewer.handle()),
+        }
+    }
+    if !unknown.is_empty() {
+        warn(&format!("no GitLab user found for {}, not added as reviewer", unknown.join(", ")));
+    }
+
+    let mut labels = args.labels.clone();
+    labels.extend(prepared.labels.iter().filter(|label| !args.labels.contains(label)).cloned());
+
+    let (iid, url) = gitlab.create_merge_request(&json!({
+        "source_branch": branch,
+        "target_branch": args.target,
+        "title": title,
+        "description": comment.description,
+        "labels": labels.join(","),
+        "reviewer_ids": reviewer_ids,
+    }))?;
+    if cli.quiet {
+        println!("{}", url);
+    } else {
+        println!("Created !{}: {}", iid, url);
+    }
+
+    Ok(())
+}
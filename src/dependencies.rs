@@ -0,0 +1,159 @@
+use regex::Regex;
+use std::collections::BTreeMap;
+
+use crate::diff::split_files;
+
+// Manifests whose dependency lines are compared; lockfiles are left to diff::filter_diff
+#[derive(Clone, Copy)]
+enum Manifest {
+    Cargo,
+    Npm,
+    GoMod,
+    Requirements,
+}
+
+impl Manifest {
+    fn for_path(path: &str) -> Option<Self> {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        match name {
+            "Cargo.toml" => Some(Manifest::Cargo),
+            "package.json" => Some(Manifest::Npm),
+            "go.mod" => Some(Manifest::GoMod),
+            _ if name.starts_with("requirements") && name.ends_with(".txt") => Some(Manifest::Requirements),
+            _ => None,
+        }
+    }
+}
+
+// Dependency name and version requirement declared on one manifest line
+struct Parser {
+    cargo: Regex,
+    cargo_version: Regex,
+    npm: Regex,
+    go: Regex,
+    requirement: Regex,
+    version_like: Regex,
+}
+
+impl Parser {
+    fn new() -> Self {
+        Parser {
+            cargo: Regex::new(r#"^\s*([A-Za-z0-9_-]+)\s*=\s*(?:"([^"]*)"|\{(.*)\})"#).unwrap(),
+            cargo_version: Regex::new(r#"\bversion\s*=\s*"([^"]*)""#).unwrap(),
+            npm: Regex::new(r#"^\s*"([^"]+)"\s*:\s*"([^"]*)"\s*,?\s*$"#).unwrap(),
+            go: Regex::new(r"^\s*(?:require\s+)?(\S+)\s+(v\d\S*)").unwrap(),
+            requirement: Regex::new(
+                r"^\s*([A-Za-z0-9][A-Za-z0-9._-]*)(?:\[[^\]]*\])?\s*(?:(==|>=|<=|~=|!=|>|<)\s*([^\s;#,]+))?",
+            )
+            .unwrap(),
+            // Version requirements, as opposed to e.g. package metadata or npm scripts
+            version_like: Regex::new(r"^(?:[\^~=<>v]*\s*\d|\*|latest|workspace:|npm:|file:|link:)").unwrap(),
+        }
+    }
+
+    fn parse(&self, manifest: Manifest, line: &str) -> Option<(String, Option<String>)> {
+        match manifest {
+            Manifest::Cargo => {
+                let caps = self.cargo.captures(line)?;
+                let name = &caps[1];
+                if ["name", "version", "edition", "rust-version", "resolver"].contains(&name) {
+                    return None;
+                }
+                if let Some(version) = caps.get(2) {
+                    let version = version.as_str();
+                    self.version_like.is_match(version).then(|| (name.to_string(), Some(version.to_string())))
+                } else {
+                    // Inline tables: version, path, git or workspace dependencies
+                    let table = &caps[3];
+                    let is_dependency = ["version", "path", "git", "workspace"]
+                        .iter()
+                        .any(|key| Regex::new(&format!(r"\b{}\s*=", key)).unwrap().is_match(table));
+                    is_dependency.then(|| {
+                        let version = self.cargo_version.captures(table).map(|caps| caps[1].to_string());
+                        (name.to_string(), version)
+                    })
+                }
+            }
+            Manifest::Npm => {
+                let caps = self.npm.captures(line)?;
+                let name = &caps[1];
+                if ["name", "version", "node", "npm", "yarn", "pnpm"].contains(&name)
+                    || !self.version_like.is_match(&caps[2])
+                {
+                    return None;
+                }
+                Some((name.to_string(), Some(caps[2].to_string())))
+            }
+            Manifest::GoMod => {
+                let caps = self.go.captures(line)?;
+                Some((caps[1].to_string(), Some(caps[2].to_string())))
+            }
+            Manifest::Requirements => {
+                let trimmed = line.trim_start();
+                if trimmed.starts_with('#') || trimmed.starts_with('-') {
+                    return None;
+                }
+                let caps = self.requirement.captures(line)?;
+                let version = match (caps.get(2), caps.get(3)) {
+                    (Some(op), Some(version)) if op.as_str() == "==" => Some(version.as_str().to_string()),
+                    (Some(op), Some(version)) => Some(format!("{}{}", op.as_str(), version.as_str())),
+                    _ => None,
+                };
+                Some((caps[1].to_string(), version))
+            }
+        }
+    }
+}
+
+// Added, removed and bumped dependencies per manifest, e.g. "Cargo.toml: serde 1.0.1 → 1.0.2"
+pub fn describe(diff: &str) -> Vec<String> {
+    let parser = Parser::new();
+    let (_, files) = split_files(diff);
+    let mut changes = Vec::new();
+
+    for file in &files {
+        let Some(manifest) = Manifest::for_path(&file.path) else {
+            continue;
+        };
+
+        let mut removed: BTreeMap<String, Option<String>> = BTreeMap::new();
+        let mut added: BTreeMap<String, Option<String>> = BTreeMap::new();
+        let mut in_hunk = false;
+        for line in &file.lines {
+            if line.starts_with("@@") {
+                in_hunk = true;
+            } else if !in_hunk {
+                continue;
+            } else if let Some(text) = line.strip_prefix('-') {
+                if let Some((name, version)) = parser.parse(manifest, text) {
+                    removed.insert(name, version);
+                }
+            } else if let Some(text) = line.strip_prefix('+') {
+                if let Some((name, version)) = parser.parse(manifest, text) {
+                    added.insert(name, version);
+                }
+            }
+        }
+
+        let version = |version: &Option<String>| version.as_ref().map(|v| format!(" {}", v)).unwrap_or_default();
+        for (name, old) in &removed {
+            match added.remove(name) {
+                // Moved or reformatted lines with the same requirement aren't a change
+                Some(new) if &new == old => {}
+                Some(new) => changes.push(format!(
+                    "{}: {} {} → {}",
+                    file.path,
+                    name,
+                    old.as_deref().unwrap_or("?"),
+                    new.as_deref().unwrap_or("?")
+                )),
+                None => changes.push(format!("{}: {}{} removed", file.path, name, version(old))),
+            }
+        }
+        for (name, new) in &added {
+            changes.push(format!("{}: {}{} added", file.path, name, version(new)));
+        }
+    }
+
+    changes
+}
@@ -0,0 +1,45 @@
+use crate::risk;
+
+// Changes whose rollout needs more than merging, by kind, detected from the changed paths
+pub fn detect(changed_files: &[String]) -> Vec<String> {
+    changed_files
+        .iter()
+        .filter_map(|path| kind(path).map(|kind| format!("{}: {}", kind, path)))
+        .collect()
+}
+
+// Instructions appended to the prompt when detect found something
+pub fn instructions(changes: &[String]) -> String {
+    let lines: Vec<String> = changes.iter().map(|change| format!("- {}", change)).collect();
+    format!(
+        "The diff contains changes that affect deployment:\n{}\n\nAlso add a \"## Deployment Notes:\" section before \
+         the Notes, as a bulleted list of what deploying this change requires: migrations to run and whether they \
+         are backwards compatible, infrastructure to apply, CI changes, the order of the steps, and how to roll back.",
+        lines.join("\n")
+    )
+}
+
+fn kind(path: &str) -> Option<&'static str> {
+    let lower = path.to_lowercase();
+    let name = lower.rsplit('/').next().unwrap_or(&lower);
+    let extension = name.rsplit_once('.').map(|(_, extension)| extension).unwrap_or_default();
+    let directories: Vec<&str> = lower.split('/').collect();
+    let directories = &directories[..directories.len() - 1];
+    let in_dir = |names: &[&str]| directories.iter().any(|dir| names.contains(dir));
+
+    if risk::is_migration(path) {
+        Some("Database migration")
+    } else if risk::is_ci(path) {
+        Some("CI configuration")
+    } else if ["tf", "tfvars", "hcl"].contains(&extension) || in_dir(&["terraform"]) {
+        Some("Terraform")
+    } else if ["chart.yaml", "helmfile.yaml"].contains(&name) || in_dir(&["helm", "charts"]) {
+        Some("Helm chart")
+    } else if name.starts_with("kustomization.") || in_dir(&["k8s", "kubernetes", "kustomize", "manifests"]) {
+        Some("Kubernetes manifest")
+    } else if name.starts_with("dockerfile") || name.starts_with("docker-compose") {
+        Some("Container build")
+    } else {
+        None
+    }
+}
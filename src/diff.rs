@@ -0,0 +1,609 @@
+use regex::Regex;
+use std::collections::{BTreeMap, HashSet};
+
+use crate::grouping;
+
+// Directory names treated as vendored/third-party code wherever they appear in a path
+pub const VENDORED_DIRS: &[&str] = &["vendor", "third_party", "third-party", "node_modules"];
+
+// Lockfiles whose changes are summarized by the dependency changes of their manifests
+const LOCKFILES: &[&str] = &[
+    "Cargo.lock",
+    "package-lock.json",
+    "npm-shrinkwrap.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "go.sum",
+    "poetry.lock",
+    "Pipfile.lock",
+    "uv.lock",
+    "Gemfile.lock",
+    "composer.lock",
+];
+
+// Leads a diff grouped by feature area, so the model knows what the headers are
+const GROUPING_NOTE: &str =
+    "Changed files are grouped by feature area (shared directory, related names, shared symbols) under \"## Area:\" headers.";
+
+// Manifest files whose "version" field identifies a vendored dependency's version
+const VERSION_MANIFESTS: &[&str] = &["package.json", "Cargo.toml", "pyproject.toml"];
+
+// Inputs that decide which files are collapsed or dropped from the diff
+#[derive(Default)]
+pub struct FilterOptions {
+    pub vendored_dirs: Vec<String>,
+    pub generated_files: HashSet<String>,
+    // New files larger than this (in bytes of content) are listed instead of shown
+    pub max_new_file_bytes: usize,
+    // Present the files grouped by feature area, with a header per area
+    pub group: bool,
+}
+
+// One file's section of a unified git diff
+pub struct FileDiff<'a> {
+    pub path: String,
+    pub lines: Vec<&'a str>,
+    pub is_new: bool,
+    pub is_deleted: bool,
+    // Source path and similarity of a rename or copy (git diff -M -C)
+    pub moved_from: Option<String>,
+    pub similarity: Option<u32>,
+    pub is_copy: bool,
+}
+
+// Split a unified diff into per-file sections, returning any preamble lines separately. Header lines are
+// only recognized before a file's first hunk: in a hunk, "--- /dev/null" is a removed "-- /dev/null" line
+// and "+++ b/x" an added "++ b/x". Nothing but hunks follows the first one until the next "diff --git"
+// line, so the hunk ends there rather than after the line counts of its "@@" header, which word diffs
+// don't keep to.
+pub fn split_files(diff: &str) -> (Vec<&str>, Vec<FileDiff<'_>>) {
+    let mut preamble = Vec::new();
+    let mut files: Vec<FileDiff> = Vec::new();
+    let mut in_hunk = false;
+
+    for line in diff.lines() {
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            in_hunk = false;
+            files.push(FileDiff {
+                path: git_header_path(rest),
+                lines: vec![line],
+                is_new: false,
+                is_deleted: false,
+                moved_from: None,
+                similarity: None,
+                is_copy: false,
+            });
+            continue;
+        }
+
+        let Some(file) = files.last_mut() else {
+            preamble.push(line);
+            continue;
+        };
+
+        in_hunk |= line.starts_with("@@");
+        if in_hunk {
+            file.lines.push(line);
+            continue;
+        }
+        if line.starts_with("new file mode") || line.starts_with("--- /dev/null") {
+            file.is_new = true;
+        } else if line.starts_with("deleted file mode") || line.starts_with("+++ /dev/null") {
+            file.is_deleted = true;
+        } else if let Some(path) = line.strip_prefix("+++ ").map(header_path) {
+            file.path = path.strip_prefix("b/").map(str::to_string).unwrap_or(path);
+        } else if let Some(percent) = line.strip_prefix("similarity index ") {
+            file.similarity = percent.trim_end_matches('%').parse().ok();
+        } else if let Some(path) = line.strip_prefix("rename from ") {
+            file.moved_from = Some(header_path(path));
+        } else if let Some(path) = line.strip_prefix("copy from ") {
+            file.moved_from = Some(header_path(path));
+            file.is_copy = true;
+        } else if let Some(path) = line.strip_prefix("rename to ").or_else(|| line.strip_prefix("copy to ")) {
+            file.path = header_path(path);
+        }
+        file.lines.push(line);
+    }
+
+    (preamble, files)
+}
+
+// Path of a "diff --git a/<path> b/<path>" line, from the text after "diff --git ". The halves are the same unless the file moved, and
+// then the rename lines name it, so unquoted paths with " b/" in them are split in the middle.
+pub fn git_header_path(rest: &str) -> String {
+    if rest.starts_with('"') {
+        // The closing quote is the first one not escaped by a backslash
+        let mut escaped = false;
+        let end = rest.char_indices().skip(1).find_map(|(index, c)| {
+            let end = c == '"' && !escaped;
+            escaped = c == '\\' && !escaped;
+            end.then_some(index)
+        });
+        let path = header_path(&rest[..end.map_or(rest.len(), |end| end + 1)]);
+        return path.strip_prefix("a/").map(str::to_string).unwrap_or(path);
+    }
+
+    // "a/" + path + " b/" + path
+    let half = rest.len().saturating_sub(5) / 2;
+    if let (Some(old), Some(" b/"), Some(new)) = (rest.get(2..2 + half), rest.get(2 + half..5 + half), rest.get(5 + half..)) {
+        if old == new {
+            return old.to_string();
+        }
+    }
+    rest.strip_prefix("a/")
+        .and_then(|rest| rest.split(" b/").next())
+        .unwrap_or(rest)
+        .to_string()
+}
+
+// A path as git writes it in headers: C-quoted with octal escapes when it has special or non-ASCII
+// characters, and followed by a tab on ---/+++ lines when it has a space
+fn header_path(text: &str) -> String {
+    let text = text.trim_end_matches('\t');
+    let Some(quoted) = text.strip_prefix('"').and_then(|text| text.strip_suffix('"')) else {
+        return text.to_string();
+    };
+
+    let mut bytes = Vec::new();
+    let mut rest = quoted.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let Some((&escape, tail)) = rest.split_first() else {
+            break;
+        };
+        rest = tail;
+        match escape {
+            b'0'..=b'7' => {
+                let digits = rest.iter().take(2).take_while(|digit| (b'0'..=b'7').contains(digit)).count();
+                let value = [escape].iter().chain(&rest[..digits]).fold(0u32, |value, digit| value * 8 + u32::from(digit - b'0'));
+                bytes.push(value as u8);
+                rest = &rest[digits..];
+            }
+            b'a' => bytes.push(0x07),
+            b'b' => bytes.push(0x08),
+            b'f' => bytes.push(0x0c),
+            b'n' => bytes.push(b'\n'),
+            b'r' => bytes.push(b'\r'),
+            b't' => bytes.push(b'\t'),
+            b'v' => bytes.push(0x0b),
+            other => bytes.push(other),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// A file whose only changes are its mode and/or whitespace
+pub struct TrivialChange {
+    pub path: String,
+    // Old and new mode, e.g. 100644 -> 100755
+    pub mode: Option<(String, String)>,
+    pub whitespace: bool,
+}
+
+// The changes of a diff where no file changes content, or None if any file does. Whitespace-only
+// files are recognized both by their hunks and as the header-only entries git diff -w leaves. Each run
+// of removed and added lines between context lines must remove the lines it adds, in the same order and
+// differing only in whitespace_insensitive's sense, so moved, swapped or joined lines are content changes.
+pub fn trivial_changes(diff: &str) -> Option<Vec<TrivialChange>> {
+    let (_, files) = split_files(diff);
+    if files.is_empty() {
+        return None;
+    }
+
+    let mut changes = Vec::new();
+    for file in &files {
+        if file.is_new || file.is_deleted || file.moved_from.is_some() {
+            return None;
+        }
+
+        let mut old_mode = None;
+        let mut new_mode = None;
+        // The current run of changed lines, compared when a context line or hunk header ends it
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        let mut in_hunk = false;
+        for line in &file.lines {
+            if line.starts_with("Binary files") || line.starts_with("GIT binary patch") {
+                return None;
+            }
+            let ends_run = line.starts_with("@@") || (in_hunk && !line.starts_with(['-', '+', '\\']));
+            if ends_run && removed != added {
+                return None;
+            }
+            if ends_run {
+                removed.clear();
+                added.clear();
+            }
+            if line.starts_with("@@") {
+                in_hunk = true;
+            } else if in_hunk && line.starts_with('~') {
+                // A word diff's line changed in place
+                return None;
+            } else if !in_hunk {
+                if let Some(mode) = line.strip_prefix("old mode ") {
+                    old_mode = Some(mode.to_string());
+                } else if let Some(mode) = line.strip_prefix("new mode ") {
+                    new_mode = Some(mode.to_string());
+                }
+            } else if let Some(text) = line.strip_prefix('-') {
+                removed.extend(whitespace_insensitive(text));
+            } else if let Some(text) = line.strip_prefix('+') {
+                added.extend(whitespace_insensitive(text));
+            }
+        }
+
+        if removed != added {
+            return None;
+        }
+        let mode = old_mode.zip(new_mode);
+        changes.push(TrivialChange {
+            path: file.path.clone(),
+            whitespace: in_hunk || mode.is_none(),
+            mode,
+        });
+    }
+
+    Some(changes)
+}
+
+// A line with its whitespace dropped except for one space between two words, so re-indenting and
+// `a+b` → `a + b` compare equal but `foo bar` → `foobar` doesn't. Blank lines are None.
+fn whitespace_insensitive(line: &str) -> Option<String> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut normalized = String::new();
+    let mut after_space = false;
+    for c in line.chars() {
+        if c.is_whitespace() {
+            after_space = true;
+            continue;
+        }
+        if after_space && is_word(c) && normalized.ends_with(is_word) {
+            normalized.push(' ');
+        }
+        after_space = false;
+        normalized.push(c);
+    }
+    (!normalized.is_empty()).then_some(normalized)
+}
+
+#[derive(Default)]
+struct VendoredDependency {
+    version: Option<String>,
+    all_new: bool,
+    all_deleted: bool,
+}
+
+// What git's output goes through before it is sent, minus the parts that ask git (the generated
+// files come in the options): the filter, then long unchanged runs collapsed to `context_lines`
+pub fn process(diff: &str, options: &FilterOptions, context_lines: usize) -> String {
+    collapse_unchanged(&filter_diff(diff, options), context_lines)
+}
+
+// Keep only the files under the given directory (the preamble, e.g. patch series messages, stays)
+pub fn restrict_to_scope(diff: &str, scope: &str) -> String {
+    let prefix = format!("{}/", scope.trim_start_matches("./").trim_end_matches('/'));
+    let (preamble, files) = split_files(diff);
+
+    let mut lines = preamble;
+    for file in files.iter().filter(|file| file.path.starts_with(&prefix)) {
+        lines.extend(file.lines.iter());
+    }
+    lines.join("\n")
+}
+
+// Shorten runs of unchanged lines inside hunks to `keep` lines next to each change, leaving a marker
+// with the number of lines left out. Hunk headers stay, so the model still sees where changes are.
+pub fn collapse_unchanged(diff: &str, keep: usize) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut run: Vec<&str> = Vec::new();
+    let mut in_hunk = false;
+    // Whether the current run follows a change in the same hunk
+    let mut after_change = false;
+
+    let flush = |run: &mut Vec<&str>, lines: &mut Vec<String>, after_change: bool, before_change: bool| {
+        let head = if after_change { keep } else { 0 };
+        let tail = if before_change { keep } else { 0 };
+        // A marker only pays off when it replaces more than one line
+        if run.len() > head.saturating_add(tail).saturating_add(1) {
+            lines.extend(run[..head].iter().map(|line| line.to_string()));
+            lines.push(format!(" [... {} unchanged lines ...]", run.len() - head - tail));
+            lines.extend(run[run.len() - tail..].iter().map(|line| line.to_string()));
+        } else {
+            lines.extend(run.iter().map(|line| line.to_string()));
+        }
+        run.clear();
+    };
+
+    for line in diff.lines() {
+        if in_hunk && line.starts_with(' ') {
+            run.push(line);
+            continue;
+        }
+
+        // "~" starts the lines of a word diff that changed in place
+        let is_change = in_hunk && line.starts_with(['+', '-', '~', '\\']);
+        flush(&mut run, &mut lines, after_change, is_change);
+        if line.starts_with("@@") {
+            in_hunk = true;
+            after_change = false;
+        } else if is_change {
+            after_change = true;
+        } else {
+            in_hunk = false;
+        }
+        lines.push(line.to_string());
+    }
+    flush(&mut run, &mut lines, after_change, false);
+
+    lines.join("\n")
+}
+
+// Work out which vendored dependency a path belongs to, if any
+fn vendored_dependency(path: &str, extra_dirs: &[String]) -> Option<String> {
+    let components: Vec<&str> = path.split('/').collect();
+
+    let rest = extra_dirs
+        .iter()
+        .map(|dir| dir.trim_matches('/'))
+        .filter(|dir| !dir.is_empty())
+        .find_map(|dir| path.strip_prefix(dir)?.strip_prefix('/'))
+        .map(|rest| rest.split('/').collect::<Vec<_>>())
+        .or_else(|| {
+            components
+                .iter()
+                .position(|c| VENDORED_DIRS.contains(c))
+                .map(|i| components[i + 1..].to_vec())
+        })?;
+
+    // Files sitting directly in the vendor root (e.g. vendor/modules.txt) are bookkeeping
+    if rest.len() < 2 {
+        return Some(String::new());
+    }
+
+    let name = if rest[0].starts_with('@') && rest.len() >= 3 {
+        // Scoped npm package
+        rest[..2].join("/")
+    } else if rest[0].contains('.') && rest.len() >= 4 {
+        // Go-style module path (host/org/repo)
+        rest[..3].join("/")
+    } else {
+        rest[0].to_string()
+    };
+
+    Some(name)
+}
+
+// Collapse vendored file sections into one entry per dependency
+fn summarize_vendored(
+    files: &[(String, &FileDiff)],
+) -> BTreeMap<String, VendoredDependency> {
+    let manifest_version = Regex::new(r#"^\+\s*"?version"?\s*[:=]\s*"([^"]+)""#).unwrap();
+    let go_module = Regex::new(r"^\+# (\S+) (v\S+)").unwrap();
+
+    let mut deps: BTreeMap<String, VendoredDependency> = BTreeMap::new();
+
+    for (name, file) in files {
+        let file_name = file.path.rsplit('/').next().unwrap_or(&file.path);
+
+        if name.is_empty() {
+            // vendor/modules.txt lists Go module versions
+            if file_name == "modules.txt" {
+                for line in &file.lines {
+                    if let Some(caps) = go_module.captures(line) {
+                        let dep = deps.entry(caps[1].to_string()).or_insert_with(|| VendoredDependency {
+                            all_new: true,
+                            all_deleted: true,
+                            ..Default::default()
+                        });
+                        dep.version = Some(caps[2].to_string());
+                    }
+                }
+            }
+            continue;
+        }
+
+        let dep = deps.entry(name.clone()).or_insert_with(|| VendoredDependency {
+            all_new: true,
+            all_deleted: true,
+            ..Default::default()
+        });
+        dep.all_new &= file.is_new;
+        dep.all_deleted &= file.is_deleted;
+
+        if VERSION_MANIFESTS.contains(&file_name) {
+            if let Some(caps) = file.lines.iter().find_map(|line| manifest_version.captures(line)) {
+                dep.version = Some(caps[1].to_string());
+            }
+        }
+    }
+
+    deps
+}
+
+// Bytes of content added by a new file, or None for binary files
+fn new_file_size(file: &FileDiff) -> Option<usize> {
+    if file.lines.iter().any(|line| line.starts_with("Binary files")) {
+        return None;
+    }
+    Some(
+        file.lines
+            .iter()
+            .filter(|line| line.starts_with('+') && !line.starts_with("+++"))
+            .map(|line| line.len())
+            .sum(),
+    )
+}
+
+fn format_size(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    }
+}
+
+// Strip binary noise and generated files, collapse large new files, deleted files and vendored code into summary lists
+pub fn filter_diff(diff: &str, options: &FilterOptions) -> String {
+    let (preamble, files) = split_files(diff);
+
+    let mut filtered_lines = preamble;
+    // Files whose changes stay in the diff, with the lines kept
+    let mut shown: Vec<(&FileDiff, Vec<&str>)> = Vec::new();
+    let mut new_files = Vec::new();
+    let mut deleted_files = Vec::new();
+    let mut generated_files = Vec::new();
+    let mut lockfiles = Vec::new();
+    let mut moved_files = Vec::new();
+    let mut binary_files = Vec::new();
+    let mut vendored = Vec::new();
+
+    for file in &files {
+        if options.generated_files.contains(&file.path) {
+            generated_files.push(file.path.as_str());
+        } else if LOCKFILES.contains(&file.path.rsplit('/').next().unwrap_or(&file.path)) {
+            lockfiles.push(file.path.as_str());
+        } else if let Some(name) = vendored_dependency(&file.path, &options.vendored_dirs) {
+            vendored.push((name, file));
+        } else if file.is_new {
+            match new_file_size(file) {
+                Some(size) if size > 0 && size <= options.max_new_file_bytes => {
+                    shown.push((file, file.lines[1..].to_vec()));
+                }
+                Some(size) if size > 0 => {
+                    new_files.push(format!("{} ({}, not shown)", file.path, format_size(size)))
+                }
+                _ => new_files.push(file.path.clone()),
+            }
+        } else if file.is_deleted {
+            deleted_files.push(file.path.as_str());
+        } else if let Some(from) = &file.moved_from {
+            // Summarized as a move; only content and mode changes made alongside it stay in the diff
+            moved_files.push(format!(
+                "{} → {} ({}{}% similar)",
+                from,
+                file.path,
+                if file.is_copy { "copy, " } else { "" },
+                file.similarity.unwrap_or(100)
+            ));
+            let lines = shown_lines(file, |line| {
+                !line.starts_with("similarity index") && !line.starts_with("rename ") && !line.starts_with("copy ")
+            });
+            if !is_binary(file) && lines.iter().any(|line| !line.starts_with("diff --git ")) {
+                shown.push((file, lines));
+            }
+        } else if is_binary(file) {
+            binary_files.push(file.path.as_str());
+        } else {
+            shown.push((file, shown_lines(file, |_| true)));
+        }
+    }
+
+    // Grouping only helps when the files fall into more than one area
+    let kept: Vec<&FileDiff> = shown.iter().map(|(file, _)| *file).collect();
+    let groups = if options.group { grouping::group(&kept) } else { Vec::new() };
+    let headers: Vec<String> = groups
+        .iter()
+        .map(|group| match group.files.len() {
+            1 => format!("## Area: {} (1 file)", group.name),
+            n => format!("## Area: {} ({} files)", group.name, n),
+        })
+        .collect();
+    if groups.len() > 1 {
+        filtered_lines.push(GROUPING_NOTE);
+        for (group, header) in groups.iter().zip(&headers) {
+            filtered_lines.push("");
+            filtered_lines.push(header);
+            for index in &group.files {
+                filtered_lines.extend(&shown[*index].1);
+            }
+        }
+    } else {
+        for (_, lines) in &shown {
+            filtered_lines.extend(lines);
+        }
+    }
+
+    // Build summary of new/moved/deleted files and vendored dependencies
+    let mut summary = String::new();
+    if !new_files.is_empty() {
+        summary += "\nNew files:\n";
+        for file in new_files {
+            summary += &format!("• {}\n", file);
+        }
+    }
+    if !moved_files.is_empty() {
+        summary += "\nMoved files:\n";
+        for file in moved_files {
+            summary += &format!("• {}\n", file);
+        }
+    }
+    if !deleted_files.is_empty() {
+        summary += "\nDeleted files:\n";
+        for file in deleted_files {
+            summary += &format!("• {}\n", file);
+        }
+    }
+
+    if !binary_files.is_empty() {
+        summary += "\nBinary files changed (not shown):\n";
+        for file in binary_files {
+            summary += &format!("• {}\n", file);
+        }
+    }
+
+    if !generated_files.is_empty() {
+        summary += "\nGenerated files (not shown):\n";
+        for file in generated_files {
+            summary += &format!("• {}\n", file);
+        }
+    }
+
+    if !lockfiles.is_empty() {
+        summary += "\nLockfiles (not shown):\n";
+        for file in lockfiles {
+            summary += &format!("• {}\n", file);
+        }
+    }
+
+    let vendored = summarize_vendored(&vendored);
+    if !vendored.is_empty() {
+        summary += "\nVendored dependencies:\n";
+        for (name, dep) in vendored {
+            let line = match (dep.all_new, dep.all_deleted, dep.version) {
+                (_, true, _) => format!("vendored dependency {} removed", name),
+                (true, _, Some(version)) => format!("vendored dependency {} added at {}", name, version),
+                (true, _, None) => format!("vendored dependency {} added", name),
+                (_, _, Some(version)) => format!("vendored dependency {} updated to {}", name, version),
+                (_, _, None) => format!("vendored dependency {} updated", name),
+            };
+            summary += &format!("• {}\n", line);
+        }
+    }
+
+    let mut filtered_diff = filtered_lines.join("\n");
+    if !filtered_diff.is_empty() && !summary.is_empty() {
+        filtered_diff.push('\n');
+    }
+    filtered_diff += &summary;
+    filtered_diff
+}
+
+fn is_binary(file: &FileDiff) -> bool {
+    file.lines.iter().any(|line| line.starts_with("Binary files") || line.starts_with("GIT binary patch"))
+}
+
+// A file's lines as sent, without the "diff --git" line the ---/+++ lines repeat; files without them
+// (mode changes, moves) keep it, so the lines still name the file
+fn shown_lines<'a>(file: &FileDiff<'a>, keep: impl Fn(&str) -> bool) -> Vec<&'a str> {
+    let named = file.lines.iter().any(|line| line.starts_with("+++ "));
+    file.lines
+        .iter()
+        .skip(usize::from(named))
+        .filter(|line| keep(line))
+        .copied()
+        .collect()
+}
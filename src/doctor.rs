@@ -0,0 +1,243 @@
+use anyhow::Result;
+use std::env;
+use std::thread;
+use std::time::Instant;
+
+use crate::{
+    auth, base_prompt, detect_git_host, get_config_path, git, github, gitlab, models, Cli, Config, GitHost, ModelSettings,
+};
+
+enum Status {
+    Ok,
+    // Runs work, but maybe not as expected
+    Warning,
+    // Not configured, and not needed for plain runs
+    Skipped,
+    Failed,
+}
+
+struct Check {
+    name: String,
+    status: Status,
+    message: String,
+}
+
+impl Check {
+    fn new(name: &str, status: Status, message: impl Into<String>) -> Self {
+        Check {
+            name: name.to_string(),
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn print(&self) {
+        let status = match self.status {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Skipped => "skipped",
+            Status::Failed => "failed",
+        };
+        println!("{}: {} - {}", self.name, status, self.message);
+    }
+}
+
+// Everything a run depends on, checked in one go for bug reports: git, the repository, the config, and
+// each configured endpoint with its key and latency. It loads the config itself, so a config that doesn't
+// parse is reported rather than stopping the report.
+pub fn run(cli: &Cli) -> Result<()> {
+    println!("mr-comment {} ({} {})", env!("CARGO_PKG_VERSION"), env::consts::OS, env::consts::ARCH);
+
+    let mut checks = vec![git_version()];
+    checks.extend(repository());
+    let config = match get_config_path() {
+        Ok(path) if !path.exists() => {
+            checks.push(Check::new("config", Status::Ok, format!("none at {}, using the defaults", path.display())));
+            Config::load().ok()
+        }
+        Ok(path) => match Config::load() {
+            Ok(config) => {
+                checks.push(Check::new("config", Status::Ok, path.display().to_string()));
+                Some(config)
+            }
+            Err(err) => {
+                checks.push(Check::new("config", Status::Failed, format!("{:#}", err)));
+                None
+            }
+        },
+        Err(err) => {
+            checks.push(Check::new("config", Status::Failed, format!("{:#}", err)));
+            None
+        }
+    };
+    checks.extend(config.as_ref().and_then(prompt));
+    checks.iter().for_each(Check::print);
+
+    let endpoints = match &config {
+        Some(config) => endpoints(cli, config),
+        None => vec![Check::new("providers", Status::Skipped, "the config doesn't load")],
+    };
+    endpoints.iter().for_each(Check::print);
+
+    let failed = checks
+        .iter()
+        .chain(&endpoints)
+        .filter(|check| matches!(check.status, Status::Failed))
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{} of the checks failed", failed);
+    }
+    Ok(())
+}
+
+fn git_version() -> Check {
+    match git::command().arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            Check::new("git", Status::Ok, String::from_utf8_lossy(&output.stdout).trim())
+        }
+        Ok(output) => Check::new("git", Status::Failed, String::from_utf8_lossy(&output.stderr).trim()),
+        Err(err) => Check::new("git", Status::Failed, format!("git could not be run: {}", err)),
+    }
+}
+
+// Where the diff comes from and whether there is one to describe without --commit
+fn repository() -> Vec<Check> {
+    if git::is_bare_repository() {
+        return vec![Check::new("repository", Status::Ok, "bare repository; choose the changes with --commit")];
+    }
+    let Ok(root) = git::repo_root() else {
+        let message = "not in a git repository; run it in one or point -C at one";
+        return vec![Check::new("repository", Status::Warning, message)];
+    };
+
+    let head = match git::output(&["branch", "--show-current"]) {
+        Some(branch) => format!("on branch {}", branch),
+        None => match git::output(&["rev-parse", "--short", "HEAD"]) {
+            Some(sha) => format!("detached at {}", sha),
+            None => "no commits yet".to_string(),
+        },
+    };
+    // Porcelain lines are "XY path": X is the index, Y the working tree, and untracked files are "??"
+    let status = git::command().args(["status", "--porcelain"]).output();
+    let status = status.map(|output| String::from_utf8_lossy(&output.stdout).into_owned()).unwrap_or_default();
+    let (mut staged, mut unstaged) = (0, 0);
+    for line in status.lines().filter(|line| !line.starts_with("??")) {
+        staged += usize::from(!line.starts_with(' '));
+        unstaged += usize::from(line.as_bytes().get(1).is_some_and(|&y| y != b' '));
+    }
+    let repository = Check::new(
+        "repository",
+        Status::Ok,
+        format!("{} {}, {} staged and {} unstaged files", root.display(), head, staged, unstaged),
+    );
+
+    let remote = match detect_git_host() {
+        Ok(GitHost::GitLab) => Check::new("remote", Status::Ok, "origin is on GitLab"),
+        Ok(GitHost::GitHub) => Check::new("remote", Status::Ok, "origin is on GitHub"),
+        Ok(GitHost::Unknown) => Check::new(
+            "remote",
+            Status::Warning,
+            "origin is neither on GitLab nor GitHub by its host name; set \"gitlab_url\" for --post",
+        ),
+        Err(_) => Check::new("remote", Status::Warning, "no origin remote; --post needs one outside CI"),
+    };
+    vec![repository, remote]
+}
+
+// The template, sections or MR template the config replaces the built-in prompt with
+fn prompt(config: &Config) -> Option<Check> {
+    if config.mr_template.is_none() && config.sections.is_none() && config.template.is_none() {
+        return None;
+    }
+    Some(match base_prompt(GitHost::Unknown, config, config.mr_template.as_ref()) {
+        Ok(_) => Check::new("prompt", Status::Ok, "the config's template loads"),
+        Err(err) => Check::new("prompt", Status::Failed, format!("{:#}", err)),
+    })
+}
+
+// The providers with a key, and GitLab or GitHub when there is a token for them, checked at the same
+// time so the report takes as long as the slowest endpoint
+fn endpoints(cli: &Cli, config: &Config) -> Vec<Check> {
+    let providers = auth::provider_settings(cli, config, None);
+    let mut checks = Vec::new();
+    if providers.is_empty() {
+        checks.push(Check::new(
+            "providers",
+            Status::Warning,
+            "no API key, so comments are written offline; set ANTHROPIC_API_KEY or OPENAI_API_KEY or run `mr-comment init`",
+        ));
+    }
+
+    let host = detect_git_host().unwrap_or(GitHost::Unknown);
+    thread::scope(|scope| {
+        let providers: Vec<_> = providers
+            .into_iter()
+            .map(|(name, settings)| scope.spawn(move || provider(&name, settings)))
+            .collect();
+        let gitlab = scope.spawn(|| gitlab_token(cli, config, host));
+        let github = scope.spawn(|| github_token(config, host));
+        checks.extend(providers.into_iter().map(|handle| handle.join().expect("provider check panicked")));
+        checks.extend(gitlab.join().expect("GitLab check panicked"));
+        checks.extend(github.join().expect("GitHub check panicked"));
+    });
+    checks
+}
+
+// The key and endpoint as `auth check` checks them, timed
+fn provider(name: &str, settings: Result<ModelSettings>) -> Check {
+    let settings = match settings {
+        Ok(settings) => settings,
+        Err(err) => return Check::new(name, Status::Failed, format!("{:#}", err)),
+    };
+    let start = Instant::now();
+    let result = auth::check(&settings);
+    let latency = start.elapsed().as_millis();
+    match result {
+        Ok(message) => match models::retired(&settings.model) {
+            Some(replacement) => Check::new(
+                name,
+                Status::Warning,
+                format!("{} ({} ms), but {} has been retired; use {}", message, latency, settings.model, replacement),
+            ),
+            None => Check::new(name, Status::Ok, format!("{} ({} ms)", message, latency)),
+        },
+        Err(message) => Check::new(name, Status::Failed, message),
+    }
+}
+
+// --post, create-mr, review-reply and release-notes need a token; plain runs don't, so a missing one is
+// only mentioned for the host of the origin remote
+fn gitlab_token(cli: &Cli, config: &Config, host: GitHost) -> Option<Check> {
+    if env::var_os("GITLAB_TOKEN").is_none() && config.gitlab_token.is_none() {
+        return matches!(host, GitHost::GitLab)
+            .then(|| Check::new("gitlab", Status::Skipped, "no GITLAB_TOKEN or \"gitlab_token\", which --post needs"));
+    }
+    let start = Instant::now();
+    let result = gitlab::GitLab::new(config, cli.gitlab_url.as_deref())
+        .and_then(|gitlab| gitlab.current_user().map(|_| gitlab.api_url().to_string()));
+    Some(match result {
+        Ok(api) => Check::new(
+            "gitlab",
+            Status::Ok,
+            format!("token accepted by {} ({} ms)", api, start.elapsed().as_millis()),
+        ),
+        Err(err) => Check::new("gitlab", Status::Failed, format!("{:#}", err)),
+    })
+}
+
+fn github_token(config: &Config, host: GitHost) -> Option<Check> {
+    if env::var("GITHUB_TOKEN").map_or(true, |token| token.is_empty()) && config.github_token.is_none() {
+        return matches!(host, GitHost::GitHub)
+            .then(|| Check::new("github", Status::Skipped, "no GITHUB_TOKEN or \"github_token\", which --post needs"));
+    }
+    let start = Instant::now();
+    let result = github::GitHub::new(config).and_then(|github| github.current_user());
+    Some(match result {
+        Ok(login) => Check::new(
+            "github",
+            Status::Ok,
+            format!("token of {} accepted ({} ms)", login, start.elapsed().as_millis()),
+        ),
+        Err(err) => Check::new("github", Status::Failed, format!("{:#}", err)),
+    })
+}
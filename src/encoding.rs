@@ -0,0 +1,103 @@
+use crate::diff;
+
+// Windows-1252 characters for bytes 0x80-0x9F, where it differs from Latin-1 (unassigned bytes map to
+// the Latin-1 control characters)
+const WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}', '\u{90}', '‘',
+    '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+// A file whose diff lines weren't UTF-8, with the encoding they were read as
+pub struct Transcoded {
+    pub path: String,
+    pub encoding: &'static str,
+}
+
+// Decode git output or a diff file. UTF-16 output (as written by Windows shells and some git
+// configurations) is transcoded as a whole; otherwise each line that isn't UTF-8 is decoded on its
+// own, so one legacy-encoded file doesn't spoil the rest of the diff. CRLF line endings are normalized.
+pub fn decode(bytes: Vec<u8>) -> (String, Vec<Transcoded>) {
+    // Without a BOM, UTF-16LE text has NUL in most odd bytes
+    let odd_nuls = bytes.iter().skip(1).step_by(2).filter(|byte| **byte == 0).count();
+    let looks_utf16le = bytes.len() >= 2 && odd_nuls * 2 > bytes.len() / 2;
+
+    let whole = if let Some(data) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+        Some(decode_utf16(data, u16::from_le_bytes))
+    } else if let Some(data) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        Some(decode_utf16(data, u16::from_be_bytes))
+    } else if looks_utf16le {
+        Some(decode_utf16(&bytes, u16::from_le_bytes))
+    } else {
+        None
+    };
+    if let Some(text) = whole {
+        return (text.replace("\r\n", "\n"), Vec::new());
+    }
+
+    let data = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(&bytes);
+    // NUL is valid UTF-8, but no text diff line has one unless it is UTF-16
+    let (text, transcoded) = match std::str::from_utf8(data) {
+        Ok(text) if !data.contains(&0) => (text.to_string(), Vec::new()),
+        _ => decode_lines(data),
+    };
+    (text.replace("\r\n", "\n"), transcoded)
+}
+
+fn decode_utf16(data: &[u8], to_u16: fn([u8; 2]) -> u16) -> String {
+    let units: Vec<u16> = data.chunks_exact(2).map(|pair| to_u16([pair[0], pair[1]])).collect();
+    String::from_utf16_lossy(&units)
+}
+
+// Decode line by line, noting the file (from the last diff header) of every line that needed transcoding
+fn decode_lines(data: &[u8]) -> (String, Vec<Transcoded>) {
+    let mut text = String::with_capacity(data.len());
+    let mut transcoded: Vec<Transcoded> = Vec::new();
+    let mut path = String::new();
+
+    for line in data.split_inclusive(|byte| *byte == b'\n') {
+        let (decoded, encoding) = match std::str::from_utf8(line) {
+            _ if is_utf16_line(line) => (decode_utf16_line(line), Some("UTF-16")),
+            Ok(line) => (line.to_string(), None),
+            Err(_) => (line.iter().map(|byte| windows_1252(*byte)).collect(), Some("Windows-1252")),
+        };
+        if let Some(header) = decoded.strip_prefix("diff --git ") {
+            path = diff::git_header_path(header.trim_end_matches(['\r', '\n']));
+        }
+        if let Some(encoding) = encoding {
+            if !transcoded.iter().any(|file| file.path == path) {
+                transcoded.push(Transcoded {
+                    path: path.clone(),
+                    encoding,
+                });
+            }
+        }
+        text += &decoded;
+    }
+    (text, transcoded)
+}
+
+// Diff lines of UTF-16 text shown with --text or a diff driver: text lines never contain NUL otherwise
+fn is_utf16_line(line: &[u8]) -> bool {
+    line.contains(&0)
+}
+
+// Keep the diff prefix (+, - or space), then decode the rest as UTF-16LE. Splitting the file at '\n'
+// leaves the NUL that completes each newline at the start of the next line, so it is skipped.
+fn decode_utf16_line(line: &[u8]) -> String {
+    let (prefix, content) = match line.first() {
+        Some(byte @ (b'+' | b'-' | b' ')) => (char::from(*byte).to_string(), &line[1..]),
+        _ => (String::new(), line),
+    };
+    let content = content.strip_prefix(&[0]).unwrap_or(content);
+    let newline = content.ends_with(b"\n");
+    let decoded = decode_utf16(content, u16::from_le_bytes);
+    let decoded = decoded.trim_end_matches(['\r', '\n', '\0']);
+    format!("{}{}{}", prefix, decoded, if newline { "\n" } else { "" })
+}
+
+fn windows_1252(byte: u8) -> char {
+    match byte {
+        0x80..=0x9F => WINDOWS_1252[usize::from(byte - 0x80)],
+        _ => char::from(byte),
+    }
+}
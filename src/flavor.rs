@@ -0,0 +1,190 @@
+use clap::ValueEnum;
+use regex::Regex;
+
+use crate::comment;
+
+// Lists with more items than this are folded into a <details> block where HTML is rendered
+const LONG_LIST_ITEMS: usize = 10;
+
+// Shortcodes GitLab and GitHub render as emoji, for markdown that doesn't; others are left as written
+const EMOJI: &[(&str, &str)] = &[
+    ("warning", "⚠️"),
+    ("white_check_mark", "✅"),
+    ("heavy_check_mark", "✔️"),
+    ("x", "❌"),
+    ("no_entry", "⛔"),
+    ("rotating_light", "🚨"),
+    ("red_circle", "🔴"),
+    ("yellow_circle", "🟡"),
+    ("green_circle", "🟢"),
+    ("information_source", "ℹ️"),
+    ("bulb", "💡"),
+    ("memo", "📝"),
+    ("pencil", "📝"),
+    ("rocket", "🚀"),
+    ("sparkles", "✨"),
+    ("tada", "🎉"),
+    ("bug", "🐛"),
+    ("fire", "🔥"),
+    ("zap", "⚡"),
+    ("lock", "🔒"),
+    ("boom", "💥"),
+    ("recycle", "♻️"),
+    ("wrench", "🔧"),
+    ("hammer", "🔨"),
+    ("package", "📦"),
+    ("construction", "🚧"),
+    ("art", "🎨"),
+    ("lipstick", "💄"),
+    ("test_tube", "🧪"),
+    ("books", "📚"),
+    ("eyes", "👀"),
+    ("arrow_up", "⬆️"),
+    ("arrow_down", "⬇️"),
+    ("heavy_plus_sign", "➕"),
+    ("heavy_minus_sign", "➖"),
+    ("card_file_box", "🗃️"),
+];
+
+// Markdown dialect of the comment: GitLab and GitHub render task lists, <details> and emoji
+// shortcodes, only GitLab runs quick actions, and plain CommonMark has none of it (collapsed
+// sections are shown expanded)
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+#[value(rename_all = "lowercase")]
+pub enum Flavor {
+    Gitlab,
+    Github,
+    Commonmark,
+}
+
+// The markdown rewritten for the flavor; fenced code is left alone
+pub fn apply(markdown: &str, flavor: Flavor) -> String {
+    let lines: Vec<String> = match flavor {
+        Flavor::Gitlab => markdown.lines().map(str::to_string).collect(),
+        Flavor::Github => outside_fences(markdown, |line| (!is_quick_action(line)).then(|| line.to_string())),
+        Flavor::Commonmark => {
+            let shortcode = Regex::new(r":([a-z0-9_+-]+):").unwrap();
+            outside_fences(&comment::expand_sections(markdown), |line| {
+                if is_quick_action(line) {
+                    return None;
+                }
+                let line = plain_task(line);
+                let line = shortcode.replace_all(&line, |caps: &regex::Captures| {
+                    match EMOJI.iter().find(|(name, _)| *name == &caps[1]) {
+                        Some((_, emoji)) => emoji.to_string(),
+                        None => caps[0].to_string(),
+                    }
+                });
+                Some(line.into_owned())
+            })
+        }
+    };
+
+    let lines = match flavor {
+        Flavor::Gitlab | Flavor::Github => fold_long_lists(lines),
+        Flavor::Commonmark => lines,
+    };
+    let mut markdown = lines.join("\n");
+    // Dropped quick actions leave blank lines at the end
+    markdown.truncate(markdown.trim_end().len());
+    markdown
+}
+
+// Apply f to the lines outside ``` fences, dropping the ones it returns None for along with the
+// blank line that separated them
+fn outside_fences(markdown: &str, f: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    let mut in_fence = false;
+    let mut dropped = false;
+    let mut lines: Vec<String> = Vec::new();
+    for line in markdown.lines() {
+        let fence = line.trim_start().starts_with("```");
+        if fence {
+            in_fence = !in_fence;
+        }
+        if fence || in_fence {
+            lines.push(line.to_string());
+            dropped = false;
+        } else if line.trim().is_empty() && dropped && lines.last().is_some_and(|last| last.trim().is_empty()) {
+            continue;
+        } else if let Some(line) = f(line) {
+            lines.push(line);
+            dropped = false;
+        } else {
+            dropped = true;
+        }
+    }
+    lines
+}
+
+// GitLab quick actions such as "/label ~backend" or "/assign_reviewer @dba-team", which other
+// platforms show as text
+pub fn is_quick_action(line: &str) -> bool {
+    let Some(rest) = line.strip_prefix('/') else {
+        return false;
+    };
+    let name: String = rest.chars().take_while(|c| c.is_ascii_lowercase() || *c == '_').collect();
+    !name.is_empty() && rest[name.len()..].chars().next().is_none_or(char::is_whitespace)
+}
+
+// CommonMark has no task lists: "- [ ] item" would show its brackets
+fn plain_task(line: &str) -> String {
+    let (indent, rest) = line.split_at(line.len() - line.trim_start().len());
+    if let Some(item) = rest.strip_prefix("- [ ] ") {
+        format!("{}- ☐ {}", indent, item)
+    } else if let Some(item) = rest.strip_prefix("- [x] ").or_else(|| rest.strip_prefix("- [X] ")) {
+        format!("{}- ☑ {}", indent, item)
+    } else {
+        line.to_string()
+    }
+}
+
+// Long lists, such as Key Changes citing a file each, folded into a collapsed <details> block unless
+// they are in one already (--collapse)
+fn fold_long_lists(lines: Vec<String>) -> Vec<String> {
+    let is_item = |line: &str| line.starts_with("- ") || line.starts_with("* ");
+    let mut folded = Vec::new();
+    let mut in_fence = false;
+    let mut in_details = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim();
+        if line.starts_with("```") {
+            in_fence = !in_fence;
+        } else if !in_fence && comment::is_details_tag(line) && !line.starts_with("<summary>") {
+            in_details = !line.starts_with("</");
+        }
+        if in_fence || in_details || !is_item(&lines[i]) {
+            folded.push(lines[i].clone());
+            i += 1;
+            continue;
+        }
+
+        // The list runs on through indented continuation lines and blank lines between items
+        let start = i;
+        let mut end = i + 1;
+        while end < lines.len() {
+            let line = &lines[end];
+            let continues = is_item(line) || line.starts_with([' ', '\t']) && !line.trim().is_empty();
+            let gap = line.trim().is_empty()
+                && lines.get(end + 1).is_some_and(|next| is_item(next) || next.starts_with([' ', '\t']));
+            if !continues && !gap {
+                break;
+            }
+            end += 1;
+        }
+
+        let items = lines[start..end].iter().filter(|line| is_item(line)).count();
+        if items > LONG_LIST_ITEMS {
+            folded.push("<details>".to_string());
+            folded.push(format!("<summary>{} items</summary>", items));
+            folded.push(String::new());
+            folded.extend(lines[start..end].iter().cloned());
+            folded.push(String::new());
+            folded.push("</details>".to_string());
+        } else {
+            folded.extend(lines[start..end].iter().cloned());
+        }
+        i = end;
+    }
+    folded
+}
@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{warn, Failure};
+
+// Terms that must not appear in a comment, such as internal codenames, customer names or profanity.
+// Unlike terminology they also count inside code spans: a name leaks just the same there.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ForbiddenConfig {
+    // Rejected: the model gets one retry to leave them out, then the run fails
+    #[serde(default)]
+    terms: Vec<String>,
+    // Rewritten by the tool instead, e.g. a customer's name to "a customer"
+    #[serde(default)]
+    replacements: HashMap<String, String>,
+}
+
+impl ForbiddenConfig {
+    // Rejected terms the text contains, in the order configured
+    pub fn found(&self, text: &str) -> Result<Vec<String>> {
+        let mut found = Vec::new();
+        for term in &self.terms {
+            if pattern(term)?.is_match(text) {
+                found.push(term.clone());
+            }
+        }
+        Ok(found)
+    }
+
+    // The text with every replacement applied, longest terms first so "Acme Corp EU" wins over "Acme Corp"
+    pub fn rewrite(&self, text: &str) -> Result<String> {
+        let mut sorted: Vec<(&String, &String)> = self.replacements.iter().collect();
+        sorted.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.0.cmp(b.0)));
+
+        let mut text = text.to_string();
+        for (from, to) in sorted {
+            text = pattern(from)?.replace_all(&text, regex::NoExpand(to)).into_owned();
+        }
+        Ok(text)
+    }
+
+    // Terms and replacements, for the cache key: a comment cached before a term was added must not be reused
+    pub fn fingerprint(&self) -> String {
+        let mut replacements: Vec<_> = self.replacements.iter().collect();
+        replacements.sort();
+        format!("{:?} {:?}", self.terms, replacements)
+    }
+}
+
+// What generate writes, checked for rejected terms. generate gets the terms a first draft contained, none
+// the first time, so its one retry can be told to leave them out; the run fails if the retry keeps any.
+// Everything the model writes for others to read goes through here, text giving what they would read.
+pub fn regenerate<T>(
+    forbidden: Option<&ForbiddenConfig>,
+    text: impl Fn(&T) -> String,
+    mut generate: impl FnMut(&[String]) -> Result<T>,
+) -> Result<T> {
+    let draft = generate(&[])?;
+    let Some(forbidden) = forbidden else {
+        return Ok(draft);
+    };
+    // Terms are counted rather than named in warnings: CI logs of public projects are public too
+    let terms = forbidden.found(&text(&draft))?;
+    if terms.is_empty() {
+        return Ok(draft);
+    }
+    warn(&format!("the reply contains {} forbidden term(s); regenerating", terms.len()));
+
+    let retry = generate(&terms)?;
+    let terms = forbidden.found(&text(&retry))?;
+    if !terms.is_empty() {
+        return Err(anyhow::anyhow!("The reply still contains {} forbidden term(s)", terms.len())
+            .context(Failure::Forbidden));
+    }
+    Ok(retry)
+}
+
+// Told to the model when it regenerates a comment whose draft contained rejected terms
+pub fn instructions(terms: &[String]) -> String {
+    let quoted: Vec<String> = terms.iter().map(|term| format!("\"{}\"", term)).collect();
+    format!(
+        "A draft of this text contained {}, which must never appear in it, not even in code or file names. \
+         Leave them out or describe what they refer to in general words.",
+        quoted.join(", ")
+    )
+}
+
+// Case-insensitive, on whole words where the term starts or ends with a word character
+fn pattern(term: &str) -> Result<Regex> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let pattern = format!(
+        "(?i){}{}{}",
+        if term.starts_with(is_word) { r"\b" } else { "" },
+        regex::escape(term),
+        if term.ends_with(is_word) { r"\b" } else { "" }
+    );
+    Regex::new(&pattern).with_context(|| format!("Invalid forbidden term: {}", term))
+}
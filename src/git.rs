@@ -0,0 +1,168 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::convert::TempFile;
+use crate::{paths, Cli, Failure};
+
+// Global git options from -C, --git-dir and --work-tree, set once at startup
+static LOCATION: OnceLock<Vec<OsString>> = OnceLock::new();
+
+pub fn set_location(cli: &Cli) {
+    let mut args = Vec::new();
+    if let Some(dir) = &cli.repo_dir {
+        args.extend(["-C".into(), dir.into()]);
+    }
+    if let Some(dir) = &cli.git_dir {
+        args.extend(["--git-dir".into(), dir.into()]);
+    }
+    if let Some(dir) = &cli.work_tree {
+        args.extend(["--work-tree".into(), dir.into()]);
+    }
+    let _ = LOCATION.set(args);
+}
+
+// Git in the repository of -C, --git-dir and --work-tree
+pub fn command() -> Command {
+    let mut command = base_command();
+    command.args(LOCATION.get().into_iter().flatten());
+    command
+}
+
+// Git in another repository, such as a submodule checkout, without the
+// superproject's --git-dir or --work-tree
+pub fn command_in(dir: &Path) -> Command {
+    let mut command = base_command();
+    command.arg("-C").arg(dir);
+    command
+}
+
+// Git at the top of the work tree, for commands that take paths relative to it. The repository is
+// passed on by absolute path, so --git-dir and --work-tree keep pointing at the same one.
+pub fn command_at_top() -> Result<Command> {
+    let root = repo_root()?;
+    let git_dir = output(&["rev-parse", "--absolute-git-dir"]).context("Failed to find the git directory")?;
+    let mut command = command_in(&root);
+    command.env("GIT_DIR", git_dir).env("GIT_WORK_TREE", &root);
+    Ok(command)
+}
+
+// Output that doesn't depend on the user's setup: messages and stats in English whatever the locale,
+// and paths as they are rather than octal-escaped when they aren't ASCII
+fn base_command() -> Command {
+    let mut command = Command::new(program());
+    command
+        .env("LC_ALL", "C")
+        .env("LANG", "C")
+        .args(["-c", "core.quotepath=false"]);
+    command
+}
+
+// --diff-algorithm of git diff
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum DiffAlgorithm {
+    Myers,
+    Minimal,
+    Patience,
+    Histogram,
+}
+
+// Trimmed stdout of a successful command with output; None otherwise
+pub fn output(args: &[&str]) -> Option<String> {
+    let output = command().args(args).output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !text.is_empty()).then_some(text)
+}
+
+// Error for a git command that exited unsuccessfully
+pub fn error(command: &str, stderr: &[u8]) -> anyhow::Error {
+    anyhow::anyhow!("Git {} failed: {}", command, String::from_utf8_lossy(stderr).trim()).context(Failure::Git)
+}
+
+// Hunks of a unified diff between two texts, from `git diff --no-index` on temporary copies; empty when
+// they are the same
+pub fn diff_texts(old: &str, new: &str) -> Result<String> {
+    let old_file = TempFile::create("txt", old.as_bytes())?;
+    let new_file = TempFile::create("txt", new.as_bytes())?;
+    let output = command()
+        .args(["diff", "--no-index", "--no-color", "--"])
+        .arg(&old_file.0)
+        .arg(&new_file.0)
+        .output()
+        .context("Failed to execute git diff --no-index")?;
+
+    // Exit code 1 just means the texts differ
+    if output.status.code().is_some_and(|code| code > 1) {
+        return Err(error("diff --no-index", &output.stderr));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let hunks: Vec<&str> = stdout
+        .lines()
+        .skip_while(|line| !line.starts_with("@@"))
+        .filter(|line| !line.starts_with("\\ No newline"))
+        .collect();
+    Ok(hunks.join("\n"))
+}
+
+pub fn repo_root() -> Result<PathBuf> {
+    let output = command()
+        .args(["rev-parse", "--show-toplevel"])
+        .output()
+        .context("Failed to execute git rev-parse command")?;
+
+    if !output.status.success() {
+        return Err(error("rev-parse", &output.stderr));
+    }
+
+    Ok(PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+// Bare repositories (e.g. on a server) have no working tree to diff or read files from
+pub fn is_bare_repository() -> bool {
+    command()
+        .args(["rev-parse", "--is-bare-repository"])
+        .output()
+        .is_ok_and(|output| output.status.success() && String::from_utf8_lossy(&output.stdout).trim() == "true")
+}
+
+// Path and lines added and deleted per file of `--numstat -z` output, None for binary files. Each file
+// is "added<TAB>deleted<TAB>path" and NUL-terminated; a rename or copy has an empty path instead, followed
+// by the old and new paths as fields of their own, so no path can be mistaken for a file. Renamed and
+// copied files are given by their new path.
+pub fn parse_numstat(output: &str) -> Vec<(String, Option<(usize, usize)>)> {
+    let mut stats = Vec::new();
+    let mut fields = output.split('\0');
+    while let Some(field) = fields.next() {
+        let mut parts = field.splitn(3, '\t');
+        let (Some(added), Some(deleted), Some(path)) = (parts.next(), parts.next(), parts.next()) else {
+            continue;
+        };
+        let path = match path {
+            "" => fields.nth(1).unwrap_or_default(),
+            path => path,
+        };
+        stats.push((path.to_string(), added.parse().ok().zip(deleted.parse().ok())));
+    }
+    stats
+}
+
+// Resolve the git executable once; on Windows this honours PATHEXT so git.cmd/git.bat shims are found
+fn program() -> &'static Path {
+    static GIT: OnceLock<PathBuf> = OnceLock::new();
+    GIT.get_or_init(|| {
+        if cfg!(windows) {
+            find_windows_executable("git").unwrap_or_else(|| PathBuf::from("git"))
+        } else {
+            PathBuf::from("git")
+        }
+    })
+}
+
+fn find_windows_executable(name: &str) -> Option<PathBuf> {
+    let extensions = env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+    paths::find_executable(name, &env::var_os("PATH")?, &extensions)
+}
@@ -0,0 +1,287 @@
+use anyhow::{Context, Result};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{ACCEPT, AUTHORIZATION, LINK, USER_AGENT};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use std::env;
+use std::fs;
+
+use crate::gitlab::{origin_project, PostMode};
+use crate::reviewers::Reviewer;
+use crate::{secrets, Config};
+
+const DEFAULT_API: &str = "https://api.github.com";
+
+// Author of comments posted with the GITHUB_TOKEN of a workflow, whose token can't look itself up
+const ACTIONS_BOT: &str = "github-actions[bot]";
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct IssueComment {
+    id: u64,
+    #[serde(default)]
+    body: String,
+    user: User,
+}
+
+#[derive(Deserialize)]
+struct Label {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct PullRequest {
+    user: User,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    labels: Vec<Label>,
+}
+
+// The pull request of a GitHub Actions event payload
+#[derive(Deserialize)]
+struct Event {
+    pull_request: Option<EventPullRequest>,
+}
+
+#[derive(Deserialize)]
+struct EventPullRequest {
+    number: u64,
+}
+
+// GitHub REST API for the repository of the workflow or the origin remote
+pub struct GitHub {
+    client: Client,
+    api: String,
+    repo: String,
+    token: String,
+}
+
+impl GitHub {
+    // The API is GITHUB_API_URL (set in Actions, also for GitHub Enterprise) or api.github.com; the
+    // repository is GITHUB_REPOSITORY or the origin remote's path
+    pub fn new(config: &Config) -> Result<Self> {
+        let api = env::var("GITHUB_API_URL")
+            .ok()
+            .filter(|api| !api.is_empty())
+            .unwrap_or_else(|| DEFAULT_API.to_string());
+        let repo = match env::var("GITHUB_REPOSITORY") {
+            Ok(repo) if !repo.is_empty() => repo,
+            _ => origin_project()?.1,
+        };
+        let token = match env::var("GITHUB_TOKEN") {
+            Ok(token) if !token.is_empty() => Some(token),
+            _ => secrets::reveal(config, "github_token", &config.github_token)?,
+        }
+        .context("GitHub token not provided. Set GITHUB_TOKEN or \"github_token\" in the config")?;
+
+        Ok(GitHub {
+            client: Client::builder().build().context("Failed to create HTTP client")?,
+            api: api.trim_end_matches('/').to_string(),
+            repo,
+            token,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/repos/{}/{}", self.api, self.repo, path)
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let response = self.send(self.client.get(self.url(path)))?;
+        response.json().context("Failed to parse GitHub API response")
+    }
+
+    // Every page of a list endpoint, following the Link header
+    fn get_all<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut url = format!("{}?per_page=100", self.url(path));
+        loop {
+            let response = self.send(self.client.get(&url))?;
+            let next = response
+                .headers()
+                .get(LINK)
+                .and_then(|value| value.to_str().ok())
+                .and_then(next_page);
+            let mut batch: Vec<T> = response.json().context("Failed to parse GitHub API response")?;
+            items.append(&mut batch);
+            match next {
+                Some(next) => url = next,
+                None => return Ok(items),
+            }
+        }
+    }
+
+    // Diff of the pull request as GitHub computes it, so shallow checkouts in CI don't matter
+    pub fn pull_request_diff(&self, number: u64) -> Result<String> {
+        let request = self
+            .client
+            .get(self.url(&format!("pulls/{}", number)))
+            .header(ACCEPT, "application/vnd.github.diff");
+        self.send(request)?
+            .text()
+            .with_context(|| format!("Failed to read the diff of pull request #{}", number))
+    }
+
+    // Post body as a sticky comment on the pull request, found again on later runs by its hidden
+    // marker; returns what was done, e.g. "updated"
+    pub fn post_comment(&self, number: u64, body: &str, marker: &str, mode: PostMode) -> Result<&'static str> {
+        let comments_path = format!("issues/{}/comments", number);
+        let tag = format!("<!-- {} -->", marker);
+        let body = json!({ "body": format!("{}\n\n{}", body.trim_end(), tag) });
+
+        let previous: Vec<IssueComment> = match mode {
+            PostMode::New => Vec::new(),
+            PostMode::Update | PostMode::Replace => {
+                // Only our own comments: someone quoting the comment copies the marker too
+                let me = self.current_login();
+                self.get_all::<IssueComment>(&comments_path)?
+                    .into_iter()
+                    .filter(|comment| comment.user.login == me && comment.body.contains(&tag))
+                    .collect()
+            }
+        };
+
+        match (mode, previous.first()) {
+            (PostMode::Update, Some(comment)) => {
+                let url = self.url(&format!("issues/comments/{}", comment.id));
+                self.send(self.client.patch(url).json(&body))?;
+                Ok("updated")
+            }
+            (PostMode::Replace, Some(_)) => {
+                for comment in &previous {
+                    self.send(self.client.delete(self.url(&format!("issues/comments/{}", comment.id))))?;
+                }
+                self.send(self.client.post(self.url(&comments_path)).json(&body))?;
+                Ok("replaced")
+            }
+            _ => {
+                self.send(self.client.post(self.url(&comments_path)).json(&body))?;
+                Ok("posted")
+            }
+        }
+    }
+
+    // The pull request description as it is, empty when there is none
+    pub fn description(&self, number: u64) -> Result<String> {
+        let pull_request: PullRequest = self.get(&format!("pulls/{}", number))?;
+        Ok(pull_request.body.unwrap_or_default())
+    }
+
+    // Replace the whole description, like the GitLab one
+    pub fn update_description(&self, number: u64, description: &str) -> Result<()> {
+        let url = self.url(&format!("pulls/{}", number));
+        self.send(self.client.patch(url).json(&json!({ "body": description }))).map(|_| ())
+    }
+
+    // Request reviews from the suggested reviewers with a GitHub handle ("@org/team" code owners as
+    // teams), leaving out the author; returns the handles requested and the ones without a handle
+    pub fn add_reviewers(&self, number: u64, reviewers: &[Reviewer]) -> Result<(Vec<String>, Vec<String>)> {
+        let pull_request: PullRequest = self.get(&format!("pulls/{}", number))?;
+        let mut users = Vec::new();
+        let mut teams = Vec::new();
+        let mut added = Vec::new();
+        let mut unknown = Vec::new();
+        for reviewer in reviewers {
+            match reviewer.username.as_deref() {
+                None => unknown.push(reviewer.handle()),
+                Some(username) if username.eq_ignore_ascii_case(&pull_request.user.login) => {}
+                Some(username) => {
+                    match username.split_once('/') {
+                        Some((_, team)) => teams.push(team.to_string()),
+                        None => users.push(username.to_string()),
+                    }
+                    added.push(reviewer.handle());
+                }
+            }
+        }
+
+        if !added.is_empty() {
+            let body = json!({ "reviewers": users, "team_reviewers": teams });
+            let url = self.url(&format!("pulls/{}/requested_reviewers", number));
+            self.send(self.client.post(url).json(&body))?;
+        }
+        Ok((added, unknown))
+    }
+
+    // Add labels to the pull request, keeping the ones already set; returns the labels it didn't have
+    pub fn add_labels(&self, number: u64, labels: &[String]) -> Result<Vec<String>> {
+        let pull_request: PullRequest = self.get(&format!("pulls/{}", number))?;
+        let added: Vec<String> = labels
+            .iter()
+            .filter(|label| !pull_request.labels.iter().any(|existing| &existing.name == *label))
+            .cloned()
+            .collect();
+
+        if !added.is_empty() {
+            let url = self.url(&format!("issues/{}/labels", number));
+            self.send(self.client.post(url).json(&json!({ "labels": added })))?;
+        }
+        Ok(added)
+    }
+
+    // Login of the token's user; a workflow's GITHUB_TOKEN can't read it and posts as the Actions bot
+    fn current_login(&self) -> String {
+        self.current_user().unwrap_or_else(|_| ACTIONS_BOT.to_string())
+    }
+
+    pub fn current_user(&self) -> Result<String> {
+        self.send(self.client.get(format!("{}/user", self.api)))
+            .and_then(|response| response.json::<User>().context("Failed to parse GitHub API response"))
+            .map(|user| user.login)
+    }
+
+    fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let response = request
+            .header(AUTHORIZATION, format!("Bearer {}", self.token))
+            .header(USER_AGENT, "mr-comment")
+            .header("X-GitHub-Api-Version", "2022-11-28")
+            .send()
+            .context("Failed to call GitHub API")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().unwrap_or_default();
+            anyhow::bail!("GitHub API request failed: {} {}", status, body.trim());
+        }
+
+        Ok(response)
+    }
+}
+
+// Whether this runs in a GitHub Actions workflow
+pub fn in_actions() -> bool {
+    env::var("GITHUB_ACTIONS").is_ok_and(|value| value == "true")
+}
+
+// Number of the pull request the workflow runs for, from the GITHUB_EVENT_PATH payload of
+// pull_request and pull_request_target events
+pub fn event_pull_request() -> Option<u64> {
+    if !in_actions() {
+        return None;
+    }
+    let path = env::var("GITHUB_EVENT_PATH").ok()?;
+    let event: Event = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    event.pull_request.map(|pull_request| pull_request.number)
+}
+
+// Pull request the comment belongs to: --mr, or the pull request of the Actions event
+pub fn pull_request_number(number: Option<u64>) -> Result<u64> {
+    number
+        .or_else(event_pull_request)
+        .context("--post needs --mr <NUMBER> outside GitHub Actions pull request workflows")
+}
+
+// URL of the rel="next" page in a Link header
+fn next_page(link: &str) -> Option<String> {
+    link.split(',').find_map(|part| {
+        let (url, rel) = part.split_once(';')?;
+        rel.contains("rel=\"next\"")
+            .then(|| url.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+    })
+}
@@ -0,0 +1,423 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use regex::Regex;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::env;
+
+use crate::reviewers::Reviewer;
+use crate::{comment, git, secrets, Config};
+
+// How --post treats notes it posted before
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum PostMode {
+    // Always add another note
+    New,
+    // Edit the previous note in place, or add one if there is none
+    Update,
+    // Delete the previous notes and add a new one at the bottom of the thread
+    Replace,
+}
+
+// Where --post puts the comment
+#[derive(Clone, Debug, PartialEq)]
+pub enum PostTarget {
+    // A note (GitLab) or comment (GitHub) of its own
+    Comment,
+    // The merge or pull request description, between markers so the rest of it is kept
+    Description,
+    // A new private snippet of the GitLab project
+    Snippet,
+    // A page of the GitLab project's wiki, between markers if it exists already
+    Wiki(String),
+}
+
+impl PostTarget {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "comment" => Ok(PostTarget::Comment),
+            "description" => Ok(PostTarget::Description),
+            "snippet" => Ok(PostTarget::Snippet),
+            _ => match value.strip_prefix("wiki:").map(|page| page.trim().trim_matches('/')) {
+                Some("") => Err("wiki: needs a page, e.g. wiki:release-notes/v1.2".to_string()),
+                Some(page) => Ok(PostTarget::Wiki(page.to_string())),
+                None => Err(format!(
+                    "invalid target '{}' (use comment, description, snippet or wiki:<page>)",
+                    value
+                )),
+            },
+        }
+    }
+
+    // Snippets and wiki pages belong to the project rather than a merge request
+    pub fn is_project(&self) -> bool {
+        matches!(self, PostTarget::Snippet | PostTarget::Wiki(_))
+    }
+}
+
+#[derive(Deserialize)]
+struct User {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct Note {
+    id: u64,
+    body: String,
+    author: User,
+}
+
+#[derive(Deserialize)]
+struct MergeRequest {
+    author: User,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    reviewers: Vec<User>,
+    #[serde(default)]
+    labels: Vec<String>,
+}
+
+// Merge request as the list endpoints return it
+#[derive(Deserialize)]
+pub struct MergeRequestSummary {
+    pub iid: u64,
+    pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    pub state: String,
+    pub web_url: String,
+}
+
+#[derive(Deserialize)]
+struct Snippet {
+    web_url: String,
+}
+
+#[derive(Deserialize)]
+struct WikiPage {
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct CreatedMergeRequest {
+    iid: u64,
+    web_url: String,
+}
+
+// GitLab REST API v4 for the origin project
+pub struct GitLab {
+    client: Client,
+    api: String,
+    project: String,
+    token: String,
+}
+
+impl GitLab {
+    // The instance is --gitlab-url, "gitlab_url" in the config, CI_SERVER_URL in GitLab CI, or the
+    // host of the origin remote; the project is CI_PROJECT_PATH or the origin remote's path
+    pub fn new(config: &Config, url: Option<&str>) -> Result<Self> {
+        let origin = || origin_project();
+        let base = match url.map(str::to_string).or_else(|| config.gitlab_url.clone()) {
+            Some(base) => base,
+            None => match env::var("CI_SERVER_URL") {
+                Ok(base) if !base.is_empty() => base,
+                _ => format!("https://{}", origin()?.0),
+            },
+        };
+        let project = match env::var("CI_PROJECT_PATH") {
+            Ok(project) if !project.is_empty() => project,
+            _ => origin()?.1,
+        };
+        let token = match env::var("GITLAB_TOKEN") {
+            Ok(token) => Some(token),
+            Err(_) => secrets::reveal(config, "gitlab_token", &config.gitlab_token)?,
+        }
+        .context("GitLab token not provided. Set GITLAB_TOKEN or \"gitlab_token\" in the config")?;
+
+        Ok(GitLab {
+            client: Client::builder().build().context("Failed to create HTTP client")?,
+            api: format!("{}/api/v4", base.trim_end_matches('/')),
+            project: project.replace('/', "%2F"),
+            token,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/projects/{}/{}", self.api, self.project, path)
+    }
+
+    pub fn api_url(&self) -> &str {
+        &self.api
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let response = self.send(self.client.get(self.url(path)))?;
+        response.json().context("Failed to parse GitLab API response")
+    }
+
+    // Every page of a list endpoint
+    pub fn get_all<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut page = "1".to_string();
+        loop {
+            let request = self
+                .client
+                .get(self.url(path))
+                .query(&[("per_page", "100"), ("page", page.as_str())]);
+            let response = self.send(request)?;
+            let next = response
+                .headers()
+                .get("x-next-page")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+                .filter(|next| !next.is_empty());
+            let mut batch: Vec<T> = response.json().context("Failed to parse GitLab API response")?;
+            items.append(&mut batch);
+            match next {
+                Some(next) => page = next,
+                None => return Ok(items),
+            }
+        }
+    }
+
+    // Post body as a note on the MR. Notes are tagged with a hidden marker so later runs can find
+    // the ones they posted; returns what was done, e.g. "updated".
+    pub fn post_note(&self, mr: u64, body: &str, marker: &str, mode: PostMode) -> Result<&'static str> {
+        let notes_path = format!("merge_requests/{}/notes", mr);
+        let tag = format!("<!-- {} -->", marker);
+        let body = json!({ "body": format!("{}\n\n{}", body.trim_end(), tag) });
+
+        let previous: Vec<Note> = match mode {
+            PostMode::New => Vec::new(),
+            PostMode::Update | PostMode::Replace => {
+                // Only our own notes: someone quoting the comment copies the marker too
+                let me = self.current_user()?;
+                self.get_all::<Note>(&notes_path)?
+                    .into_iter()
+                    .filter(|note| note.author.id == me && note.body.contains(&tag))
+                    .collect()
+            }
+        };
+
+        match (mode, previous.first()) {
+            (PostMode::Update, Some(note)) => {
+                let request = self.client.put(self.url(&format!("{}/{}", notes_path, note.id))).json(&body);
+                self.send(request)?;
+                Ok("updated")
+            }
+            (PostMode::Replace, Some(_)) => {
+                for note in &previous {
+                    self.send(self.client.delete(self.url(&format!("{}/{}", notes_path, note.id))))?;
+                }
+                self.post(&notes_path, &body)?;
+                Ok("replaced")
+            }
+            _ => {
+                self.post(&notes_path, &body)?;
+                Ok("posted")
+            }
+        }
+    }
+
+    // The MR description as it is, empty when there is none
+    pub fn description(&self, mr: u64) -> Result<String> {
+        let merge_request: MergeRequest = self.get(&format!("merge_requests/{}", mr))?;
+        Ok(merge_request.description.unwrap_or_default())
+    }
+
+    // Replace the whole description; the comment goes in between markers with comment::splice_marked
+    pub fn update_description(&self, mr: u64, description: &str) -> Result<()> {
+        let url = self.url(&format!("merge_requests/{}", mr));
+        self.send(self.client.put(url).json(&json!({ "description": description }))).map(|_| ())
+    }
+
+    // Publish body as a private project snippet with the title; returns its URL
+    pub fn create_snippet(&self, title: &str, body: &str) -> Result<String> {
+        let name: String = title
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+            .collect();
+        let file_name = format!("{}.md", name.trim_matches('-'));
+        let request = self.client.post(self.url("snippets")).json(&json!({
+            "title": title,
+            "visibility": "private",
+            "files": [{ "file_path": file_name, "content": body }],
+        }));
+        let snippet: Snippet = self.send(request)?.json().context("Failed to parse GitLab API response")?;
+        Ok(snippet.web_url)
+    }
+
+    // Write body to the wiki page, creating it or putting it between markers in the page as it is, like
+    // the MR description; returns what was done, e.g. "updated"
+    pub fn write_wiki_page(&self, page: &str, body: &str, marker: &str) -> Result<&'static str> {
+        let path = format!("wikis/{}", wiki_slug(page).replace('/', "%2F"));
+        let response = self
+            .client
+            .get(self.url(&path))
+            .header("PRIVATE-TOKEN", &self.token)
+            .send()
+            .context("Failed to call GitLab API")?;
+        if response.status() == StatusCode::NOT_FOUND {
+            let content = comment::splice_marked("", body, marker);
+            return self
+                .post("wikis", &json!({ "title": page, "content": content, "format": "markdown" }))
+                .map(|_| "created");
+        }
+        let existing: WikiPage = check(response)?.json().context("Failed to parse GitLab API response")?;
+        let content = comment::splice_marked(&existing.content, body, marker);
+        self.send(self.client.put(self.url(&path)).json(&json!({ "content": content })))
+            .map(|_| "updated")
+    }
+
+    // Add the suggested reviewers GitLab has a user for (by username, or by public email) to the MR's
+    // reviewers, keeping the ones already set; returns the handles added and the ones without a user
+    pub fn add_reviewers(&self, mr: u64, reviewers: &[Reviewer]) -> Result<(Vec<String>, Vec<String>)> {
+        let path = format!("merge_requests/{}", mr);
+        let merge_request: MergeRequest = self.get(&path)?;
+        let mut ids: Vec<u64> = merge_request.reviewers.iter().map(|user| user.id).collect();
+
+        let mut added = Vec::new();
+        let mut unknown = Vec::new();
+        for reviewer in reviewers {
+            match self.find_user(reviewer)? {
+                // Groups have no user, and the author can't review their own MR
+                None => unknown.push(reviewer.handle()),
+                Some(id) if id == merge_request.author.id || ids.contains(&id) => {}
+                Some(id) => {
+                    ids.push(id);
+                    added.push(reviewer.handle());
+                }
+            }
+        }
+
+        if !added.is_empty() {
+            self.send(self.client.put(self.url(&path)).json(&json!({ "reviewer_ids": ids })))?;
+        }
+        Ok((added, unknown))
+    }
+
+    // Add labels to the MR, keeping the ones already set; returns the labels it didn't have
+    pub fn add_labels(&self, mr: u64, labels: &[String]) -> Result<Vec<String>> {
+        let path = format!("merge_requests/{}", mr);
+        let merge_request: MergeRequest = self.get(&path)?;
+        let added: Vec<String> = labels
+            .iter()
+            .filter(|label| !merge_request.labels.contains(label))
+            .cloned()
+            .collect();
+
+        if !added.is_empty() {
+            self.send(self.client.put(self.url(&path)).json(&json!({ "add_labels": added.join(",") })))?;
+        }
+        Ok(added)
+    }
+
+    // Merge requests containing the commit
+    pub fn commit_merge_requests(&self, sha: &str) -> Result<Vec<MergeRequestSummary>> {
+        self.get(&format!("repository/commits/{}/merge_requests", sha))
+    }
+
+    // Id of the reviewer's user, by username or else by public email; None when GitLab has no such user
+    pub fn find_user(&self, reviewer: &Reviewer) -> Result<Option<u64>> {
+        let query = match (&reviewer.username, &reviewer.email) {
+            (Some(username), _) => ("username", username),
+            (None, Some(email)) => ("search", email),
+            (None, None) => return Ok(None),
+        };
+        let users: Vec<User> = self
+            .send(self.client.get(format!("{}/users", self.api)).query(&[query]))?
+            .json()
+            .context("Failed to parse GitLab API response")?;
+        Ok(users.first().map(|user| user.id))
+    }
+
+    // Id of the token's user
+    pub fn current_user(&self) -> Result<u64> {
+        let me: User = self
+            .send(self.client.get(format!("{}/user", self.api)))?
+            .json()
+            .context("Failed to parse GitLab API response")?;
+        Ok(me.id)
+    }
+
+    // Create a merge request from the body (source_branch, target_branch, title, ...); returns its IID and URL
+    pub fn create_merge_request(&self, body: &Value) -> Result<(u64, String)> {
+        let created: CreatedMergeRequest = self
+            .send(self.client.post(self.url("merge_requests")).json(body))?
+            .json()
+            .context("Failed to parse GitLab API response")?;
+        Ok((created.iid, created.web_url))
+    }
+
+    // URL of the open merge request from the branch, if there is one
+    pub fn open_merge_request(&self, source_branch: &str) -> Result<Option<String>> {
+        let request = self
+            .client
+            .get(self.url("merge_requests"))
+            .query(&[("source_branch", source_branch), ("state", "opened")]);
+        let open: Vec<CreatedMergeRequest> = self.send(request)?.json().context("Failed to parse GitLab API response")?;
+        Ok(open.into_iter().next().map(|merge_request| merge_request.web_url))
+    }
+
+    pub fn post(&self, path: &str, body: &Value) -> Result<()> {
+        self.send(self.client.post(self.url(path)).json(body)).map(|_| ())
+    }
+
+    fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let response = request
+            .header("PRIVATE-TOKEN", &self.token)
+            .send()
+            .context("Failed to call GitLab API")?;
+        check(response)
+    }
+}
+
+fn check(response: Response) -> Result<Response> {
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().unwrap_or_default();
+        anyhow::bail!("GitLab API request failed: {} {}", status, body.trim());
+    }
+
+    Ok(response)
+}
+
+// Merge request the comment belongs to: --mr, or CI_MERGE_REQUEST_IID in merge request pipelines
+pub fn merge_request_iid(mr: Option<u64>) -> Result<u64> {
+    if let Some(mr) = mr {
+        return Ok(mr);
+    }
+    let Ok(iid) = env::var("CI_MERGE_REQUEST_IID") else {
+        anyhow::bail!("--post needs --mr <IID> outside GitLab merge request pipelines");
+    };
+    iid.parse().with_context(|| format!("Invalid CI_MERGE_REQUEST_IID: {}", iid))
+}
+
+// Slug GitLab gives a wiki page of the title: nested pages keep their slashes, spaces become dashes
+fn wiki_slug(title: &str) -> String {
+    title.trim().replace(' ', "-")
+}
+
+// Host and "group/project" path of the origin remote (SSH or HTTPS)
+pub fn origin_project() -> Result<(String, String)> {
+    let output = git::command()
+        .args(["remote", "get-url", "origin"])
+        .output()
+        .context("Failed to execute git remote command")?;
+    let url = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || url.is_empty() {
+        anyhow::bail!("No origin remote found");
+    }
+
+    let re = Regex::new(r"^(?:[a-z+]+://)?(?:[^@/]+@)?([^:/]+)(?::\d+)?[:/](.+?)(?:\.git)?/?$").unwrap();
+    let caps = re
+        .captures(&url)
+        .with_context(|| format!("Could not read the project from the origin remote: {}", url))?;
+    Ok((caps[1].to_string(), caps[2].to_string()))
+}
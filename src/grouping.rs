@@ -0,0 +1,158 @@
+use regex::Regex;
+use std::collections::HashMap;
+
+use crate::diff::FileDiff;
+
+// Base names too common to say two files belong together
+const GENERIC_STEMS: &[&str] = &[
+    "mod", "lib", "main", "index", "init", "__init__", "utils", "util", "helpers", "types", "common", "readme",
+    "changelog", "config", "test", "tests", "spec",
+];
+
+// Symbols used in more files than this are shared infrastructure, not a feature
+const MAX_SYMBOL_FILES: usize = 4;
+
+// Files of one feature area, by their index in the diff
+pub struct Group {
+    pub name: String,
+    pub files: Vec<usize>,
+}
+
+// Group the files of a diff by feature area: files are in one area when they share a directory, a
+// base name (code and its tests, e.g. src/auth.rs and tests/auth_test.rs), or a symbol one of them
+// defines and another's changed lines use. Larger areas come first; files keep their diff order.
+pub fn group(files: &[&FileDiff]) -> Vec<Group> {
+    let mut areas = UnionFind::new(files.len());
+
+    let mut by_key: HashMap<String, usize> = HashMap::new();
+    for (index, file) in files.iter().enumerate() {
+        let keys = [Some(format!("dir:{}", directory(&file.path))), stem(&file.path).map(|stem| format!("stem:{}", stem))];
+        for key in keys.into_iter().flatten() {
+            match by_key.get(&key) {
+                Some(&first) => areas.union(first, index),
+                None => {
+                    by_key.insert(key, index);
+                }
+            }
+        }
+    }
+
+    let definition = Regex::new(
+        r"\b(?:fn|def|class|struct|enum|trait|interface|type|func|function|module|impl)\s+([A-Za-z_][A-Za-z0-9_]{3,})",
+    )
+    .unwrap();
+    let changed: Vec<String> = files
+        .iter()
+        .map(|file| {
+            let lines = file.lines.iter().filter(|line| {
+                (line.starts_with('+') && !line.starts_with("+++")) || (line.starts_with('-') && !line.starts_with("---"))
+            });
+            lines.map(|line| &line[1..]).collect::<Vec<_>>().join("\n")
+        })
+        .collect();
+    for (index, text) in changed.iter().enumerate() {
+        for symbol in definition.captures_iter(text).map(|caps| caps[1].to_string()) {
+            let word = Regex::new(&format!(r"\b{}\b", regex::escape(&symbol))).unwrap();
+            let users: Vec<usize> = (0..files.len())
+                .filter(|other| *other != index && word.is_match(&changed[*other]))
+                .collect();
+            if users.len() < MAX_SYMBOL_FILES {
+                for user in users {
+                    areas.union(index, user);
+                }
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut roots: HashMap<usize, usize> = HashMap::new();
+    for index in 0..files.len() {
+        let root = areas.find(index);
+        let position = *roots.entry(root).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[position].push(index);
+    }
+    // Stable: areas of equal size keep the order they first appear in
+    groups.sort_by_key(|group| std::cmp::Reverse(group.len()));
+
+    groups
+        .into_iter()
+        .map(|indexes| {
+            let paths: Vec<&str> = indexes.iter().map(|index| files[*index].path.as_str()).collect();
+            Group {
+                name: area_name(&paths),
+                files: indexes,
+            }
+        })
+        .collect()
+}
+
+fn directory(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+// File name without extensions and test markers: "auth_test.go", "test_auth.py" and "Auth.spec.ts"
+// all become "auth"
+fn stem(path: &str) -> Option<String> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let name = name.split('.').next().unwrap_or(name).to_lowercase();
+    let name = name.strip_prefix("test_").unwrap_or(&name);
+    let name = ["_test", "_tests", "_spec", "test", "tests"]
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .unwrap_or(name)
+        .trim_end_matches(['_', '-']);
+    (name.len() >= 3 && !GENERIC_STEMS.contains(&name)).then(|| name.to_string())
+}
+
+// The directory the files share, or the file name of a single file
+fn area_name(paths: &[&str]) -> String {
+    if let [path] = paths {
+        return path.to_string();
+    }
+    let mut common: Vec<&str> = directory(paths[0]).split('/').collect();
+    for path in &paths[1..] {
+        let parts: Vec<&str> = directory(path).split('/').collect();
+        let shared = common.iter().zip(&parts).take_while(|(a, b)| a == b).count();
+        common.truncate(shared);
+    }
+    let common = common.join("/");
+    if common.is_empty() {
+        // Related by name or symbol across the tree, e.g. src/auth.rs and tests/auth_test.rs
+        stem(paths[0]).unwrap_or_else(|| "(root)".to_string())
+    } else {
+        format!("{}/", common)
+    }
+}
+
+struct UnionFind {
+    parent: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(size: usize) -> Self {
+        UnionFind {
+            parent: (0..size).collect(),
+        }
+    }
+
+    fn find(&mut self, index: usize) -> usize {
+        let parent = self.parent[index];
+        if parent == index {
+            return index;
+        }
+        let root = self.find(parent);
+        self.parent[index] = root;
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        // The earlier file stays the root, so areas are named and ordered by their first file
+        if a != b {
+            self.parent[a.max(b)] = a.min(b);
+        }
+    }
+}
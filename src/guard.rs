@@ -0,0 +1,85 @@
+use regex::Regex;
+
+// Extensions that make a word a file name even without a directory
+const FILE_EXTENSIONS: &[&str] = &[
+    "rs", "py", "js", "jsx", "mjs", "ts", "tsx", "go", "java", "kt", "rb", "php", "c", "h", "cc", "cpp", "hpp", "cs",
+    "swift", "scala", "vue", "sh", "sql", "md", "yml", "yaml", "json", "toml", "xml", "html", "css", "scss", "proto",
+    "tf", "gradle", "lock",
+];
+
+// Names with a file extension that are products, not files of the diff
+const PRODUCT_NAMES: &[&str] = &["node.js", "deno.js", "vue.js", "next.js", "nuxt.js", "express.js", "d3.js", "three.js"];
+
+// Identifiers shorter than this are too likely to match anything to be worth checking
+const MIN_IDENTIFIER_LENGTH: usize = 3;
+
+// Told to the model when it regenerates a comment whose draft mentioned things the diff doesn't have
+pub fn instructions(mentions: &[String]) -> String {
+    format!(
+        "A draft of this comment mentioned {}, which the diff doesn't contain. Only mention files, functions and \
+         version numbers that appear in the diff, and don't describe changes it doesn't show.",
+        mentions.join(", ")
+    )
+}
+
+// File names, function names and version numbers the comment mentions that the diff doesn't contain,
+// in the order they first appear
+pub fn unverified(comment: &str, diff: &str) -> Vec<String> {
+    let code_span = Regex::new(r"`([^`\n]+)`").unwrap();
+    let identifier = Regex::new(r"^[A-Za-z_][\w]*(?:(?:::|\.|->)[A-Za-z_]\w*)*(?:\(\))?$").unwrap();
+    let path = Regex::new(r"(?:[\w.-]+/)*[\w-]+(?:\.[\w-]+)*\.([A-Za-z][A-Za-z0-9]{0,7})\b").unwrap();
+    let call = Regex::new(r"\b([A-Za-z_]\w*)\(\)").unwrap();
+    let version = Regex::new(r"\bv?(\d+\.\d+\.\d+(?:-[\w.]+)?)\b|\bv(\d+\.\d+)\b").unwrap();
+
+    let mut mentions: Vec<String> = Vec::new();
+    let mut check = |mention: &str, found: bool| {
+        if !found && !mentions.iter().any(|seen| seen == mention) {
+            mentions.push(mention.to_string());
+        }
+    };
+
+    for caps in code_span.captures_iter(comment) {
+        let span = caps[1].trim();
+        if is_file(span, &path) {
+            check(span, diff.contains(span));
+        } else if identifier.is_match(span) {
+            // The name itself, e.g. `login` of `auth::login()`
+            let name = span.trim_end_matches("()").rsplit([':', '.', '>']).next().unwrap_or(span);
+            if name.len() >= MIN_IDENTIFIER_LENGTH {
+                check(span, contains_word(diff, name));
+            }
+        }
+    }
+
+    // Prose outside code spans, where only clear file names, calls and versions count
+    let prose = code_span.replace_all(comment, " ");
+    for word in prose.split_whitespace() {
+        let word = word.trim_matches(|char: char| "()[]{}<>,;:!?\"'*".contains(char)).trim_end_matches('.');
+        if !word.contains("://") && !PRODUCT_NAMES.contains(&word.to_lowercase().as_str()) && is_file(word, &path) {
+            check(word, diff.contains(word));
+        }
+    }
+    for caps in call.captures_iter(&prose) {
+        if caps[1].len() >= MIN_IDENTIFIER_LENGTH {
+            check(&caps[0], contains_word(diff, &caps[1]));
+        }
+    }
+    for caps in version.captures_iter(&prose) {
+        let number = caps.get(1).or(caps.get(2)).map_or("", |number| number.as_str());
+        check(&caps[0], diff.contains(number));
+    }
+
+    mentions
+}
+
+// A path with a directory, or a file name with a known extension
+fn is_file(word: &str, path: &Regex) -> bool {
+    let Some(caps) = path.captures(word).filter(|caps| caps[0].len() == word.len()) else {
+        return false;
+    };
+    (word.contains('/') && !word.starts_with('/')) || FILE_EXTENSIONS.contains(&caps[1].to_lowercase().as_str())
+}
+
+fn contains_word(text: &str, word: &str) -> bool {
+    Regex::new(&format!(r"\b{}\b", regex::escape(word))).unwrap().is_match(text)
+}
@@ -0,0 +1,213 @@
+use anyhow::{Context, Result};
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{git, models, token_usage, ModelSettings};
+
+#[derive(Args, Clone)]
+pub struct HistoryArgs {
+    /// Only runs whose repository contains this text
+    #[arg(long, value_name = "TEXT")]
+    repo: Option<String>,
+
+    /// Only runs whose model contains this text
+    #[arg(long, value_name = "TEXT")]
+    model: Option<String>,
+
+    /// Only runs on or after this date (YYYY-MM-DD or YYYY-MM)
+    #[arg(long, value_name = "DATE")]
+    since: Option<String>,
+
+    /// Only runs before this date (YYYY-MM-DD or YYYY-MM)
+    #[arg(long, value_name = "DATE")]
+    until: Option<String>,
+
+    /// Show totals per month and repository instead of individual runs
+    #[arg(long)]
+    monthly: bool,
+}
+
+// One line of history.jsonl
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    // UTC, e.g. 2025-03-14T09:26:53Z
+    timestamp: String,
+    repo: String,
+    range: String,
+    provider: String,
+    model: String,
+    input_tokens: usize,
+    output_tokens: usize,
+    // Part of output_tokens spent on reasoning; missing in entries written before --reasoning
+    #[serde(default)]
+    reasoning_tokens: usize,
+    cost_usd: Option<f64>,
+    output: Option<String>,
+}
+
+fn history_path() -> Result<PathBuf> {
+    let dir = dirs::data_dir().context("Could not find data directory")?;
+    Ok(dir.join("mr-comment").join("history.jsonl"))
+}
+
+// Append the run with the tokens the provider reported; only called when history is enabled
+pub fn record(settings: &ModelSettings, range: &str, output: Option<String>) -> Result<()> {
+    let (input_tokens, output_tokens, reasoning_tokens) = token_usage();
+    let entry = Entry {
+        timestamp: utc_timestamp(SystemTime::now()),
+        repo: repository(),
+        range: range.to_string(),
+        provider: settings
+            .provider
+            .to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default(),
+        model: settings.model.clone(),
+        input_tokens,
+        output_tokens,
+        reasoning_tokens,
+        cost_usd: models::cost(&settings.model, input_tokens, output_tokens),
+        output,
+    };
+
+    let path = history_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create data directory: {}", dir.display()))?;
+    }
+    let line = serde_json::to_string(&entry).context("Failed to serialize history entry")?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open history: {}", path.display()))?;
+    writeln!(file, "{}", line).with_context(|| format!("Failed to write history: {}", path.display()))
+}
+
+// Print the matching runs (or monthly totals) with their total cost
+pub fn run(args: &HistoryArgs) -> Result<()> {
+    let path = history_path()?;
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(_) => {
+            println!("No history recorded yet. Set \"history\": true in the config to keep one.");
+            return Ok(());
+        }
+    };
+
+    let entries: Vec<Entry> = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .filter(|entry: &Entry| {
+            args.repo.as_ref().is_none_or(|repo| entry.repo.contains(repo.as_str()))
+                && args.model.as_ref().is_none_or(|model| entry.model.contains(model.as_str()))
+                && args.since.as_ref().is_none_or(|since| entry.timestamp.as_str() >= since.as_str())
+                && args.until.as_ref().is_none_or(|until| entry.timestamp.as_str() < until.as_str())
+        })
+        .collect();
+
+    if args.monthly {
+        // (month, repo) -> (runs, input tokens, output tokens, cost)
+        let mut totals: BTreeMap<(&str, &str), (usize, usize, usize, f64)> = BTreeMap::new();
+        for entry in &entries {
+            let total = totals.entry((&entry.timestamp[..7], &entry.repo)).or_default();
+            total.0 += 1;
+            total.1 += entry.input_tokens;
+            total.2 += entry.output_tokens;
+            total.3 += entry.cost_usd.unwrap_or(0.0);
+        }
+        for ((month, repo), (runs, input, output, cost)) in totals {
+            println!(
+                "{}  {}  {} run{}, {} in / {} out tokens, ${:.4}",
+                month,
+                repo,
+                runs,
+                if runs == 1 { "" } else { "s" },
+                input,
+                output,
+                cost
+            );
+        }
+    } else {
+        for entry in &entries {
+            let cost = entry
+                .cost_usd
+                .map_or("unknown cost".to_string(), |cost| format!("${:.4}", cost));
+            let reasoning = match entry.reasoning_tokens {
+                0 => String::new(),
+                tokens => format!(" ({} reasoning)", tokens),
+            };
+            println!(
+                "{}  {}  {}  {}  {} in / {} out{} tokens, {}{}",
+                entry.timestamp,
+                entry.repo,
+                entry.range,
+                entry.model,
+                entry.input_tokens,
+                entry.output_tokens,
+                reasoning,
+                cost,
+                entry
+                    .output
+                    .as_ref()
+                    .map(|output| format!("  -> {}", output))
+                    .unwrap_or_default()
+            );
+        }
+    }
+
+    let total = entries.iter().filter_map(|entry| entry.cost_usd).fold(0.0, |sum, cost| sum + cost);
+    println!(
+        "Total: {} run{}, ${:.4}",
+        entries.len(),
+        if entries.len() == 1 { "" } else { "s" },
+        total
+    );
+    Ok(())
+}
+
+// The origin remote identifies a project across clones; fall back to the checkout path
+fn repository() -> String {
+    let git = |args: &[&str]| {
+        let output = git::command().args(args).output().ok()?;
+        let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (output.status.success() && !text.is_empty()).then_some(text)
+    };
+
+    git(&["remote", "get-url", "origin"])
+        .or_else(|| git(&["rev-parse", "--show-toplevel"]))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+// RFC 3339 UTC timestamp without pulling in a date library
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
@@ -0,0 +1,91 @@
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+
+use crate::git;
+
+// Days of history churn is measured over, unless "hotspot_days" is set
+pub const DEFAULT_DAYS: u32 = 90;
+
+// Commits read at most, so a busy repository's log stays quick to go through
+const MAX_COMMITS: &str = "1000";
+
+// Hotspots listed at most
+const MAX_HOTSPOTS: usize = 5;
+
+// A directory needs this many commits to be a hotspot by rank, so a quiet repository has none
+const MIN_COMMITS: usize = 5;
+
+// Fix commits that make a directory a hotspot whatever its rank
+const MIN_FIXES: usize = 3;
+
+#[derive(Default)]
+struct Churn {
+    commits: usize,
+    fixes: usize,
+    lines: usize,
+}
+
+// Directories of the changed files that changed the most, or needed the most fixes, in the history of
+// the last days up to base, one line each with its numbers: those in the top tenth of all directories by
+// commits (the top 3 in small repositories), and any with MIN_FIXES fix commits. Only the local history
+// is read.
+pub fn find(base: &str, days: u32, changed_files: &[String]) -> Vec<String> {
+    let since = format!("--since={}.days.ago", days);
+    // Each commit is its subject after a record separator, then the NUL-terminated numstat of its files
+    let args = ["log", "--no-merges", "--no-renames", "--numstat", "-z", "--format=%x1e%s", "-n", MAX_COMMITS, &since, base];
+    let Some(log) = git::output(&args) else {
+        return Vec::new();
+    };
+    let fix = Regex::new(r"(?i)\b(fix(es|ed)?|bug|hotfix|revert|regression)\b").unwrap();
+
+    let mut churn: HashMap<String, Churn> = HashMap::new();
+    for commit in log.split('\x1e') {
+        let (subject, files) = commit.split_once('\0').unwrap_or((commit, ""));
+        let is_fix = fix.is_match(subject);
+        let mut touched = HashSet::new();
+        for (path, lines) in git::parse_numstat(files.trim_start_matches('\n')) {
+            let dir = directory(&path).to_string();
+            let entry = churn.entry(dir.clone()).or_default();
+            // Binary files have no line counts
+            entry.lines += lines.map_or(0, |(added, deleted)| added + deleted);
+            if touched.insert(dir) {
+                entry.commits += 1;
+                entry.fixes += usize::from(is_fix);
+            }
+        }
+    }
+
+    let mut ranked: Vec<(String, Churn)> = churn.into_iter().collect();
+    ranked.sort_by(|(a_dir, a), (b_dir, b)| b.commits.cmp(&a.commits).then(a_dir.cmp(b_dir)));
+    let top = ranked.len().div_ceil(10).max(3);
+    let changed: HashSet<&str> = changed_files.iter().map(|path| directory(path)).collect();
+    ranked
+        .iter()
+        .enumerate()
+        .filter(|(rank, (dir, churn))| {
+            changed.contains(dir.as_str()) && ((*rank < top && churn.commits >= MIN_COMMITS) || churn.fixes >= MIN_FIXES)
+        })
+        .take(MAX_HOTSPOTS)
+        .map(|(rank, (dir, churn))| {
+            let fixes = match churn.fixes {
+                0 => String::new(),
+                1 => " (1 fix)".to_string(),
+                fixes => format!(" ({} fixes)", fixes),
+            };
+            format!(
+                "{}: {} commits{} and {} changed lines in the last {} days, #{} of {} directories by commits",
+                dir,
+                churn.commits,
+                fixes,
+                churn.lines,
+                days,
+                rank + 1,
+                ranked.len()
+            )
+        })
+        .collect()
+}
+
+fn directory(path: &str) -> &str {
+    path.rsplit_once('/').map_or("(root)", |(dir, _)| dir)
+}
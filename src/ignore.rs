@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::diff;
+
+// Repository file with paths to leave out of the diff, one gitignore-style pattern per line
+pub const IGNORE_FILE: &str = ".mrcommentignore";
+
+// "*" and "?" stop at "/", "**" crosses directories
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+pub struct Rule {
+    pattern: Pattern,
+    negated: bool,
+    // Contains a "/" other than a trailing one, so it matches from the repository root
+    anchored: bool,
+    // Ends with "/", so it only matches directories
+    dir_only: bool,
+}
+
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    // Patterns from the ignore file in the repository root (if any), then the --exclude ones, so a
+    // command line "!pattern" can bring back a file the repository excludes
+    pub fn load(root: Option<&Path>, excludes: &[String]) -> Result<Self> {
+        let mut rules = Vec::new();
+        if let Some(root) = root {
+            let path = root.join(IGNORE_FILE);
+            match fs::read_to_string(&path) {
+                Ok(content) => {
+                    for line in content.lines() {
+                        let line = line.trim_end();
+                        if line.is_empty() || line.starts_with('#') {
+                            continue;
+                        }
+                        rules.push(parse(line).with_context(|| format!("Invalid pattern in {}", path.display()))?);
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+            }
+        }
+        for exclude in excludes {
+            rules.push(parse(exclude).context("Invalid --exclude pattern")?);
+        }
+        Ok(IgnoreRules { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // Like gitignore, the last matching pattern decides
+    pub fn is_ignored(&self, path: &str) -> bool {
+        let mut ignored = false;
+        for rule in self.rules.iter().filter(|rule| rule.matches(path)) {
+            ignored = !rule.negated;
+        }
+        ignored
+    }
+
+    // Drop the files of ignored paths (the preamble, e.g. patch series messages, stays)
+    pub fn apply(&self, diff: &str) -> String {
+        if self.is_empty() {
+            return diff.to_string();
+        }
+        let (preamble, files) = diff::split_files(diff);
+
+        let mut lines = preamble;
+        for file in files.iter().filter(|file| !self.is_ignored(&file.path)) {
+            lines.extend(file.lines.iter());
+        }
+        lines.join("\n")
+    }
+}
+
+impl Rule {
+    // Whether the pattern matches the path or a directory it is in, so a directory pattern covers everything in it
+    pub fn matches(&self, path: &str) -> bool {
+        let components: Vec<&str> = path.split('/').collect();
+        (1..=components.len()).any(|depth| {
+            if self.dir_only && depth == components.len() {
+                return false;
+            }
+            if self.anchored {
+                self.pattern.matches_with(&components[..depth].join("/"), MATCH_OPTIONS)
+            } else {
+                self.pattern.matches_with(components[depth - 1], MATCH_OPTIONS)
+            }
+        })
+    }
+}
+
+// A gitignore-style pattern, also used for CODEOWNERS paths
+pub fn parse(line: &str) -> Result<Rule> {
+    let (negated, pattern) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    // "\#" and "\!" escape a leading "#" or "!"
+    let pattern = pattern.strip_prefix('\\').filter(|rest| rest.starts_with(['#', '!'])).unwrap_or(pattern);
+    let dir_only = pattern.ends_with('/');
+    let pattern = pattern.trim_end_matches('/');
+    let anchored = pattern.contains('/');
+    let pattern = pattern.trim_start_matches('/');
+
+    Ok(Rule {
+        pattern: Pattern::new(pattern).with_context(|| format!("Invalid glob: {}", line))?,
+        negated,
+        anchored,
+        dir_only,
+    })
+}
@@ -0,0 +1,176 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+
+use crate::{
+    build_prompt, collect_diff, detect_git_host, diff_budget, generate_mr_comment, get_config_path, git,
+    host_terms, keyring_entry, resolve_settings, ApiProvider, Cli, Config, GitHost,
+};
+
+// Interactive setup: detect the host, pick provider/model/key, write the config and try it out
+pub fn run(cli: &Cli) -> Result<()> {
+    let config_path = get_config_path()?;
+    let mut config = match fs::read_to_string(&config_path) {
+        Ok(content) => match serde_json::from_str::<Value>(&content) {
+            Ok(Value::Object(map)) => map,
+            _ => anyhow::bail!("Existing config is not a JSON object: {}", config_path.display()),
+        },
+        Err(_) => Map::new(),
+    };
+
+    let host = detect_git_host().unwrap_or(GitHost::Unknown);
+    match host {
+        GitHost::Unknown => println!("No GitHub or GitLab origin remote detected, using generic MR/PR wording."),
+        _ => println!("Detected {} remote.", host_terms(host).1),
+    }
+
+    let default_provider = config
+        .get("provider")
+        .and_then(Value::as_str)
+        .unwrap_or("claude")
+        .to_string();
+    let provider = loop {
+        let answer = ask("Provider (claude/openai)", &default_provider)?;
+        match ApiProvider::from_str(&answer, true) {
+            Ok(provider) => break provider,
+            Err(_) => println!("Please enter 'claude' or 'openai'."),
+        }
+    };
+    let (name, default_model) = match provider {
+        ApiProvider::OpenAi => ("openai", "gpt-4-turbo"),
+        ApiProvider::Claude => ("claude", "claude-3-7-sonnet-20250219"),
+    };
+
+    let model_key = format!("{}_model", name);
+    let current_model = config
+        .get(&model_key)
+        .and_then(Value::as_str)
+        .unwrap_or(default_model)
+        .to_string();
+    let model = ask("Model", &current_model)?;
+
+    let api_key = ask_secret(&format!("{} API key (leave empty to keep the current one): ", name))?;
+    let api_key = api_key.trim();
+
+    if !api_key.is_empty() {
+        match keyring_entry(&provider).and_then(|entry| entry.set_password(api_key)) {
+            Ok(()) => {
+                println!("API key stored in the system keyring.");
+                // A plain-text key in the config would take precedence over the keyring
+                config.remove(&format!("{}_api_key", name));
+            }
+            Err(err) => {
+                println!("Could not store the key in the system keyring: {}", err);
+                if ask("Store it in the config file instead? (y/n)", "n")?.eq_ignore_ascii_case("y") {
+                    config.insert(format!("{}_api_key", name), Value::String(api_key.to_string()));
+                }
+            }
+        }
+    }
+
+    config.insert("provider".to_string(), Value::String(name.to_string()));
+    config.insert(model_key, Value::String(model));
+
+    if let Some(dir) = config_path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create config directory: {}", dir.display()))?;
+    }
+    let content = serde_json::to_string_pretty(&Value::Object(config))
+        .context("Failed to serialize config")?;
+    fs::write(&config_path, content + "\n")
+        .with_context(|| format!("Failed to write to file: {}", config_path.display()))?;
+    println!("Config written to {}", config_path.display());
+
+    test_generation(cli, api_key)
+}
+
+// Generate a comment for the smallest recent commit to check the setup end to end
+fn test_generation(cli: &Cli, api_key: &str) -> Result<()> {
+    let Some((commit, subject)) = smallest_recent_commit()? else {
+        println!("No commits found to test with; run mr-comment in a repository to try it out.");
+        return Ok(());
+    };
+
+    println!("\nTesting with commit {} ({})...\n", &commit[..commit.len().min(8)], subject);
+
+    let test_cli = Cli {
+        commit: Some(commit),
+        file: None,
+        stash: None,
+        provider: None,
+        model: None,
+        api_key: (!api_key.is_empty()).then(|| api_key.to_string()).or_else(|| cli.api_key.clone()),
+        command: None,
+        ..cli.clone()
+    };
+
+    let config = Config::load()?;
+    let settings = resolve_settings(&test_cli, &config)?;
+    let (diff, changed_files) = collect_diff(&test_cli, &config)?;
+    let prompt = build_prompt(&test_cli, &config, &settings)?.for_diff(&test_cli, &diff, &changed_files)?;
+    let comment = generate_mr_comment(&diff, &settings, &prompt, &diff_budget(&test_cli, &config))
+        .context("Test generation failed")?
+        .text;
+
+    println!("{}\n\nSetup complete.", comment);
+    Ok(())
+}
+
+// Of the last 20 non-merge commits, the one with the fewest changed lines
+fn smallest_recent_commit() -> Result<Option<(String, String)>> {
+    let output = git::command()
+        .args(["log", "-n", "20", "--no-merges", "--min-parents=1", "--numstat", "--format=>%H %s"])
+        .output()
+        .context("Failed to execute git log command")?;
+
+    // Not a repository, or no commits yet
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    let mut commits: Vec<(String, String, usize)> = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(header) = line.strip_prefix('>') {
+            let (hash, subject) = header.split_once(' ').unwrap_or((header, ""));
+            commits.push((hash.to_string(), subject.to_string(), 0));
+        } else if let Some(commit) = commits.last_mut() {
+            // "added<TAB>deleted<TAB>path", with "-" for binary files
+            let mut fields = line.split('\t');
+            let added = fields.next().and_then(|n| n.parse::<usize>().ok()).unwrap_or(0);
+            let deleted = fields.next().and_then(|n| n.parse::<usize>().ok()).unwrap_or(0);
+            commit.2 += added + deleted;
+        }
+    }
+
+    Ok(commits
+        .into_iter()
+        .filter(|(_, _, lines)| *lines > 0)
+        .min_by_key(|(_, _, lines)| *lines)
+        .map(|(hash, subject, _)| (hash, subject)))
+}
+
+fn ask(question: &str, default: &str) -> Result<String> {
+    print!("{} [{}]: ", question, default);
+    io::stdout().flush().context("Failed to write prompt")?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).context("Failed to read answer")?;
+
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_string())
+}
+
+// Read the key without echo on a terminal; piped input (e.g. provisioning scripts) is read as a line
+fn ask_secret(prompt: &str) -> Result<String> {
+    if io::stdin().is_terminal() {
+        return rpassword::prompt_password(prompt).context("Failed to read API key");
+    }
+
+    print!("{}", prompt);
+    io::stdout().flush().context("Failed to write prompt")?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).context("Failed to read API key")?;
+    Ok(answer)
+}
@@ -0,0 +1,147 @@
+use anyhow::{Context, Result};
+use clap::{CommandFactory, FromArgMatches};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::env;
+use std::io::Read;
+
+use crate::{
+    build_prompt, comment_for, diff_source, finish_comment, finish_structured, generate_text, history, models,
+    normalize_scope, prepare_diff, read_diff, resolve_settings, restrict_diff, schema, token_usage, Cli, Config, Generated,
+};
+
+// One --stdin-json job; options are long flag names without "--" (either - or _), e.g.
+// { "length": "short", "max_diff_lines": 300, "risk": true, "exclude": ["*.lock"] }
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Job {
+    // Without a diff the working tree (or --commit etc.) is described
+    diff: Option<String>,
+    // Prompt template path or URL, in place of the configured prompt
+    template: Option<String>,
+    provider: Option<String>,
+    #[serde(default)]
+    options: Map<String, Value>,
+}
+
+// Read a job from stdin, generate the comment and print it as JSON: { markdown, comment, usage }.
+// Errors are printed as JSON by main.
+pub fn run(cli: &Cli, config: &Config) -> Result<()> {
+    if cli.command.is_some() {
+        anyhow::bail!("--stdin-json can't be combined with a subcommand");
+    }
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input).context("Failed to read the job from stdin")?;
+    let job: Job = serde_json::from_str(&input).context("Invalid job on stdin")?;
+
+    let mut args = options_to_args(&job.options)?;
+    if let Some(provider) = &job.provider {
+        args.push(format!("--provider={}", provider));
+    }
+    // The options follow the command line and override flags given there
+    let matches = Cli::command()
+        .args_override_self(true)
+        .try_get_matches_from(env::args().chain(args))
+        .map_err(|err| {
+            let message = err.render().to_string();
+            let message = message.split("\n\nUsage:").next().unwrap_or_default();
+            anyhow::anyhow!("Invalid job options: {}", message.trim_start_matches("error: ").trim())
+        })?;
+    let mut cli = Cli::from_arg_matches(&matches)?;
+    normalize_scope(&mut cli);
+    if let Some(flag) = unsupported_flag(&cli) {
+        anyhow::bail!("{} is not available with --stdin-json", flag);
+    }
+    if job.diff.is_some() {
+        // A submitted diff is filtered like a git diff, without a commit range to check
+        cli.commit = None;
+        cli.file = None;
+        cli.stash = None;
+    }
+
+    let mut config = config.clone();
+    if let Some(template) = &job.template {
+        config.template = Some(template.clone());
+        config.sections = None;
+        config.mr_template = None;
+    }
+
+    let settings = resolve_settings(&cli, &config)?;
+    let source = if job.diff.is_some() { "stdin".to_string() } else { diff_source(&cli) };
+    let raw_diff = match job.diff {
+        Some(diff) => restrict_diff(&cli, diff)?,
+        None => read_diff(&cli, &config)?,
+    };
+
+    let prepared = prepare_diff(&cli, &config, raw_diff)?;
+    let prompt = build_prompt(&cli, &config, &settings)?.for_diff(&cli, &prepared.diff, &prepared.changed_files)?;
+    // The model that answered, which is the fallback model when the configured one ran out of time
+    let mut model = settings.model.clone();
+    let comment = comment_for(&cli, &config, &settings, &prompt, &prepared, false, |prompt, budget| {
+        let reply = generate_text(&cli, &prepared.diff, &settings, prompt, budget)?;
+        model = reply.model;
+        Ok(Generated::Text(reply.text))
+    })?
+    .into_markdown();
+
+    let structured = finish_structured(&cli, &config, schema::MrComment::from_markdown(&comment), &prepared)?;
+    let markdown = finish_comment(&cli, &config, comment, &prepared)?;
+    let (input_tokens, output_tokens, reasoning_tokens) = token_usage();
+    let result = json!({
+        "markdown": markdown,
+        "comment": structured,
+        "model": model,
+        "usage": {
+            "input_tokens": input_tokens,
+            "output_tokens": output_tokens,
+            "reasoning_tokens": reasoning_tokens,
+            "cost_usd": models::cost(&model, input_tokens, output_tokens),
+        }
+    });
+    println!("{}", serde_json::to_string_pretty(&result)?);
+
+    if config.history.unwrap_or(false) {
+        history::record(&settings, &source, None)?;
+    }
+    Ok(())
+}
+
+// { "max_diff_lines": 300, "risk": true, "exclude": ["a", "b"], "merge_base": false } becomes
+// ["--max-diff-lines=300", "--risk", "--exclude=a", "--exclude=b"]
+fn options_to_args(options: &Map<String, Value>) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    for (name, value) in options {
+        let flag = format!("--{}", name.replace('_', "-"));
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                Value::Bool(true) => args.push(flag.clone()),
+                Value::Bool(false) | Value::Null => {}
+                Value::String(text) => args.push(format!("{}={}", flag, text)),
+                Value::Number(number) => args.push(format!("{}={}", flag, number)),
+                _ => anyhow::bail!("Invalid value for option \"{}\": expected a string, number, boolean or list", name),
+            }
+        }
+    }
+    Ok(args)
+}
+
+// Flags that write somewhere other than stdout or keep running, which a one-shot worker can't use
+fn unsupported_flag(cli: &Cli) -> Option<&'static str> {
+    [
+        ("--output", cli.output.is_some()),
+        ("--split-output", cli.split_output.is_some()),
+        ("--clipboard", cli.clipboard),
+        ("--post", cli.post),
+        ("--webhook", cli.webhook.is_some()),
+        ("--watch", cli.watch),
+        ("--debug", cli.debug),
+        ("--regenerate-section", cli.regenerate_section.is_some()),
+    ]
+    .into_iter()
+    .find(|(_, used)| *used)
+    .map(|(flag, _)| flag)
+}
@@ -0,0 +1,136 @@
+use regex::Regex;
+use std::collections::HashMap;
+
+use crate::diff::{self, FileDiff};
+use crate::risk;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Feature,
+    Bugfix,
+    Refactor,
+    Docs,
+    Ci,
+    Test,
+}
+
+impl Kind {
+    // Key of the kind in the "labels" config, and its label when the config doesn't map it
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Feature => "feature",
+            Kind::Bugfix => "bugfix",
+            Kind::Refactor => "refactor",
+            Kind::Docs => "docs",
+            Kind::Ci => "ci",
+            Kind::Test => "test",
+        }
+    }
+
+    // Whether the file is of this kind whatever its change; only docs, CI and tests are
+    fn covers(self, path: &str) -> bool {
+        match self {
+            Kind::Docs => is_docs(path),
+            Kind::Ci => risk::is_ci(path),
+            Kind::Test => risk::is_test(path),
+            Kind::Feature | Kind::Bugfix | Kind::Refactor => false,
+        }
+    }
+}
+
+// Labels for the change, renamed by the "labels" config (e.g. "bugfix" to "type::bug")
+pub fn suggest(raw_diff: &str, subjects: &[String], names: Option<&HashMap<String, String>>) -> Vec<String> {
+    classify(raw_diff, subjects)
+        .into_iter()
+        .map(|kind| match names.and_then(|names| names.get(kind.name())) {
+            Some(label) => label.clone(),
+            None => kind.name().to_string(),
+        })
+        .filter(|label| !label.is_empty())
+        .collect()
+}
+
+// Kinds of the change, from its commit subjects and the files it touches. Code changes get one of
+// feature, bugfix or refactor; docs and tests only count on their own or when a commit is about
+// them, CI changes always do.
+pub fn classify(raw_diff: &str, subjects: &[String]) -> Vec<Kind> {
+    let (_, files) = diff::split_files(raw_diff);
+    let stated: Vec<Kind> = subjects.iter().filter_map(|subject| subject_kind(subject)).collect();
+    let code: Vec<&FileDiff> = files
+        .iter()
+        .filter(|file| ![Kind::Docs, Kind::Ci, Kind::Test].iter().any(|kind| kind.covers(&file.path)))
+        .collect();
+
+    let mut kinds = Vec::new();
+    if !code.is_empty() {
+        kinds.extend(code_kind(&code, &stated));
+    }
+    for kind in [Kind::Docs, Kind::Ci, Kind::Test] {
+        let changed = files.iter().any(|file| kind.covers(&file.path));
+        if changed && (code.is_empty() || kind == Kind::Ci || stated.contains(&kind)) {
+            kinds.push(kind);
+        }
+    }
+    kinds
+}
+
+// The kind most commits state, ties going to bugfix, then feature; without a stated kind, new
+// files make a feature and pure renames a refactor
+fn code_kind(code: &[&FileDiff], stated: &[Kind]) -> Option<Kind> {
+    let votes = |kind: &Kind| stated.iter().filter(|stated| *stated == kind).count();
+    let winner = [Kind::Bugfix, Kind::Feature, Kind::Refactor]
+        .into_iter()
+        .filter(|kind| votes(kind) > 0)
+        .rev()
+        .max_by_key(votes);
+    if winner.is_some() {
+        return winner;
+    }
+
+    if code.iter().any(|file| file.is_new && file.moved_from.is_none()) {
+        Some(Kind::Feature)
+    } else if code.iter().all(|file| file.moved_from.is_some() && !file.is_copy) {
+        Some(Kind::Refactor)
+    } else {
+        None
+    }
+}
+
+// Conventional commit type ("fix(api): ..."), else the first keyword the subject has
+pub fn subject_kind(subject: &str) -> Option<Kind> {
+    let conventional = Regex::new(r"^(\w+)(?:\([^)]*\))?!?:").unwrap();
+    if let Some(caps) = conventional.captures(subject) {
+        let kind = match caps[1].to_lowercase().as_str() {
+            "feat" | "feature" => Some(Kind::Feature),
+            "fix" | "bugfix" | "hotfix" => Some(Kind::Bugfix),
+            "refactor" | "perf" | "style" => Some(Kind::Refactor),
+            "docs" | "doc" => Some(Kind::Docs),
+            "ci" | "build" => Some(Kind::Ci),
+            "test" | "tests" => Some(Kind::Test),
+            _ => None,
+        };
+        if kind.is_some() {
+            return kind;
+        }
+    }
+
+    let keywords = [
+        (Kind::Bugfix, r"fix(es|ed)?|bug|bugs|hotfix|regression|crash(es)?|broken|typo"),
+        (Kind::Refactor, r"refactor\w*|renamed?|renames|move[sd]?|clean(s|ed)? ?up|simplif\w+|restructur\w+|extract\w*|tidy"),
+        (Kind::Feature, r"add(s|ed)?|implement\w*|introduc\w+|support\w*|new|allow\w*|enable\w*"),
+    ];
+    keywords.into_iter().find_map(|(kind, words)| {
+        let pattern = Regex::new(&format!(r"(?i)\b({})\b", words)).unwrap();
+        pattern.is_match(subject).then_some(kind)
+    })
+}
+
+fn is_docs(path: &str) -> bool {
+    let lower = path.to_lowercase();
+    let name = lower.rsplit('/').next().unwrap_or(&lower);
+    let extension = name.rsplit_once('.').map(|(_, extension)| extension).unwrap_or_default();
+    let in_docs_dir = lower.split('/').rev().skip(1).any(|dir| ["doc", "docs", "documentation"].contains(&dir));
+    in_docs_dir
+        || ["md", "markdown", "rst", "adoc", "asciidoc"].contains(&extension)
+        || ["readme", "changelog", "contributing"].iter().any(|prefix| name.starts_with(prefix))
+}
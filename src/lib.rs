@@ -0,0 +1,9 @@
+// Diff processing that needs neither git nor a provider: decoding, filtering and summarizing a diff
+// the binary has read, and the Windows path handling around it. It is a library so the tests can run
+// it on fixture diffs.
+pub mod dependencies;
+pub mod diff;
+pub mod encoding;
+pub mod grouping;
+pub mod paths;
+pub mod word_diff;
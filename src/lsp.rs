@@ -0,0 +1,341 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+
+use crate::{
+    build_prompt, collect_diff, comment, complete_allowed, decode_diff_bytes, diff_budget, filter_git_diff,
+    forbidden, generate_mr_comment, git, injection, truncate_diff, untrusted, Cli, Config, ModelSettings,
+};
+
+const COMMAND_GENERATE: &str = "mr-comment.generate";
+const COMMAND_SHORTEN: &str = "mr-comment.shorten";
+const COMMAND_CONVENTIONALIZE: &str = "mr-comment.conventionalize";
+
+const CONVENTIONAL_TYPES: &[(&str, &str)] = &[
+    ("feat", "A new feature"),
+    ("fix", "A bug fix"),
+    ("docs", "Documentation only changes"),
+    ("style", "Formatting, no code change"),
+    ("refactor", "Code change that neither fixes a bug nor adds a feature"),
+    ("perf", "Performance improvement"),
+    ("test", "Adding or correcting tests"),
+    ("build", "Build system or dependency changes"),
+    ("ci", "CI configuration changes"),
+    ("chore", "Other changes that don't modify src or test files"),
+    ("revert", "Reverts a previous commit"),
+];
+
+const COMMIT_PROMPT: &str = "Write a git commit message for the staged diff. \
+Use an imperative subject line of at most 72 characters, then a blank line and a body wrapped at 72 columns \
+explaining what changed and why. Output only the commit message.";
+
+const SHORTEN_PROMPT: &str = "Shorten the following text while keeping its meaning, structure and any markdown. \
+Output only the rewritten text.";
+
+const CONVENTIONAL_PROMPT: &str = "Rewrite the following commit message to follow the Conventional Commits format \
+(`type(scope): subject`, types: feat, fix, docs, style, refactor, perf, test, build, ci, chore, revert). \
+Keep the body. Output only the rewritten message.";
+
+struct Server<'a> {
+    cli: &'a Cli,
+    config: &'a Config,
+    settings: &'a ModelSettings,
+    documents: HashMap<String, String>,
+    next_request_id: u64,
+}
+
+// Run the language server over stdin/stdout until the client sends `exit`
+pub fn run(cli: &Cli, config: &Config, settings: &ModelSettings) -> Result<()> {
+    let mut server = Server {
+        cli,
+        config,
+        settings,
+        documents: HashMap::new(),
+        next_request_id: 1,
+    };
+
+    let mut reader = BufReader::new(io::stdin().lock());
+    while let Some(message) = read_message(&mut reader)? {
+        let method = message["method"].as_str().unwrap_or_default().to_string();
+        if method == "exit" {
+            break;
+        }
+        server.handle(&method, &message)?;
+    }
+
+    Ok(())
+}
+
+fn read_message(reader: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut content_length = None;
+
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).context("Failed to read LSP header")? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            content_length = Some(value.trim().parse::<usize>().context("Invalid Content-Length header")?);
+        }
+    }
+
+    let length = content_length.context("LSP message without Content-Length header")?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).context("Failed to read LSP message body")?;
+
+    serde_json::from_slice(&body)
+        .map(Some)
+        .context("Failed to parse LSP message")
+}
+
+fn write_message(message: &Value) -> Result<()> {
+    let body = message.to_string();
+    let mut stdout = io::stdout().lock();
+    write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| stdout.flush())
+        .context("Failed to write LSP message")
+}
+
+// COMMIT_EDITMSG, MERGE_MSG and friends get commit-message behaviour, everything else MR descriptions
+fn is_commit_message(uri: &str) -> bool {
+    ["COMMIT_EDITMSG", "MERGE_MSG", "TAG_EDITMSG", "SQUASH_MSG"]
+        .iter()
+        .any(|name| uri.ends_with(name))
+}
+
+// Split a commit buffer into the editable message and git's trailing "#" comment block
+fn split_git_comments(text: &str) -> (String, String) {
+    let lines: Vec<&str> = text.lines().collect();
+    let split = lines
+        .iter()
+        .position(|line| line.starts_with('#'))
+        .unwrap_or(lines.len());
+    (
+        lines[..split].join("\n").trim_end().to_string(),
+        lines[split..].join("\n"),
+    )
+}
+
+impl Server<'_> {
+    fn handle(&mut self, method: &str, message: &Value) -> Result<()> {
+        let id = message.get("id").cloned();
+        let params = &message["params"];
+
+        // Responses to our own requests (workspace/applyEdit) carry no method
+        if method.is_empty() {
+            return Ok(());
+        }
+
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "completionProvider": { "triggerCharacters": [] },
+                    "codeActionProvider": true,
+                    "executeCommandProvider": {
+                        "commands": [COMMAND_GENERATE, COMMAND_SHORTEN, COMMAND_CONVENTIONALIZE]
+                    }
+                },
+                "serverInfo": { "name": "mr-comment", "version": env!("CARGO_PKG_VERSION") }
+            })),
+            "shutdown" => Ok(Value::Null),
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                if let (Some(uri), Some(text)) = (document["uri"].as_str(), document["text"].as_str()) {
+                    self.documents.insert(uri.to_string(), text.to_string());
+                }
+                return Ok(());
+            }
+            "textDocument/didChange" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                    self.documents.insert(uri.to_string(), text.to_string());
+                }
+                return Ok(());
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                self.documents.remove(uri);
+                return Ok(());
+            }
+            "textDocument/completion" => Ok(self.completion(params)),
+            "textDocument/codeAction" => Ok(self.code_actions(params)),
+            "workspace/executeCommand" => self.execute_command(params).map(|_| Value::Null),
+            _ if id.is_none() => return Ok(()),
+            _ => {
+                return write_message(&json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": -32601, "message": format!("Method not found: {}", method) }
+                }));
+            }
+        };
+
+        let Some(id) = id else {
+            return Ok(());
+        };
+
+        match result {
+            Ok(result) => write_message(&json!({ "jsonrpc": "2.0", "id": id, "result": result })),
+            Err(err) => {
+                let text = format!("mr-comment: {:#}", err);
+                write_message(&json!({
+                    "jsonrpc": "2.0",
+                    "method": "window/showMessage",
+                    "params": { "type": 1, "message": text }
+                }))?;
+                write_message(&json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": -32603, "message": text }
+                }))
+            }
+        }
+    }
+
+    // Conventional commit types on the subject line of commit buffers
+    fn completion(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        if !is_commit_message(uri) || params["position"]["line"].as_u64() != Some(0) {
+            return json!([]);
+        }
+
+        let items: Vec<Value> = CONVENTIONAL_TYPES
+            .iter()
+            .map(|(name, detail)| {
+                json!({
+                    "label": name,
+                    "kind": 14,
+                    "detail": detail,
+                    "insertText": format!("{}: ", name)
+                })
+            })
+            .collect();
+        json!(items)
+    }
+
+    fn code_actions(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+
+        let mut actions = vec![
+            (
+                if is_commit_message(uri) { "Generate commit message" } else { "Generate MR description" },
+                COMMAND_GENERATE,
+            ),
+            ("Shorten", COMMAND_SHORTEN),
+        ];
+        if is_commit_message(uri) {
+            actions.push(("Convert to Conventional Commit", COMMAND_CONVENTIONALIZE));
+        }
+
+        let actions: Vec<Value> = actions
+            .into_iter()
+            .map(|(title, command)| {
+                json!({
+                    "title": format!("mr-comment: {}", title),
+                    "kind": "refactor.rewrite",
+                    "command": { "title": title, "command": command, "arguments": [uri] }
+                })
+            })
+            .collect();
+        json!(actions)
+    }
+
+    fn execute_command(&mut self, params: &Value) -> Result<()> {
+        let command = params["command"].as_str().unwrap_or_default();
+        let uri = params["arguments"][0]
+            .as_str()
+            .context("Command requires the document URI as its argument")?
+            .to_string();
+        let text = self.documents.get(&uri).cloned().unwrap_or_default();
+        let (message, git_comments) = split_git_comments(&text);
+        let commit_buffer = is_commit_message(&uri);
+
+        let replacement = match command {
+            COMMAND_GENERATE if commit_buffer => self.generate_commit_message()?,
+            COMMAND_GENERATE => self.generate_description()?,
+            COMMAND_SHORTEN => complete_allowed(self.config, self.settings, SHORTEN_PROMPT, &message)?,
+            COMMAND_CONVENTIONALIZE => complete_allowed(self.config, self.settings, CONVENTIONAL_PROMPT, &message)?,
+            _ => anyhow::bail!("Unknown command: {}", command),
+        };
+
+        // Keep git's "#" help comments at the end of commit buffers
+        let new_text = if commit_buffer && !git_comments.is_empty() {
+            format!("{}\n\n{}\n", replacement.trim(), git_comments)
+        } else {
+            format!("{}\n", replacement.trim())
+        };
+
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        write_message(&json!({
+            "jsonrpc": "2.0",
+            "id": request_id,
+            "method": "workspace/applyEdit",
+            "params": {
+                "label": "mr-comment",
+                "edit": {
+                    "changes": {
+                        uri.clone(): [{
+                            "range": {
+                                "start": { "line": 0, "character": 0 },
+                                "end": { "line": text.lines().count() + 1, "character": 0 }
+                            },
+                            "newText": new_text
+                        }]
+                    }
+                }
+            }
+        }))?;
+        self.documents.insert(uri, new_text);
+
+        Ok(())
+    }
+
+    fn generate_commit_message(&self) -> Result<String> {
+        let output = git::command()
+            .args(["diff", "--cached", "-M", "-C"])
+            .output()
+            .context("Failed to execute git command")?;
+
+        if !output.status.success() {
+            return Err(git::error("diff", &output.stderr));
+        }
+
+        let staged = decode_diff_bytes(output.stdout);
+        if staged.trim().is_empty() {
+            anyhow::bail!("No staged changes to describe");
+        }
+
+        let diff = filter_git_diff(self.cli, self.config, &staged)?;
+        let (diff, _) = truncate_diff(&diff, &diff_budget(self.cli, self.config));
+        let system = format!("{}\n\n{}", COMMIT_PROMPT, injection::INSTRUCTIONS);
+        let user = format!("Staged diff:\n\n{}", untrusted(&diff, "the staged diff"));
+        complete_allowed(self.config, self.settings, &system, &user)
+    }
+
+    fn generate_description(&self) -> Result<String> {
+        let (diff, changed_files) = collect_diff(self.cli, self.config)?;
+        let prompt = build_prompt(self.cli, self.config, self.settings)?.for_diff(self.cli, &diff, &changed_files)?;
+        let budget = diff_budget(self.cli, self.config);
+        let description = forbidden::regenerate(self.config.forbidden.as_ref(), String::clone, |terms| {
+            let prompt = prompt.clone().with_forbidden(terms.to_vec());
+            generate_mr_comment(&diff, self.settings, &prompt, &budget).map(|reply| reply.text)
+        })?;
+        let description = match &self.config.forbidden {
+            Some(forbidden) => forbidden.rewrite(&description)?,
+            None => description,
+        };
+
+        match &self.config.terminology {
+            Some(terms) => comment::apply_terminology(&description, terms),
+            None => Ok(description),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use regex::Regex;
+
+use crate::git;
+
+// Default date format (strftime, as git --date=format: takes it); "date_format" in the config overrides it
+pub const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+// Who made the change and when, from the commits being described
+pub struct Metadata {
+    pub branch: Option<String>,
+    // Commit authors with their number of commits, most commits first
+    pub authors: Vec<(String, usize)>,
+    // Co-authored-by trailers of the commits (names only), without the authors
+    pub co_authors: Vec<String>,
+    // Dates of the oldest and newest commit
+    pub dates: Option<(String, String)>,
+    // Commit subjects, oldest first
+    pub subjects: Vec<String>,
+    // Breaking changes the commits declare, by a "!" after their type ("feat(api)!: ...") or a
+    // BREAKING CHANGE footer, oldest first
+    pub breaking: Vec<String>,
+}
+
+// Metadata for the commits in log_range, or for the uncommitted changes of the current user when it is None
+pub fn collect(revision: Option<&str>, log_range: Option<Vec<String>>, date_format: &str) -> Metadata {
+    let branch = git::output(&["rev-parse", "--abbrev-ref", revision.unwrap_or("HEAD")]).filter(|branch| branch != "HEAD");
+
+    let Some(range) = log_range else {
+        let authors = git::output(&["config", "user.name"]).map(|name| vec![(name, 0)]).unwrap_or_default();
+        return Metadata {
+            branch,
+            authors,
+            co_authors: Vec::new(),
+            dates: None,
+            subjects: Vec::new(),
+            breaking: Vec::new(),
+        };
+    };
+
+    // Fields are separated by 0x1f, commits by 0x1e and trailers by 0x1d
+    let format = "--format=%an%x1f%ad%x1f%s%x1f%(trailers:key=Co-authored-by,valueonly,separator=%x1d)%x1f%b%x1e";
+    let date = format!("--date=format:{}", date_format);
+    let mut args = vec!["log", format, &date];
+    args.extend(range.iter().map(String::as_str));
+    let log = git::output(&args).unwrap_or_default();
+
+    let email = Regex::new(r"\s*<[^>]*>\s*$").unwrap();
+    let breaking_type = Regex::new(r"^\w+(?:\([^)]*\))?!:").unwrap();
+    let breaking_footer = Regex::new(r"(?m)^BREAKING[ -]CHANGE:\s*(.+)$").unwrap();
+    let mut authors: Vec<(String, usize)> = Vec::new();
+    let mut co_authors: Vec<String> = Vec::new();
+    let mut dates = Vec::new();
+    let mut subjects = Vec::new();
+    let mut breaking = Vec::new();
+    for commit in log.split('\x1e').map(|commit| commit.trim()).filter(|commit| !commit.is_empty()) {
+        let mut fields = commit.split('\x1f');
+        let (Some(author), Some(date), Some(subject)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        subjects.push(subject.to_string());
+        match authors.iter_mut().find(|(name, _)| name == author) {
+            Some((_, count)) => *count += 1,
+            None => authors.push((author.to_string(), 1)),
+        }
+        dates.push(date.to_string());
+        for trailer in fields.next().unwrap_or_default().split('\x1d') {
+            let name = email.replace(trailer.trim(), "").to_string();
+            if !name.is_empty() && !co_authors.contains(&name) {
+                co_authors.push(name);
+            }
+        }
+
+        let body = fields.next().unwrap_or_default();
+        let mut declared: Vec<String> = breaking_footer
+            .captures_iter(body)
+            .map(|caps| caps[1].trim().to_string())
+            .collect();
+        if declared.is_empty() && breaking_type.is_match(subject) {
+            declared.push(subject.to_string());
+        }
+        // Commits come newest first; keep each one's footers in order
+        breaking.splice(0..0, declared);
+    }
+    authors.sort_by_key(|(_, commits)| std::cmp::Reverse(*commits));
+    co_authors.retain(|name| !authors.iter().any(|(author, _)| author == name));
+
+    // git log lists the newest commit first
+    subjects.reverse();
+    let dates = match (dates.last(), dates.first()) {
+        (Some(first), Some(last)) => Some((first.clone(), last.clone())),
+        _ => None,
+    };
+
+    Metadata {
+        branch,
+        authors,
+        co_authors,
+        dates,
+        subjects,
+        breaking,
+    }
+}
+
+impl Metadata {
+    // Lines given to the model ahead of the diff
+    pub fn prompt_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(branch) = &self.branch {
+            lines.push(format!("Branch: {}", branch));
+        }
+        if !self.authors.is_empty() {
+            let authors: Vec<String> = self
+                .authors
+                .iter()
+                .map(|(name, commits)| match commits {
+                    0 => format!("{} (uncommitted changes)", name),
+                    1 => format!("{} (1 commit)", name),
+                    _ => format!("{} ({} commits)", name, commits),
+                })
+                .collect();
+            lines.push(format!("Authors: {}", authors.join(", ")));
+        }
+        if !self.co_authors.is_empty() {
+            lines.push(format!("Co-authors: {}", self.co_authors.join(", ")));
+        }
+        if let Some(dates) = self.date_range() {
+            lines.push(format!("Dates: {}", dates));
+        }
+        lines
+    }
+
+    // Attribution footer crediting everyone who worked on the change
+    pub fn footer(&self) -> Option<String> {
+        let mut names: Vec<&str> = self.authors.iter().map(|(name, _)| name.as_str()).collect();
+        names.extend(self.co_authors.iter().map(String::as_str));
+        if names.is_empty() {
+            return None;
+        }
+
+        let mut footer = format!("Contributors: {}", names.join(", "));
+        if let Some(dates) = self.date_range() {
+            footer += &format!(" · {}", dates);
+        }
+        Some(footer)
+    }
+
+    fn date_range(&self) -> Option<String> {
+        let (first, last) = self.dates.as_ref()?;
+        Some(if first == last {
+            first.clone()
+        } else {
+            format!("{} – {}", first, last)
+        })
+    }
+}
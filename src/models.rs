@@ -0,0 +1,220 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use reqwest::blocking::{Client, RequestBuilder};
+use serde::Deserialize;
+
+use crate::{api_client, api_error, resolve_settings, warn, ApiProvider, Cli, Config, Length, ModelSettings, Reasoning};
+
+#[derive(Args, Clone)]
+pub struct ModelsArgs {
+    /// Provider whose models to list (default: --provider, the config's provider, or claude)
+    #[arg(long, value_enum)]
+    provider: Option<ApiProvider>,
+}
+
+// Response of the OpenAI and Claude model list endpoints
+#[derive(Deserialize)]
+pub struct ModelList {
+    pub data: Vec<ModelInfo>,
+}
+
+#[derive(Deserialize)]
+pub struct ModelInfo {
+    pub id: String,
+}
+
+// Models the providers have retired, matched by name prefix, with the model to use instead
+struct Retired {
+    prefix: &'static str,
+    replacement: &'static str,
+}
+
+const RETIRED: &[Retired] = &[
+    Retired { prefix: "claude-instant", replacement: "claude-3-5-haiku-latest" },
+    Retired { prefix: "claude-2", replacement: "claude-sonnet-4-0" },
+    Retired { prefix: "claude-3-sonnet-2024", replacement: "claude-sonnet-4-0" },
+    Retired { prefix: "claude-3-5-sonnet-2024", replacement: "claude-sonnet-4-0" },
+    Retired { prefix: "claude-3-opus-2024", replacement: "claude-opus-4-1" },
+    Retired { prefix: "gpt-4-32k", replacement: "gpt-4.1" },
+    Retired { prefix: "gpt-4-vision-preview", replacement: "gpt-4o" },
+    Retired { prefix: "gpt-4.5-preview", replacement: "gpt-4.1" },
+    Retired { prefix: "gpt-3.5-turbo-0301", replacement: "gpt-4o-mini" },
+    Retired { prefix: "gpt-3.5-turbo-0613", replacement: "gpt-4o-mini" },
+    Retired { prefix: "gpt-3.5-turbo-16k-0613", replacement: "gpt-4o-mini" },
+];
+
+// What a model takes and costs, matched by longest model-name prefix: tokens of its context window
+// (prompt and reply together), most tokens per reply, published prices in USD per million tokens, and
+// whether it accepts a system message (o1-mini and o1-preview don't)
+pub struct Capabilities {
+    prefix: &'static str,
+    pub context_window: usize,
+    pub max_output: usize,
+    input: f64,
+    output: f64,
+    pub system_role: bool,
+}
+
+const CAPABILITIES: &[Capabilities] = &[
+    Capabilities { prefix: "claude-opus-4", context_window: 200_000, max_output: 32_000, input: 15.0, output: 75.0, system_role: true },
+    Capabilities { prefix: "claude-sonnet-4", context_window: 200_000, max_output: 64_000, input: 3.0, output: 15.0, system_role: true },
+    Capabilities { prefix: "claude-3-7-sonnet", context_window: 200_000, max_output: 64_000, input: 3.0, output: 15.0, system_role: true },
+    Capabilities { prefix: "claude-3-5-sonnet", context_window: 200_000, max_output: 8_192, input: 3.0, output: 15.0, system_role: true },
+    Capabilities { prefix: "claude-3-5-haiku", context_window: 200_000, max_output: 8_192, input: 0.8, output: 4.0, system_role: true },
+    Capabilities { prefix: "claude-3-opus", context_window: 200_000, max_output: 4_096, input: 15.0, output: 75.0, system_role: true },
+    Capabilities { prefix: "claude-3-sonnet", context_window: 200_000, max_output: 4_096, input: 3.0, output: 15.0, system_role: true },
+    Capabilities { prefix: "claude-3-haiku", context_window: 200_000, max_output: 4_096, input: 0.25, output: 1.25, system_role: true },
+    Capabilities { prefix: "gpt-5", context_window: 400_000, max_output: 128_000, input: 1.25, output: 10.0, system_role: true },
+    Capabilities { prefix: "gpt-5-mini", context_window: 400_000, max_output: 128_000, input: 0.25, output: 2.0, system_role: true },
+    Capabilities { prefix: "gpt-5-nano", context_window: 400_000, max_output: 128_000, input: 0.05, output: 0.4, system_role: true },
+    Capabilities { prefix: "gpt-4.1", context_window: 1_047_576, max_output: 32_768, input: 2.0, output: 8.0, system_role: true },
+    Capabilities { prefix: "gpt-4.1-mini", context_window: 1_047_576, max_output: 32_768, input: 0.4, output: 1.6, system_role: true },
+    Capabilities { prefix: "gpt-4.1-nano", context_window: 1_047_576, max_output: 32_768, input: 0.1, output: 0.4, system_role: true },
+    Capabilities { prefix: "gpt-4o", context_window: 128_000, max_output: 16_384, input: 2.5, output: 10.0, system_role: true },
+    Capabilities { prefix: "gpt-4o-mini", context_window: 128_000, max_output: 16_384, input: 0.15, output: 0.6, system_role: true },
+    Capabilities { prefix: "gpt-4-turbo", context_window: 128_000, max_output: 4_096, input: 10.0, output: 30.0, system_role: true },
+    Capabilities { prefix: "gpt-4", context_window: 8_192, max_output: 8_192, input: 30.0, output: 60.0, system_role: true },
+    Capabilities { prefix: "gpt-3.5-turbo", context_window: 16_385, max_output: 4_096, input: 0.5, output: 1.5, system_role: true },
+    Capabilities { prefix: "o1", context_window: 200_000, max_output: 100_000, input: 15.0, output: 60.0, system_role: true },
+    Capabilities { prefix: "o1-mini", context_window: 128_000, max_output: 65_536, input: 1.1, output: 4.4, system_role: false },
+    Capabilities { prefix: "o1-preview", context_window: 128_000, max_output: 32_768, input: 15.0, output: 60.0, system_role: false },
+    Capabilities { prefix: "o3", context_window: 200_000, max_output: 100_000, input: 2.0, output: 8.0, system_role: true },
+    Capabilities { prefix: "o3-mini", context_window: 200_000, max_output: 100_000, input: 1.1, output: 4.4, system_role: true },
+    Capabilities { prefix: "o4-mini", context_window: 200_000, max_output: 100_000, input: 1.1, output: 4.4, system_role: true },
+];
+
+// Upper bound on reply length for each --length, used for cost estimates and as the request's max_tokens
+pub fn output_tokens(length: Length) -> usize {
+    match length {
+        Length::Short => 400,
+        Length::Medium => 4000,
+        Length::Long => 8000,
+    }
+}
+
+// Claude extended thinking budget for each --reasoning effort (the API minimum is 1024)
+pub fn thinking_budget(reasoning: Reasoning) -> usize {
+    match reasoning {
+        Reasoning::Low => 2048,
+        Reasoning::Medium => 8192,
+        Reasoning::High => 16384,
+    }
+}
+
+// OpenAI o-series and GPT-5 models, which reason before replying and only accept the default temperature
+pub fn is_reasoning_model(model: &str) -> bool {
+    ["o1", "o3", "o4", "gpt-5"].iter().any(|prefix| {
+        model.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with(['-', '.']))
+    })
+}
+
+// The model's entry in the table, or None for models this tool doesn't know
+pub fn capabilities(model: &str) -> Option<&'static Capabilities> {
+    CAPABILITIES
+        .iter()
+        .filter(|capabilities| model.starts_with(capabilities.prefix))
+        .max_by_key(|capabilities| capabilities.prefix.len())
+}
+
+// Cost in USD of the given token counts, or None for models without known pricing
+pub fn cost(model: &str, input_tokens: usize, output_tokens: usize) -> Option<f64> {
+    let pricing = capabilities(model)?;
+    Some((input_tokens as f64 * pricing.input + output_tokens as f64 * pricing.output) / 1_000_000.0)
+}
+
+// Models are assumed to take a system message unless known otherwise
+pub fn supports_system_role(model: &str) -> bool {
+    capabilities(model).is_none_or(|capabilities| capabilities.system_role)
+}
+
+// The model of the same provider with the smallest context window that is larger than the model's and fits
+// the tokens, to suggest instead
+pub fn larger_model(provider: &ApiProvider, model: &str, tokens: usize) -> Option<&'static str> {
+    let window = capabilities(model)?.context_window;
+    let claude = matches!(provider, ApiProvider::Claude);
+    CAPABILITIES
+        .iter()
+        .filter(|capabilities| {
+            capabilities.prefix.starts_with("claude") == claude
+                && capabilities.context_window > window
+                && capabilities.context_window >= tokens
+        })
+        .min_by_key(|capabilities| capabilities.context_window)
+        .map(|capabilities| capabilities.prefix)
+}
+
+// Model to use instead of a retired one, or None for models that still work as far as this tool knows
+pub fn retired(model: &str) -> Option<&'static str> {
+    RETIRED
+        .iter()
+        .find(|retired| model.starts_with(retired.prefix))
+        .map(|retired| retired.replacement)
+}
+
+// "/v1/chat/completions" and "/v1/messages" become "/v1/models"
+pub fn list_url(settings: &ModelSettings) -> String {
+    let endpoint = settings.endpoint.trim_end_matches('/');
+    let base = ["/chat/completions", "/messages"]
+        .iter()
+        .find_map(|suffix| endpoint.strip_suffix(suffix))
+        .unwrap_or(endpoint);
+    match settings.provider {
+        // Claude pages the list, 20 models by default
+        ApiProvider::Claude => format!("{}/models?limit=1000", base),
+        ApiProvider::OpenAi => format!("{}/models", base),
+    }
+}
+
+// Model list request with the same key and headers as a completion, which generates nothing
+pub fn list_request(client: &Client, settings: &ModelSettings) -> RequestBuilder {
+    let url = list_url(settings);
+    api_client::authorize(client.get(&url), settings)
+}
+
+// List the provider's models, marking the configured one, and fail if the provider doesn't offer it
+pub fn run(cli: &Cli, config: &Config, args: &ModelsArgs) -> Result<()> {
+    let cli = Cli {
+        provider: args.provider.clone().or(cli.provider.clone()),
+        ..cli.clone()
+    };
+    let settings = resolve_settings(&cli, config)?;
+    let (provider, model_key) = match settings.provider {
+        ApiProvider::OpenAi => ("OpenAI", "openai_model"),
+        ApiProvider::Claude => ("Claude", "claude_model"),
+    };
+
+    let response = list_request(&Client::new(), &settings)
+        .send()
+        .with_context(|| format!("Failed to request the model list from {}", list_url(&settings)))?;
+    if !response.status().is_success() {
+        return Err(api_error(provider, response));
+    }
+    let list: ModelList = response.json().context("Failed to parse the model list")?;
+
+    let mut ids: Vec<String> = list.data.into_iter().map(|model| model.id).collect();
+    ids.sort();
+    for id in &ids {
+        let marker = if *id == settings.model { "*" } else { " " };
+        let note = retired(id)
+            .map(|replacement| format!(" (retired, use {})", replacement))
+            .unwrap_or_default();
+        println!("{} {}{}", marker, id, note);
+    }
+
+    if !ids.contains(&settings.model) {
+        let hint = retired(&settings.model)
+            .map(|replacement| format!(" (it has been retired, use {})", replacement))
+            .unwrap_or_default();
+        anyhow::bail!(
+            "{} doesn't offer the configured model {}{}; choose one of the models above with --model or \"{}\" in the config",
+            provider,
+            settings.model,
+            hint,
+            model_key
+        );
+    }
+    if let Some(replacement) = retired(&settings.model) {
+        warn(&format!("{} is listed but has been retired; use {}", settings.model, replacement));
+    }
+    Ok(())
+}
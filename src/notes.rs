@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use minijinja::{context, Environment, UndefinedBehavior};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Command;
+
+// Where the system keeps the timezone database that date(1) reads
+const ZONEINFO: &str = "/usr/share/zoneinfo";
+
+const WEEKDAYS: &[&str] = &["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+
+// What models write in a Notes section that has nothing to say
+const EMPTY_NOTES: &[&str] = &["", "none", "n/a", "na", "-", "nothing", "nothing to add", "no additional notes"];
+
+// Boilerplate added to the Notes section when a changed path matches one of the globs (every change
+// without globs), only on the given days when it has some
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NoteRule {
+    #[serde(default)]
+    paths: Vec<String>,
+    text: String,
+    #[serde(default)]
+    days: Vec<String>,
+    // IANA name such as "Europe/Berlin" for the days and the time in the text; the local timezone when unset
+    #[serde(default)]
+    timezone: Option<String>,
+}
+
+// Current time in a rule's timezone, as the text's {{ date }}, {{ time }}, {{ weekday }} and {{ timezone }}
+struct Clock {
+    date: String,
+    time: String,
+    weekday: String,
+    zone: String,
+}
+
+// Text of every rule that applies to the change, rendered, without duplicates
+pub fn matching(rules: &[NoteRule], changed_files: &[String], date_format: &str) -> Result<Vec<String>> {
+    let mut notes: Vec<String> = Vec::new();
+
+    for rule in rules {
+        let patterns = rule
+            .paths
+            .iter()
+            .map(|path| glob::Pattern::new(path).with_context(|| format!("Invalid notes path pattern: {}", path)))
+            .collect::<Result<Vec<_>>>()?;
+        // {{ files }} of a rule without globs are all the changed files
+        let files: Vec<&String> = changed_files
+            .iter()
+            .filter(|file| patterns.is_empty() || patterns.iter().any(|pattern| pattern.matches(file)))
+            .collect();
+        if files.is_empty() {
+            continue;
+        }
+
+        // Rules without days or template variables don't need the time, so they work without date(1)
+        let clock = if !rule.days.is_empty() || rule.text.contains("{{") {
+            Some(clock(rule.timezone.as_deref(), date_format)?)
+        } else {
+            None
+        };
+        if let Some(clock) = clock.as_ref().filter(|_| !rule.days.is_empty()) {
+            if !on_day(&rule.days, &clock.weekday)? {
+                continue;
+            }
+        }
+
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        let text = match &clock {
+            Some(clock) => env.render_str(
+                &rule.text,
+                context! {
+                    date => clock.date,
+                    time => clock.time,
+                    weekday => clock.weekday,
+                    timezone => clock.zone,
+                    files => files,
+                },
+            ),
+            None => env.render_str(&rule.text, context! { files => files }),
+        }
+        .map_err(|err| anyhow::anyhow!("Failed to render notes text: {:#}", err))?;
+
+        let text = text.trim().to_string();
+        if !text.is_empty() && !notes.contains(&text) {
+            notes.push(text);
+        }
+    }
+
+    Ok(notes)
+}
+
+// Notes section body with the snippets after what the model wrote, or instead of its "None"
+pub fn merge(existing: &str, notes: &[String]) -> String {
+    let existing = existing.trim();
+    let placeholder = existing.trim_end_matches('.').to_lowercase();
+    if EMPTY_NOTES.contains(&placeholder.as_str()) {
+        notes.join("\n\n")
+    } else {
+        format!("{}\n\n{}", existing, notes.join("\n\n"))
+    }
+}
+
+// Whether today is one of the days, given as names or their first three letters ("fri", "Friday")
+fn on_day(days: &[String], weekday: &str) -> Result<bool> {
+    let mut matched = false;
+    for day in days {
+        let day = day.trim().to_lowercase();
+        let Some(name) = WEEKDAYS.iter().find(|name| day.len() >= 3 && name.starts_with(&day)) else {
+            anyhow::bail!("Invalid day in notes rule: {} (expected mon to sun)", day);
+        };
+        matched |= name.eq_ignore_ascii_case(weekday);
+    }
+    Ok(matched)
+}
+
+// Read with date(1), so the system's timezone database applies
+fn clock(timezone: Option<&str>, date_format: &str) -> Result<Clock> {
+    let mut command = Command::new("date");
+    command.env("LC_ALL", "C");
+    if let Some(timezone) = timezone {
+        // date(1) falls back to UTC for unknown names instead of failing
+        let zoneinfo = Path::new(ZONEINFO);
+        if zoneinfo.is_dir() && !zoneinfo.join(timezone).is_file() {
+            anyhow::bail!("Unknown timezone in notes rule: {}", timezone);
+        }
+        command.env("TZ", timezone);
+    }
+    let output = command
+        .arg(format!("+{}%n%H:%M%n%A%n%Z", date_format))
+        .output()
+        .context("Failed to execute date command for the notes")?;
+    if !output.status.success() {
+        anyhow::bail!("date command failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    let [date, time, weekday, zone] = lines[..] else {
+        anyhow::bail!("Unexpected date command output: {}", stdout.trim());
+    };
+    Ok(Clock {
+        date: date.to_string(),
+        time: time.to_string(),
+        weekday: weekday.to_string(),
+        zone: zone.to_string(),
+    })
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use crate::diff::TrivialChange;
+use crate::{risk, PreparedDiff};
+
+// Deterministic comment built from the diff, its commits and what was detected in it, without a model:
+// for --offline, when no API key is configured and when the model can't answer in time
+pub fn summarize(prepared: &PreparedDiff, artifact: &str) -> String {
+    let counts = diffstat(&prepared.diff);
+    let changed_files = &prepared.changed_files;
+    let subjects = prepared.metadata.as_ref().map_or(&[][..], |metadata| metadata.subjects.as_slice());
+
+    let total_added: usize = counts.values().map(|(added, _)| added).sum();
+    let total_removed: usize = counts.values().map(|(_, removed)| removed).sum();
+
+    // A single commit already has a title; several are listed with the files
+    let title = match (subjects, changed_files.as_slice()) {
+        ([subject], _) => subject.clone(),
+        (_, []) => "Update repository".to_string(),
+        (_, [path]) => format!("Update {}", path),
+        _ => format!("Update {} files", changed_files.len()),
+    };
+    let mut summary = format!(
+        "Changes {} file{} (+{} -{})",
+        changed_files.len(),
+        if changed_files.len() == 1 { "" } else { "s" },
+        total_added,
+        total_removed
+    );
+    if subjects.len() > 1 {
+        summary += &format!(" in {} commits", subjects.len());
+    }
+
+    let mut changes: Vec<String> = if subjects.len() > 1 {
+        subjects.iter().map(|subject| format!("- {}", subject)).collect()
+    } else {
+        Vec::new()
+    };
+    changes.extend(changed_files.iter().map(|path| match counts.get(path) {
+        Some((added, removed)) => format!("- `{}`: +{} -{}", path, added, removed),
+        None => format!("- `{}`", path),
+    }));
+
+    let mut checklist = vec!["- [ ] Review the changed files listed above"];
+    if changed_files.iter().any(|path| risk::is_migration(path)) {
+        checklist.push("- [ ] Check the migrations are backwards compatible and can be rolled back");
+    }
+    if !changed_files.iter().any(|path| risk::is_test(path)) {
+        checklist.push("- [ ] Add or update tests for the changed code");
+    }
+    if !prepared.dependency_changes.is_empty() {
+        checklist.push("- [ ] Check the dependency changes listed below");
+    }
+
+    format!(
+        "{artifact} Title: {title}\n\n\
+         {artifact} Summary: {summary}.\n\n\
+         ## Key Changes:\n\n{}\n\n\
+         ## Review Checklist:\n\n{}\n\n\
+         ## Notes:\n\nGenerated from the diff without a model; edit before posting.",
+        changes.join("\n"),
+        checklist.join("\n"),
+    )
+}
+
+// Added and removed lines per file. The filtered diff drops "diff --git" lines, so files are tracked by
+// their ---/+++ headers.
+fn diffstat(diff: &str) -> HashMap<String, (usize, usize)> {
+    let mut counts: HashMap<String, (usize, usize)> = HashMap::new();
+    let mut old_path = None;
+    let mut current = None;
+
+    for line in diff.lines() {
+        if let Some(path) = line.strip_prefix("--- ") {
+            old_path = path.strip_prefix("a/").map(|p| p.to_string());
+        } else if let Some(path) = line.strip_prefix("+++ ") {
+            current = path
+                .strip_prefix("b/")
+                .map(|p| p.to_string())
+                .or_else(|| old_path.take());
+        } else if let Some(path) = &current {
+            let entry = counts.entry(path.clone()).or_default();
+            if line.starts_with('+') {
+                entry.0 += 1;
+            } else if line.starts_with('-') {
+                entry.1 += 1;
+            }
+        }
+    }
+    counts
+}
+
+// Comment for a diff that only changes whitespace and file modes; not worth a request to the model
+pub fn summarize_trivial(changes: &[TrivialChange], artifact: &str) -> String {
+    let whitespace = changes.iter().any(|change| change.whitespace);
+    let modes = changes.iter().any(|change| change.mode.is_some());
+    let target = match changes {
+        [change] => change.path.clone(),
+        _ => format!("{} files", changes.len()),
+    };
+
+    let (title, summary) = match (whitespace, modes) {
+        (true, true) => (
+            format!("Fix whitespace and file modes in {}", target),
+            "Whitespace and file mode changes only; no content changes.",
+        ),
+        (false, true) => (
+            format!("Change file mode of {}", target),
+            "File mode changes only; no content changes.",
+        ),
+        _ => (format!("Fix whitespace in {}", target), "Whitespace changes only; no content changes."),
+    };
+
+    let key_changes: Vec<String> = changes
+        .iter()
+        .map(|change| {
+            let mut parts = Vec::new();
+            if let Some((old, new)) = &change.mode {
+                parts.push(format!("mode {} → {}", old, new));
+            }
+            if change.whitespace {
+                parts.push("whitespace".to_string());
+            }
+            format!("- `{}`: {}", change.path, parts.join(", "))
+        })
+        .collect();
+
+    let mut checklist = vec!["- [ ] Confirm no content changes were intended"];
+    if modes {
+        checklist.push("- [ ] Confirm the executable bit changes are intended");
+    }
+
+    format!(
+        "{artifact} Title: {title}\n\n\
+         {artifact} Summary: {summary}\n\n\
+         ## Key Changes:\n\n{}\n\n\
+         ## Review Checklist:\n\n{}\n\n\
+         ## Notes:\n\nGenerated from the diff without a model.",
+        key_changes.join("\n"),
+        checklist.join("\n"),
+    )
+}
@@ -0,0 +1,118 @@
+use regex::Regex;
+
+// One patch from a `git format-patch` series or mbox
+struct Patch {
+    subject: String,
+    message: Vec<String>,
+    diff: Vec<String>,
+}
+
+// Turn a format-patch/mbox series into the commit messages followed by the combined diff.
+// Returns None when the content isn't a patch series so it can be used as a plain diff.
+pub fn parse_patch_series(content: &str) -> Option<String> {
+    let mbox_separator = Regex::new(r"^From [0-9a-f]{7,40} ").unwrap();
+    let patch_prefix = Regex::new(r"^\[[^\]]*PATCH[^\]]*\]\s*").unwrap();
+
+    let first_line = content.lines().find(|line| !line.trim().is_empty())?;
+    if !mbox_separator.is_match(first_line) {
+        return None;
+    }
+
+    let mut patches: Vec<Patch> = Vec::new();
+    // 0 = headers, 1 = message, 2 = diffstat, 3 = diff, 4 = signature, 5 = binary patch
+    let mut stage = 0;
+    let mut last_header = String::new();
+
+    for line in content.lines() {
+        if mbox_separator.is_match(line) {
+            patches.push(Patch {
+                subject: String::new(),
+                message: Vec::new(),
+                diff: Vec::new(),
+            });
+            stage = 0;
+            continue;
+        }
+
+        let Some(patch) = patches.last_mut() else {
+            continue;
+        };
+
+        match stage {
+            0 => {
+                if line.is_empty() {
+                    stage = 1;
+                } else if line.starts_with([' ', '\t']) {
+                    // Folded header continuation
+                    if last_header == "subject" {
+                        patch.subject.push(' ');
+                        patch.subject.push_str(line.trim());
+                    }
+                } else if let Some((name, value)) = line.split_once(':') {
+                    last_header = name.to_lowercase();
+                    if last_header == "subject" {
+                        patch.subject = value.trim().to_string();
+                    }
+                }
+            }
+            1 if line == "---" => stage = 2,
+            1 if line.starts_with("diff --git ") => {
+                stage = 3;
+                patch.diff.push(line.to_string());
+            }
+            1 => patch.message.push(line.to_string()),
+            2 if line.starts_with("diff --git ") => {
+                stage = 3;
+                patch.diff.push(line.to_string());
+            }
+            2 => {}
+            5 if line.starts_with("diff --git ") => {
+                stage = 3;
+                patch.diff.push(line.to_string());
+            }
+            5 => {}
+            _ => {
+                // "-- " starts the signature (usually the git version) after the diff
+                if line == "-- " {
+                    stage = 4;
+                } else if line == "GIT binary patch" {
+                    // The base85 payload is meaningless to the model
+                    stage = 5;
+                    patch.diff.push("Binary files differ".to_string());
+                } else if stage == 3 {
+                    patch.diff.push(line.to_string());
+                }
+            }
+        }
+    }
+
+    if patches.is_empty() {
+        return None;
+    }
+
+    let mut output = format!(
+        "Patch series ({} patch{}):\n\n",
+        patches.len(),
+        if patches.len() == 1 { "" } else { "es" }
+    );
+
+    for (i, patch) in patches.iter().enumerate() {
+        let subject = patch_prefix.replace(&patch.subject, "");
+        output += &format!("{}. {}\n", i + 1, subject);
+
+        let message = patch.message.join("\n");
+        for line in message.trim().lines() {
+            output += &format!("   {}\n", line);
+        }
+    }
+
+    output += "\n";
+    for patch in &patches {
+        for line in &patch.diff {
+            output += line;
+            output += "\n";
+        }
+    }
+
+    Some(output)
+}
@@ -0,0 +1,41 @@
+use std::ffi::OsStr;
+use std::path::PathBuf;
+
+// A path from the config or the command line in the form diff headers use: forward slashes, and
+// relative to the repository when it is an absolute path inside it (git prints the root of a Windows
+// checkout as C:/repo). Drive paths are compared case-insensitively, as Windows does, so
+// c:\Repo\vendor\lib in C:/repo is vendor/lib.
+pub fn repo_relative(path: &str, root: Option<&str>) -> String {
+    let path = path.replace('\\', "/");
+    let Some(root) = root.map(|root| root.replace('\\', "/")) else {
+        return path;
+    };
+    let root = root.trim_end_matches('/');
+
+    let rest = path
+        .get(..root.len())
+        .filter(|prefix| if has_drive(root) { prefix.eq_ignore_ascii_case(root) } else { *prefix == root })
+        .and_then(|_| path[root.len()..].strip_prefix('/'));
+    match rest {
+        Some(rest) => rest.to_string(),
+        None => path,
+    }
+}
+
+// "C:" at the start of a path
+fn has_drive(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+// The first file named name plus one of the PATHEXT extensions (".COM;.EXE;.BAT;.CMD") in the PATH
+// directories, so git.cmd and git.bat shims are found as well as git.exe
+pub fn find_executable(name: &str, path: &OsStr, extensions: &str) -> Option<PathBuf> {
+    std::env::split_paths(path).find_map(|dir| {
+        extensions
+            .split(';')
+            .filter(|ext| !ext.is_empty())
+            .map(|ext| dir.join(format!("{}{}", name, ext.to_lowercase())))
+            .find(|candidate| candidate.is_file())
+    })
+}
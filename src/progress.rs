@@ -0,0 +1,38 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::{cache, get_cache_dir, ModelSettings};
+
+// Replies to the requests of a --compose run, under cache/progress/<key>.txt as each one arrives, so
+// a run cut short by Ctrl-C or a network error can be finished with --resume without paying for the
+// sections it already has. The key covers the model, the prompt and the message, so a changed diff
+// or template never resumes from replies written for another.
+pub fn key(settings: &ModelSettings, system_message: &str, user_message: &str) -> String {
+    cache::key(settings, system_message, "progress", user_message)
+}
+
+pub fn load(key: &str) -> Option<String> {
+    fs::read_to_string(path(key).ok()?).ok()
+}
+
+pub fn save(key: &str, text: &str) -> Result<()> {
+    let path = path(key)?;
+    fs::write(&path, text).with_context(|| format!("Failed to write to file: {}", path.display()))
+}
+
+// Forget the replies of a run that finished
+pub fn clear(keys: &[String]) {
+    for key in keys {
+        if let Ok(path) = path(key) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn path(key: &str) -> Result<PathBuf> {
+    let dir = get_cache_dir()?.join("progress");
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create cache directory: {}", dir.display()))?;
+    Ok(dir.join(format!("{}.txt", key)))
+}
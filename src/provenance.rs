@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+use crate::history::utc_timestamp;
+use crate::{api_client, git, ModelSettings};
+
+// Hex digits of the template hash shown in the footer; the cache key uses the full hash
+const TEMPLATE_HASH_LENGTH: usize = 12;
+
+// Which tool, model and prompt template wrote a comment, for auditors asking "which model wrote this?"
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Provenance {
+    pub tool: String,
+    pub provider: String,
+    pub model: String,
+    // Hash of the effective system prompt: the template with the config and flags that shape it
+    pub template: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed: Option<Signed>,
+}
+
+// What a --sign comment was written from and when, for an audit trail tying it to one diff state
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Signed {
+    // Where the diff came from: a revision or range, a stash, a file or the working tree
+    pub source: String,
+    // SHA-256 of the diff as read, before filtering
+    pub diff_sha256: String,
+    pub date: String,
+}
+
+impl Signed {
+    pub fn new(source: &str, diff: &str) -> Self {
+        Signed {
+            source: source.to_string(),
+            diff_sha256: format!("{:x}", Sha256::digest(diff.as_bytes())),
+            date: utc_timestamp(SystemTime::now()),
+        }
+    }
+}
+
+impl Provenance {
+    // model is the one that answered, which is the fallback model when the configured one ran out of time
+    pub fn new(settings: &ModelSettings, model: &str, system_message: &str) -> Self {
+        Provenance {
+            tool: format!("mr-comment {}", env!("CARGO_PKG_VERSION")),
+            provider: api_client::provider_name(settings).to_string(),
+            model: model.to_string(),
+            template: template_hash(system_message)[..TEMPLATE_HASH_LENGTH].to_string(),
+            signed: None,
+        }
+    }
+
+    // A comment built without a model (offline, whitespace-only changes, deadline fallbacks), which
+    // only --sign gives provenance
+    pub fn without_model() -> Self {
+        Provenance {
+            tool: format!("mr-comment {}", env!("CARGO_PKG_VERSION")),
+            provider: String::new(),
+            model: String::new(),
+            template: String::new(),
+            signed: None,
+        }
+    }
+
+    // Hidden footer of the markdown comment; GitLab and GitHub don't render HTML comments. A signed
+    // comment ends with a visible trailer of "Key: value" lines instead, which every output format keeps.
+    pub fn footer(&self) -> String {
+        let Some(signed) = &self.signed else {
+            return format!(
+                "<!-- generated by {} with {} {} (template {}) -->",
+                self.tool, self.provider, self.model, self.template
+            );
+        };
+
+        let mut lines = vec![format!("Generated-By: {}", self.tool)];
+        if self.model.is_empty() {
+            lines.push("Model: none".to_string());
+        } else {
+            lines.push(format!("Model: {} {}", self.provider, self.model));
+            lines.push(format!("Template: {}", self.template));
+        }
+        lines.push(format!("Diff: {}", signed.source));
+        lines.push(format!("Diff-SHA256: {}", signed.diff_sha256));
+        lines.push(format!("Date: {}", signed.date));
+        format!("---\n\n{}", lines.join("\n"))
+    }
+}
+
+// Write a detached, ASCII-armored GPG signature of the file next to it as <file>.asc, with the
+// configured key, git's user.signingkey or GPG's default key
+pub fn detach_sign(path: &Path, key: Option<&str>) -> Result<PathBuf> {
+    let key = key.map(str::to_string).or_else(|| {
+        let output = git::command().args(["config", "user.signingkey"]).output().ok()?;
+        let key = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (output.status.success() && !key.is_empty()).then_some(key)
+    });
+
+    let mut signature = path.as_os_str().to_owned();
+    signature.push(".asc");
+    let signature = PathBuf::from(signature);
+
+    let mut gpg = Command::new("gpg");
+    gpg.args(["--batch", "--yes", "--armor", "--detach-sign"]);
+    if let Some(key) = &key {
+        gpg.args(["--local-user", key]);
+    }
+    let output = gpg
+        .arg("--output")
+        .arg(&signature)
+        .arg(path)
+        .output()
+        .context("Failed to run gpg; is it installed?")?;
+    if !output.status.success() {
+        anyhow::bail!("gpg failed to sign {}: {}", path.display(), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(signature)
+}
+
+pub fn template_hash(system_message: &str) -> String {
+    format!("{:x}", Sha256::digest(system_message.as_bytes()))
+}
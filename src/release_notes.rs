@@ -0,0 +1,260 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use std::collections::HashMap;
+use std::fs;
+use std::thread;
+
+use crate::gitlab::{GitLab, MergeRequestSummary};
+use crate::{
+    check_cost, comment, complete_allowed, git, injection, publish_to_gitlab, untrusted, Cli, Config, ModelSettings,
+};
+
+#[derive(Args, Clone)]
+pub struct ReleaseNotesArgs {
+    /// Tag or revision of the previous release
+    #[arg(long, value_name = "REV")]
+    from: String,
+
+    /// Tag or revision of the release
+    #[arg(long, value_name = "REV", default_value = "HEAD")]
+    to: String,
+
+    /// Labels to group the merge requests by, in this order (comma-separated or repeated; default: every label, most used first)
+    #[arg(long = "group", value_name = "LABEL", value_delimiter = ',')]
+    groups: Vec<String>,
+
+    /// Number of GitLab lookups made at the same time
+    #[arg(short, long, default_value_t = 8)]
+    jobs: usize,
+
+    /// GitLab instance URL (default: "gitlab_url" in the config, CI_SERVER_URL, or the host of the origin remote)
+    #[arg(long, value_name = "URL")]
+    gitlab_url: Option<String>,
+}
+
+// Description lines kept per merge request; the start says what it is about
+const MAX_DESCRIPTION_LINES: usize = 30;
+
+// Group of the merge requests without any of the grouped labels, and of commits without a merge request
+const OTHER_GROUP: &str = "Other changes";
+
+const RELEASE_NOTES_PROMPT: &str = "You are writing the release notes of a software release from the merge \
+requests merged since the previous release. They are grouped by label below, each with its title, labels, \
+diffstat and description. Write markdown release notes:
+
+- Start with a short paragraph on the highlights of the release.
+- Then write one ## section per group, in the order given. Name each section in plain words after its label \
+(e.g. \"Bug Fixes\" for type::bug), and leave out groups with nothing worth telling users.
+- Write one bullet per user-visible change, merging related merge requests into one bullet, and end each bullet \
+with the references of its merge requests (e.g. !123).
+- Put breaking changes and upgrade steps in a \"Breaking Changes\" section before the others.
+
+Don't invent changes that aren't in the merge requests. Output only the release notes.";
+
+// A first-parent commit of the range, with the merge request that brought it in
+struct Change {
+    sha: String,
+    subject: String,
+    stat: Option<String>,
+    merge_request: Option<MergeRequestSummary>,
+}
+
+pub fn run(cli: &Cli, config: &Config, settings: &ModelSettings, args: &ReleaseNotesArgs) -> Result<()> {
+    // Release notes aren't about one merge request
+    if cli.post && !cli.post_to.is_project() {
+        anyhow::bail!("release-notes posts with --post-to snippet or --post-to wiki:<page>");
+    }
+    let range = format!("{}..{}", args.from, args.to);
+    let commits = first_parent_commits(&range)?;
+    if commits.is_empty() {
+        anyhow::bail!("No commits between {} and {}", args.from, args.to);
+    }
+
+    // Merge commits and squashed merge requests are on the first-parent history; each is looked up
+    // on its own, so the lookups run in parallel
+    let gitlab_url = args.gitlab_url.as_deref().or(cli.gitlab_url.as_deref());
+    let gitlab = GitLab::new(config, gitlab_url)?;
+    let chunk_size = commits.len().div_ceil(args.jobs.clamp(1, commits.len()));
+    let lookups: Vec<Result<Vec<MergeRequestSummary>>> = thread::scope(|scope| {
+        let handles: Vec<_> = commits
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let gitlab = &gitlab;
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|(sha, _)| gitlab.commit_merge_requests(sha))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("merge request lookup panicked"))
+            .collect()
+    });
+
+    let mut changes: Vec<Change> = Vec::new();
+    for ((sha, subject), lookup) in commits.into_iter().zip(lookups) {
+        let merge_requests = lookup.with_context(|| format!("Failed to look up the merge requests of {}", sha))?;
+        let merge_request = merge_requests.into_iter().find(|merge_request| merge_request.state == "merged");
+        // A merge request merged in several commits is listed once
+        if let Some(merge_request) = &merge_request {
+            let listed = changes
+                .iter()
+                .any(|change| change.merge_request.as_ref().is_some_and(|listed| listed.iid == merge_request.iid));
+            if listed {
+                continue;
+            }
+        }
+        changes.push(Change {
+            stat: diffstat(&sha),
+            sha,
+            subject,
+            merge_request,
+        });
+    }
+
+    let merge_requests = changes.iter().filter(|change| change.merge_request.is_some()).count();
+    let mut grouped = String::new();
+    for (group, members) in group(&changes, &args.groups) {
+        grouped += &format!("## Group: {}\n", group);
+        for change in members {
+            grouped += &format!("\n{}\n", describe(change));
+        }
+        grouped += "\n";
+    }
+    // Titles, descriptions and commit subjects are written by each merge request's author
+    let user = format!(
+        "Release: {} ({} merge requests, {} commits without one)\n\n{}",
+        range,
+        merge_requests,
+        changes.len() - merge_requests,
+        untrusted(grouped.trim_end(), "the merge requests")
+    );
+
+    let system = format!("{}\n\n{}", RELEASE_NOTES_PROMPT, injection::INSTRUCTIONS);
+    check_cost(cli, config, settings, &system, &user, 1)?;
+    let notes = complete_allowed(config, settings, &system, &user)?;
+    let notes = match &config.terminology {
+        Some(terms) => comment::apply_terminology(&notes, terms)?,
+        None => notes,
+    };
+
+    if let Some(path) = &cli.output {
+        fs::write(path, &notes).with_context(|| format!("Failed to write to file: {}", path.display()))?;
+    } else if !cli.post {
+        println!("{}", notes);
+    }
+    if cli.post {
+        publish_to_gitlab(cli, config, gitlab_url, &format!("Release notes {}", range), &notes)?;
+    }
+    Ok(())
+}
+
+// Hashes and subjects of the commits on the first-parent history of the range, oldest first
+fn first_parent_commits(range: &str) -> Result<Vec<(String, String)>> {
+    let output = git::command()
+        .args(["log", "--first-parent", "--reverse", "--format=%H%x1f%s", range])
+        .output()
+        .context("Failed to execute git log command")?;
+    if !output.status.success() {
+        return Err(git::error("log", &output.stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once('\x1f'))
+        .map(|(sha, subject)| (sha.to_string(), subject.to_string()))
+        .collect())
+}
+
+// "8 files changed, 120 insertions(+), 30 deletions(-)" against the commit's first parent, counted
+// from --numstat rather than read from --shortstat, whose wording follows the locale
+fn diffstat(sha: &str) -> Option<String> {
+    let output = git::command()
+        .args(["diff", "--numstat", "-z", "-M", &format!("{}^1", sha), sha])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stats = git::parse_numstat(&String::from_utf8_lossy(&output.stdout));
+    if stats.is_empty() {
+        return None;
+    }
+
+    let (added, deleted) = stats
+        .iter()
+        .filter_map(|(_, lines)| *lines)
+        .fold((0, 0), |(added, deleted), (a, d)| (added + a, deleted + d));
+    let plural = |count: usize| if count == 1 { "" } else { "s" };
+    Some(format!(
+        "{} file{} changed, {} insertion{}(+), {} deletion{}(-)",
+        stats.len(),
+        plural(stats.len()),
+        added,
+        plural(added),
+        deleted,
+        plural(deleted)
+    ))
+}
+
+// Changes by group: the given labels in their order, or else every label with the most used first.
+// A merge request goes to the first group it has the label of; the rest go to the other changes.
+fn group<'a>(changes: &'a [Change], labels: &[String]) -> Vec<(String, Vec<&'a Change>)> {
+    let labels: Vec<String> = if labels.is_empty() {
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for label in changes.iter().filter_map(|change| change.merge_request.as_ref()).flat_map(|mr| &mr.labels) {
+            match counts.iter_mut().find(|(name, _)| name == label) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((label.clone(), 1)),
+            }
+        }
+        // Stable: labels used as often keep the order they first appear in
+        counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        counts.into_iter().map(|(label, _)| label).collect()
+    } else {
+        labels.to_vec()
+    };
+
+    let mut groups: HashMap<&str, Vec<&Change>> = HashMap::new();
+    for change in changes {
+        let label = change
+            .merge_request
+            .as_ref()
+            .and_then(|mr| labels.iter().find(|label| mr.labels.contains(label)));
+        groups.entry(label.map_or(OTHER_GROUP, String::as_str)).or_default().push(change);
+    }
+    labels
+        .iter()
+        .map(String::as_str)
+        .chain([OTHER_GROUP])
+        .filter_map(|label| groups.remove(label).map(|members| (label.to_string(), members)))
+        .collect()
+}
+
+// A merge request (or a commit without one) as given to the model
+fn describe(change: &Change) -> String {
+    let stat = change.stat.as_deref().unwrap_or("no diffstat");
+    let Some(mr) = &change.merge_request else {
+        return format!("### Commit {} {} ({})", &change.sha[..change.sha.len().min(10)], change.subject, stat);
+    };
+
+    let mut text = format!("### !{} {} ({})\n{}", mr.iid, mr.title, stat, mr.web_url);
+    if !mr.labels.is_empty() {
+        text += &format!("\nLabels: {}", mr.labels.join(", "));
+    }
+    // Hidden markers, such as the one --post adds, say nothing about the change
+    let description: Vec<&str> = mr
+        .description
+        .as_deref()
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with("<!--"))
+        .take(MAX_DESCRIPTION_LINES)
+        .collect();
+    if !description.is_empty() {
+        text += &format!("\n{}", description.join("\n"));
+    }
+    text
+}
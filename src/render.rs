@@ -0,0 +1,489 @@
+use anyhow::{Context, Result};
+use clap::builder::PossibleValue;
+use regex::Regex;
+use reqwest::blocking::Client;
+use serde_json::{json, Value};
+use termimad::crossterm::style::Color;
+use termimad::MadSkin;
+use std::fs;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::flavor::{self, Flavor};
+use crate::{chat, comment, schema, Failure};
+
+// Finished comment handed to renderers; structured is set when the provider returned schema output
+pub struct Comment {
+    pub markdown: String,
+    pub structured: Option<schema::MrComment>,
+    // --flavor of the markdown format; None leaves the markdown as written
+    pub flavor: Option<Flavor>,
+}
+
+// One --format value. Adding a format means adding a renderer to RENDERERS.
+pub trait OutputRenderer: Sync {
+    fn name(&self) -> &'static str;
+
+    fn help(&self) -> &'static str;
+
+    // Ask the provider for schema-conforming output instead of markdown
+    fn structured(&self) -> bool {
+        false
+    }
+
+    fn render(&self, comment: &Comment) -> Result<String>;
+
+    // Whether the output is markdown that can be styled when printed to a terminal
+    fn styled(&self) -> bool {
+        false
+    }
+
+    // Whether the output can be posted to an incoming webhook (see webhook_payload)
+    fn postable(&self) -> bool {
+        false
+    }
+
+    // Incoming webhook body for the rendered output
+    fn webhook_payload(&self, _rendered: &str) -> Option<Value> {
+        None
+    }
+}
+
+const RENDERERS: &[&dyn OutputRenderer] = &[&Markdown, &Json, &Html, &Plain, &Slack, &Teams];
+
+// --format values for clap, with "text" kept as the markdown format's old name
+pub fn possible_values() -> Vec<PossibleValue> {
+    RENDERERS
+        .iter()
+        .map(|renderer| {
+            let value = PossibleValue::new(renderer.name()).help(renderer.help());
+            if renderer.name() == "markdown" {
+                value.alias("text")
+            } else {
+                value
+            }
+        })
+        .collect()
+}
+
+pub fn lookup(name: &str) -> Result<&'static dyn OutputRenderer> {
+    let name = if name == "text" { "markdown" } else { name };
+    RENDERERS
+        .iter()
+        .copied()
+        .find(|renderer| renderer.name() == name)
+        .with_context(|| format!("Unknown output format: {}", name))
+}
+
+struct Markdown;
+
+impl OutputRenderer for Markdown {
+    fn name(&self) -> &'static str {
+        "markdown"
+    }
+
+    fn help(&self) -> &'static str {
+        "Markdown MR/PR comment"
+    }
+
+    fn render(&self, comment: &Comment) -> Result<String> {
+        Ok(match comment.flavor {
+            Some(flavor) => flavor::apply(&comment.markdown, flavor),
+            None => comment.markdown.clone(),
+        })
+    }
+
+    fn styled(&self) -> bool {
+        true
+    }
+}
+
+struct Json;
+
+impl OutputRenderer for Json {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn help(&self) -> &'static str {
+        "Schema-conforming JSON from the provider's structured output"
+    }
+
+    fn structured(&self) -> bool {
+        true
+    }
+
+    fn render(&self, comment: &Comment) -> Result<String> {
+        let structured = match &comment.structured {
+            Some(structured) => structured.clone(),
+            None => schema::MrComment::from_markdown(&comment.markdown),
+        };
+        serde_json::to_string_pretty(&structured).context("Failed to serialize comment")
+    }
+}
+
+struct Html;
+
+impl OutputRenderer for Html {
+    fn name(&self) -> &'static str {
+        "html"
+    }
+
+    fn help(&self) -> &'static str {
+        "HTML fragment, e.g. for email or wiki pages"
+    }
+
+    fn render(&self, comment: &Comment) -> Result<String> {
+        Ok(markdown_to_html(&without_hidden(&comment.markdown)))
+    }
+}
+
+struct Plain;
+
+impl OutputRenderer for Plain {
+    fn name(&self) -> &'static str {
+        "plain"
+    }
+
+    fn help(&self) -> &'static str {
+        "Plain text without markdown markup"
+    }
+
+    fn render(&self, comment: &Comment) -> Result<String> {
+        Ok(markdown_to_plain(&comment::expand_sections(&without_hidden(&comment.markdown))))
+    }
+}
+
+struct Slack;
+
+impl OutputRenderer for Slack {
+    fn name(&self) -> &'static str {
+        "slack"
+    }
+
+    fn help(&self) -> &'static str {
+        "Slack mrkdwn message"
+    }
+
+    fn render(&self, comment: &Comment) -> Result<String> {
+        Ok(chat::slack_message(&comment::expand_sections(&without_hidden(&comment.markdown))))
+    }
+
+    fn postable(&self) -> bool {
+        true
+    }
+
+    fn webhook_payload(&self, rendered: &str) -> Option<Value> {
+        Some(json!({ "text": rendered }))
+    }
+}
+
+struct Teams;
+
+impl OutputRenderer for Teams {
+    fn name(&self) -> &'static str {
+        "teams"
+    }
+
+    fn help(&self) -> &'static str {
+        "Microsoft Teams Adaptive Card JSON"
+    }
+
+    fn render(&self, comment: &Comment) -> Result<String> {
+        let markdown = comment::expand_sections(&without_hidden(&comment.markdown));
+        serde_json::to_string_pretty(&chat::teams_card(&markdown))
+            .context("Failed to serialize Adaptive Card")
+    }
+
+    fn postable(&self) -> bool {
+        true
+    }
+
+    fn webhook_payload(&self, rendered: &str) -> Option<Value> {
+        let card: Value = serde_json::from_str(rendered).ok()?;
+        Some(json!({
+            "type": "message",
+            "attachments": [{
+                "contentType": "application/vnd.microsoft.card.adaptive",
+                "content": card
+            }]
+        }))
+    }
+}
+
+// Where rendered output goes
+pub struct Sinks<'a> {
+    pub stdout: bool,
+    pub file: Option<&'a Path>,
+    pub webhook: Option<&'a str>,
+    pub clipboard: bool,
+    pub quiet: bool,
+}
+
+// Send the rendered output to every requested sink
+pub fn deliver(renderer: &dyn OutputRenderer, rendered: &str, sinks: &Sinks) -> Result<()> {
+    if let Some(url) = sinks.webhook {
+        let payload = renderer
+            .webhook_payload(rendered)
+            .with_context(|| format!("The {} format can't be posted to a webhook", renderer.name()))?;
+        post_webhook(url, &payload).context(Failure::Post)?;
+        if !sinks.quiet {
+            println!("Message posted to the webhook");
+        }
+    }
+
+    if sinks.clipboard {
+        copy_to_clipboard(rendered)?;
+        if !sinks.quiet {
+            println!("MR comment copied to the clipboard");
+        }
+    }
+
+    if let Some(path) = sinks.file {
+        fs::write(path, rendered)
+            .with_context(|| format!("Failed to write to file: {}", path.display()))?;
+        if !sinks.quiet {
+            println!("MR comment written to {}", path.display());
+        }
+    }
+
+    if sinks.stdout {
+        if renderer.styled() && color_terminal() {
+            print!("{}", terminal_markdown(rendered));
+        } else {
+            println!("{}", rendered);
+        }
+    }
+
+    Ok(())
+}
+
+// Colors only for an interactive terminal, and never when NO_COLOR is set (https://no-color.org)
+fn color_terminal() -> bool {
+    io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+// Markdown with colored headings, bullets and checkboxes, wrapped to the terminal width
+fn terminal_markdown(markdown: &str) -> String {
+    let mut skin = MadSkin::default();
+    skin.set_headers_fg(Color::Cyan);
+    skin.bullet.set_fg(Color::Yellow);
+    skin.inline_code.set_fg(Color::Green);
+
+    // Bold the "MR Title:" / "MR Summary:" labels; task list items become boxes without a bullet
+    let label = Regex::new(r"^(\S+ (?:Title|Summary):)").unwrap();
+    let lines: Vec<String> = markdown
+        .lines()
+        .map(|line| {
+            let (indent, rest) = line.split_at(line.len() - line.trim_start().len());
+            if let Some(item) = rest.strip_prefix("- [ ] ") {
+                format!("{}☐ {}", indent, item)
+            } else if let Some(item) = rest.strip_prefix("- [x] ").or_else(|| rest.strip_prefix("- [X] ")) {
+                format!("{}☑ {}", indent, item)
+            } else if let Some(item) = rest.strip_prefix("- ") {
+                // termimad only recognizes "*" list items
+                format!("{}* {}", indent, item)
+            } else {
+                label.replace(line, "**$1**").into_owned()
+            }
+        })
+        .collect();
+
+    skin.term_text(&lines.join("\n")).to_string()
+}
+
+fn post_webhook(url: &str, payload: &Value) -> Result<()> {
+    let response = Client::new()
+        .post(url)
+        .json(payload)
+        .send()
+        .context("Failed to post to webhook")?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().unwrap_or_default();
+        anyhow::bail!("Posting to webhook failed: {} {}", status, body.trim());
+    }
+
+    Ok(())
+}
+
+// Pipe the text into the platform's clipboard tool (Wayland, X11, macOS, Windows)
+fn copy_to_clipboard(text: &str) -> Result<()> {
+    let tools: &[(&str, &[&str])] = if cfg!(target_os = "macos") {
+        &[("pbcopy", &[])]
+    } else if cfg!(windows) {
+        &[("clip", &[])]
+    } else {
+        &[
+            ("wl-copy", &[]),
+            ("xclip", &["-selection", "clipboard"]),
+            ("xsel", &["--clipboard", "--input"]),
+        ]
+    };
+
+    for (program, args) in tools {
+        let Ok(mut child) = Command::new(program)
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            continue;
+        };
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(text.as_bytes())
+                .with_context(|| format!("Failed to write to {}", program))?;
+        }
+        let status = child.wait().with_context(|| format!("Failed to run {}", program))?;
+        if !status.success() {
+            anyhow::bail!("{} failed: {}", program, status);
+        }
+        return Ok(());
+    }
+
+    let names: Vec<&str> = tools.iter().map(|(program, _)| *program).collect();
+    anyhow::bail!("No clipboard tool found (tried {})", names.join(", "))
+}
+
+// Markdown without lines that are only an HTML comment, such as the provenance footer; formats
+// other than markdown would show them
+fn without_hidden(markdown: &str) -> String {
+    let hidden = Regex::new(r"^\s*<!--.*-->\s*$").unwrap();
+    let lines: Vec<&str> = markdown.lines().filter(|line| !hidden.is_match(line)).collect();
+    lines.join("\n").trim_end().to_string()
+}
+
+// Heading level and text, without the trailing colon the prompt format uses
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let trimmed = line.trim_start();
+    let level = trimmed.chars().take_while(|c| *c == '#').count();
+    if level == 0 {
+        return None;
+    }
+    Some((level.min(6), trimmed[level..].trim().trim_end_matches(':').trim()))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Inline code, bold and links; everything else is escaped
+fn inline_html(text: &str) -> String {
+    let bold = Regex::new(r"\*\*(.+?)\*\*|__(.+?)__").unwrap();
+    let link = Regex::new(r"\[([^\]]+)\]\(([^)\s]+)\)").unwrap();
+
+    text.split('`')
+        .enumerate()
+        .map(|(i, segment)| {
+            let segment = escape_html(segment);
+            if i % 2 == 1 {
+                return format!("<code>{}</code>", segment);
+            }
+            let segment = bold.replace_all(&segment, |caps: &regex::Captures| {
+                format!("<strong>{}</strong>", caps.get(1).or(caps.get(2)).map_or("", |m| m.as_str()))
+            });
+            link.replace_all(&segment, r#"<a href="$2">$1</a>"#).into_owned()
+        })
+        .collect()
+}
+
+fn markdown_to_html(markdown: &str) -> String {
+    let parsed = comment::parse(markdown);
+    let mut html = vec![format!("<h1>{}</h1>", inline_html(&parsed.title))];
+    let mut paragraph: Vec<String> = Vec::new();
+    let mut in_list = false;
+    let mut in_fence = false;
+
+    let close = |html: &mut Vec<String>, paragraph: &mut Vec<String>, in_list: &mut bool| {
+        if !paragraph.is_empty() {
+            html.push(format!("<p>{}</p>", paragraph.join("\n")));
+            paragraph.clear();
+        }
+        if *in_list {
+            html.push("</ul>".to_string());
+            *in_list = false;
+        }
+    };
+
+    for line in parsed.description.lines() {
+        if line.trim_start().starts_with("```") {
+            close(&mut html, &mut paragraph, &mut in_list);
+            html.push(if in_fence { "</code></pre>" } else { "<pre><code>" }.to_string());
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            html.push(escape_html(line));
+            continue;
+        }
+
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() {
+            close(&mut html, &mut paragraph, &mut in_list);
+        } else if comment::is_details_tag(line) {
+            // Collapsed sections stay collapsed
+            close(&mut html, &mut paragraph, &mut in_list);
+            html.push(trimmed.trim_end().to_string());
+        } else if let Some((level, text)) = heading(line) {
+            close(&mut html, &mut paragraph, &mut in_list);
+            html.push(format!("<h{0}>{1}</h{0}>", level, inline_html(text)));
+        } else if let Some(item) = trimmed.strip_prefix("- ").or_else(|| trimmed.strip_prefix("* ")) {
+            if !paragraph.is_empty() {
+                html.push(format!("<p>{}</p>", paragraph.join("\n")));
+                paragraph.clear();
+            }
+            if !in_list {
+                html.push("<ul>".to_string());
+                in_list = true;
+            }
+            let item = if let Some(text) = item.strip_prefix("[ ] ") {
+                format!(r#"<input type="checkbox" disabled> {}"#, inline_html(text))
+            } else if let Some(text) = item.strip_prefix("[x] ").or_else(|| item.strip_prefix("[X] ")) {
+                format!(r#"<input type="checkbox" checked disabled> {}"#, inline_html(text))
+            } else {
+                inline_html(item)
+            };
+            html.push(format!("<li>{}</li>", item));
+        } else {
+            if in_list {
+                html.push("</ul>".to_string());
+                in_list = false;
+            }
+            paragraph.push(inline_html(trimmed));
+        }
+    }
+    if in_fence {
+        html.push("</code></pre>".to_string());
+    }
+    close(&mut html, &mut paragraph, &mut in_list);
+
+    html.join("\n")
+}
+
+fn markdown_to_plain(markdown: &str) -> String {
+    let parsed = comment::parse(markdown);
+    let link = Regex::new(r"\[([^\]]+)\]\(([^)\s]+)\)").unwrap();
+    let strip = |text: &str| {
+        let text = link.replace_all(text, "$1 ($2)");
+        text.replace("**", "").replace("__", "").replace('`', "")
+    };
+
+    let mut lines = vec![strip(&parsed.title), String::new()];
+    for line in parsed.description.lines() {
+        if line.trim_start().starts_with("```") {
+            continue;
+        }
+        match heading(line) {
+            Some((_, text)) => lines.push(strip(text)),
+            None => lines.push(strip(line)),
+        }
+    }
+
+    lines.join("\n").trim().to_string()
+}
@@ -0,0 +1,137 @@
+use regex::Regex;
+
+use crate::ignore;
+use crate::git;
+
+// README lines given to the model unless "readme_lines" is set
+pub const DEFAULT_README_LINES: usize = 30;
+
+const README_NAMES: &[&str] = &["README.md", "README.rst", "README.txt", "README", "readme.md"];
+
+// Where GitHub and GitLab look for the code owners file, first match wins
+const CODEOWNERS_PATHS: &[&str] = &[".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS", ".gitlab/CODEOWNERS"];
+
+struct OwnerRule {
+    pattern: ignore::Rule,
+    owners: Vec<String>,
+}
+
+// GitLab "[Section]" of a CODEOWNERS file; everything before the first header is one unnamed section
+#[derive(Default)]
+struct Section {
+    default_owners: Vec<String>,
+    rules: Vec<OwnerRule>,
+}
+
+// Prompt block with the start of the README and the owners of the changed files, as of the revision
+pub fn describe(revision: &str, readme_lines: usize, changed_files: &[String]) -> Option<String> {
+    let mut blocks = Vec::new();
+    if let Some(readme) = readme(revision, readme_lines) {
+        blocks.push(format!("Project README (start, for the project's domain and vocabulary):\n{}", readme));
+    }
+    let owners = owners(revision, changed_files);
+    if !owners.is_empty() {
+        let lines: Vec<String> = owners.iter().map(|line| format!("• {}", line)).collect();
+        blocks.push(format!(
+            "Code owners of the changed files (suggest them as reviewers in the Notes):\n{}",
+            lines.join("\n")
+        ));
+    }
+    (!blocks.is_empty()).then(|| blocks.join("\n\n"))
+}
+
+// First lines of the README, without badges, images and HTML
+fn readme(revision: &str, max_lines: usize) -> Option<String> {
+    let text = README_NAMES
+        .iter()
+        .find_map(|name| git::output(&["show", &format!("{}:{}", revision, name)]))?;
+    let lines: Vec<&str> = text
+        .lines()
+        .filter(|line| {
+            let line = line.trim_start();
+            !(line.starts_with("[![") || line.starts_with("![") || line.starts_with('<'))
+        })
+        .take(max_lines)
+        .collect();
+    let summary = lines.join("\n").trim().to_string();
+    (!summary.is_empty()).then_some(summary)
+}
+
+// "@owners: files" lines, one per distinct set of owners
+fn owners(revision: &str, changed_files: &[String]) -> Vec<String> {
+    let mut groups: Vec<(String, Vec<&str>)> = Vec::new();
+    for (file, owners) in file_owners(revision, changed_files) {
+        let owners = owners.join(" ");
+        match groups.iter_mut().find(|(group, _)| *group == owners) {
+            Some((_, files)) => files.push(file),
+            None => groups.push((owners, vec![file])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(owners, files)| format!("{}: {}", owners, files.join(", ")))
+        .collect()
+}
+
+// CODEOWNERS owners ("@user", "@group/subgroup" or an email) of each changed file that has any
+pub fn file_owners<'a>(revision: &str, changed_files: &'a [String]) -> Vec<(&'a str, Vec<String>)> {
+    let Some(content) = CODEOWNERS_PATHS
+        .iter()
+        .find_map(|path| git::output(&["show", &format!("{}:{}", revision, path)]))
+    else {
+        return Vec::new();
+    };
+    let sections = parse(&content);
+
+    let mut result = Vec::new();
+    for file in changed_files {
+        // The last matching line of each section decides, and every section adds its owners
+        let mut owners: Vec<String> = Vec::new();
+        for section in &sections {
+            let Some(rule) = section.rules.iter().rev().find(|rule| rule.pattern.matches(file)) else {
+                continue;
+            };
+            let rule_owners = if rule.owners.is_empty() { &section.default_owners } else { &rule.owners };
+            for owner in rule_owners {
+                if !owners.contains(owner) {
+                    owners.push(owner.clone());
+                }
+            }
+        }
+        if !owners.is_empty() {
+            result.push((file.as_str(), owners));
+        }
+    }
+    result
+}
+
+// Lines with patterns the glob crate can't parse are skipped rather than failing the run
+fn parse(content: &str) -> Vec<Section> {
+    // "[Name]", "^[Name]" (optional) and "[Name][2]" (approvals), followed by default owners
+    let header = Regex::new(r"^\^?\[[^\]]+\](?:\[\d+\])?\s*(.*)$").unwrap();
+
+    let mut sections = vec![Section::default()];
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(captures) = header.captures(line) {
+            sections.push(Section {
+                default_owners: captures[1].split_whitespace().map(str::to_string).collect(),
+                rules: Vec::new(),
+            });
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let Some(Ok(pattern)) = fields.next().map(ignore::parse) else {
+            continue;
+        };
+        let owners = fields.take_while(|field| !field.starts_with('#')).map(str::to_string).collect();
+        if let Some(section) = sections.last_mut() {
+            section.rules.push(OwnerRule { pattern, owners });
+        }
+    }
+    sections
+}
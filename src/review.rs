@@ -0,0 +1,213 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use serde::Deserialize;
+use std::fs;
+
+use crate::gitlab::{GitLab, PostMode};
+use crate::{
+    check_cost, comment, complete_allowed, diff_budget, diff_message, injection, prepare_diff, read_diff, untrusted,
+    Cli, Config, Failure, ModelSettings,
+};
+
+#[derive(Args, Clone)]
+pub struct ReviewArgs {
+    /// Merge request IID (the number in the MR URL)
+    #[arg(long, value_name = "IID")]
+    mr: u64,
+
+    /// Summarize the discussion threads instead of describing the commits pushed since the last review
+    #[arg(long)]
+    summary: bool,
+
+    /// Post the reply as a comment on the MR instead of printing it
+    #[arg(long)]
+    post: bool,
+
+    /// With --post: add a new note each time, or update or replace the reply posted before
+    #[arg(long, value_enum, default_value = "new")]
+    post_mode: PostMode,
+
+    /// GitLab instance URL (default: "gitlab_url" in the config, CI_SERVER_URL, or the host of the origin remote)
+    #[arg(long, value_name = "URL")]
+    gitlab_url: Option<String>,
+}
+
+const SUMMARY_PROMPT: &str = "You are helping the author of a GitLab merge request reply to its review. \
+Summarize the review discussion below as a markdown comment with these sections:
+
+## Review Summary: [decisions reached and changes agreed on, in a few bullets]
+## Open Threads: [unresolved questions, each with the reviewer's @username and the file it is about]
+## Action Items: [markdown checkboxes for what the author still has to do]
+
+Leave out sections with nothing to list. Don't invent discussion that isn't there. Output only the comment.";
+
+const CHANGES_PROMPT: &str = "You are helping the author of a GitLab merge request reply to its review. \
+The author pushed new commits after the review threads below. Using the threads and the git diff of the new \
+commits, write a markdown comment with these sections:
+
+## Changes Made in Response to Review: [one bullet per change, naming the thread it addresses by the \
+reviewer's @username and file]
+## Other Changes: [changes in the diff no thread asked for]
+## Still Open: [unresolved threads the diff doesn't address]
+
+Leave out sections with nothing to list. Only describe changes the diff shows. Output only the comment.";
+
+#[derive(Deserialize)]
+struct MergeRequest {
+    author: User,
+    web_url: String,
+}
+
+#[derive(Deserialize)]
+struct User {
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct Discussion {
+    notes: Vec<Note>,
+}
+
+#[derive(Deserialize)]
+struct Note {
+    body: String,
+    author: User,
+    created_at: String,
+    #[serde(default)]
+    system: bool,
+    #[serde(default)]
+    resolvable: bool,
+    #[serde(default)]
+    resolved: bool,
+    position: Option<Position>,
+}
+
+#[derive(Deserialize)]
+struct Position {
+    new_path: Option<String>,
+    new_line: Option<u64>,
+    old_path: Option<String>,
+}
+
+// One push to the MR branch, newest first in the API response
+#[derive(Deserialize)]
+struct Version {
+    head_commit_sha: String,
+    created_at: String,
+}
+
+pub fn run(cli: &Cli, config: &Config, settings: &ModelSettings, args: &ReviewArgs) -> Result<()> {
+    let gitlab = GitLab::new(config, args.gitlab_url.as_deref())?;
+    let mr_path = format!("merge_requests/{}", args.mr);
+
+    let mr: MergeRequest = gitlab.get(&mr_path)?;
+    let discussions: Vec<Discussion> = gitlab.get_all(&format!("{}/discussions", mr_path))?;
+    let threads = format_threads(&discussions, &mr.author.username);
+    if threads.is_empty() {
+        anyhow::bail!("!{} has no review discussion to reply to", args.mr);
+    }
+
+    // Threads are written by anyone who can comment on the merge request
+    let threads = untrusted(&threads, "the review threads");
+    let (system, user) = if args.summary {
+        (SUMMARY_PROMPT, format!("Review threads:\n\n{}", threads))
+    } else {
+        let versions: Vec<Version> = gitlab.get_all(&format!("{}/versions", mr_path))?;
+        let range = review_range(&discussions, &mr.author.username, &versions)
+            .with_context(|| format!("No commits were pushed to !{} since the last review (try --summary)", args.mr))?;
+
+        let range_cli = Cli {
+            commit: Some(range.clone()),
+            file: None,
+            stash: None,
+            merge_base: false,
+            ..cli.clone()
+        };
+        let raw_diff = read_diff(&range_cli, config)
+            .with_context(|| format!("Failed to diff {}; fetch the MR branch first", range))?;
+        let prepared = prepare_diff(&range_cli, config, raw_diff)?;
+        let user = format!(
+            "Review threads:\n\n{}\n\n{}",
+            threads,
+            diff_message(&prepared.diff, &diff_budget(cli, config))
+        );
+        (CHANGES_PROMPT, user)
+    };
+    let system = format!("{}\n\n{}", system, injection::INSTRUCTIONS);
+
+    check_cost(cli, config, settings, &system, &user, 1)?;
+    let reply = complete_allowed(config, settings, &system, &user)?;
+    let reply = match &config.terminology {
+        Some(terms) => comment::apply_terminology(&reply, terms)?,
+        None => reply,
+    };
+
+    if args.post {
+        let action = gitlab
+            .post_note(args.mr, &reply, "mr-comment:review-reply", args.post_mode)
+            .context(Failure::Post)?;
+        if !cli.quiet {
+            println!("Reply {} on {}", action, mr.web_url);
+        }
+    } else if let Some(path) = &cli.output {
+        fs::write(path, &reply).with_context(|| format!("Failed to write to file: {}", path.display()))?;
+    } else {
+        println!("{}", reply);
+    }
+
+    Ok(())
+}
+
+// Threads with their notes, marking the MR author's replies. System notes ("added 1 commit", ...) are left out.
+fn format_threads(discussions: &[Discussion], author: &str) -> String {
+    let mut threads = Vec::new();
+    for discussion in discussions {
+        let notes: Vec<&Note> = discussion.notes.iter().filter(|note| !note.system).collect();
+        let Some(first) = notes.first() else {
+            continue;
+        };
+
+        let status = if !first.resolvable {
+            "comment"
+        } else if notes.iter().all(|note| !note.resolvable || note.resolved) {
+            "resolved"
+        } else {
+            "unresolved"
+        };
+        let location = first
+            .position
+            .as_ref()
+            .and_then(|position| {
+                let path = position.new_path.as_ref().or(position.old_path.as_ref())?;
+                Some(match position.new_line {
+                    Some(line) => format!(" on {}:{}", path, line),
+                    None => format!(" on {}", path),
+                })
+            })
+            .unwrap_or_default();
+
+        let mut thread = format!("Thread {} ({}){}:", threads.len() + 1, status, location);
+        for note in notes {
+            let role = if note.author.username == author { " (author)" } else { "" };
+            thread += &format!("\n- @{}{}: {}", note.author.username, role, note.body.trim().replace('\n', "\n  "));
+        }
+        threads.push(thread);
+    }
+    threads.join("\n\n")
+}
+
+// Commits pushed after the last note by someone other than the author, as a git range
+fn review_range(discussions: &[Discussion], author: &str, versions: &[Version]) -> Option<String> {
+    // GitLab timestamps are ISO 8601 in UTC with a fixed width, so they sort as strings
+    let last_review = discussions
+        .iter()
+        .flat_map(|discussion| &discussion.notes)
+        .filter(|note| !note.system && note.author.username != author)
+        .map(|note| note.created_at.as_str())
+        .max()?;
+
+    let latest = versions.first()?;
+    let reviewed = versions.iter().find(|version| version.created_at.as_str() <= last_review)?;
+    (reviewed.head_commit_sha != latest.head_commit_sha)
+        .then(|| format!("{}..{}", reviewed.head_commit_sha, latest.head_commit_sha))
+}
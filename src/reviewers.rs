@@ -0,0 +1,173 @@
+use regex::Regex;
+use std::collections::HashMap;
+
+use crate::diff::split_files;
+use crate::repo_context;
+use crate::git;
+
+// People suggested from git history, in addition to every code owner
+const MAX_REVIEWERS: usize = 3;
+
+// Files blamed at most, so huge changes don't run git blame hundreds of times
+const MAX_BLAMED_FILES: usize = 50;
+
+// Commits of the changed paths looked at when blame finds too few people
+const HISTORY_COMMITS: &str = "200";
+
+#[derive(Clone, Debug)]
+pub struct Reviewer {
+    pub name: String,
+    // GitLab username of a "@user" code owner
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub reason: String,
+}
+
+impl Reviewer {
+    // "@user" for code owners, the author name otherwise
+    pub fn handle(&self) -> String {
+        match &self.username {
+            Some(username) => format!("@{}", username),
+            None => self.name.clone(),
+        }
+    }
+}
+
+// An author found by blame or the log, with how many lines or commits they account for
+struct Author {
+    name: String,
+    count: usize,
+}
+
+// Code owners of the changed files, then the people who wrote the lines the diff changes (from git blame
+// at base) or, failing that, who committed most to the changed paths. Names and emails in exclude (the
+// change's own authors) are left out.
+pub fn suggest(raw_diff: &str, base: &str, revision: &str, exclude: &[String]) -> Vec<Reviewer> {
+    let excluded = |name: &str, email: &str| exclude.iter().any(|person| person == name || person == email);
+    let (_, files) = split_files(raw_diff);
+    let changed_files: Vec<String> = files.iter().map(|file| file.path.clone()).collect();
+
+    let mut reviewers: Vec<Reviewer> = Vec::new();
+    for (file, owners) in repo_context::file_owners(revision, &changed_files) {
+        for owner in owners {
+            if let Some(reviewer) = reviewers.iter_mut().find(|reviewer| reviewer.name == owner) {
+                reviewer.reason += &format!(", {}", file);
+                continue;
+            }
+            // Owners are "@user", "@group/subgroup" or an email address
+            let (username, email) = match owner.strip_prefix('@') {
+                Some(username) => (Some(username.to_string()), None),
+                None => (None, Some(owner.clone())),
+            };
+            if excluded(username.as_deref().unwrap_or_default(), email.as_deref().unwrap_or_default()) {
+                continue;
+            }
+            reviewers.push(Reviewer {
+                name: owner.clone(),
+                username,
+                email,
+                reason: format!("code owner of {}", file),
+            });
+        }
+    }
+
+    // Hunk headers give the old line ranges the diff touches
+    let hunk = Regex::new(r"^@@ -(\d+)(?:,(\d+))? \+").unwrap();
+    let mut authors: HashMap<String, Author> = HashMap::new();
+    for file in files.iter().filter(|file| !file.is_new).take(MAX_BLAMED_FILES) {
+        let mut args = vec!["blame".to_string(), "--line-porcelain".to_string()];
+        for captures in file.lines.iter().filter_map(|line| hunk.captures(line)) {
+            let start: usize = captures[1].parse().unwrap_or(0);
+            let count: usize = captures.get(2).map_or(1, |count| count.as_str().parse().unwrap_or(0));
+            if start > 0 && count > 0 {
+                args.push(format!("-L{},+{}", start, count));
+            }
+        }
+        if args.len() == 2 {
+            continue;
+        }
+        args.extend([base.to_string(), "--".to_string(), file.moved_from.clone().unwrap_or(file.path.clone())]);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let Some(blame) = git::output(&args) else {
+            continue;
+        };
+
+        // --line-porcelain repeats the author fields for every line
+        let mut name = "";
+        for line in blame.lines() {
+            if let Some(author) = line.strip_prefix("author ") {
+                name = author;
+            } else if let Some(mail) = line.strip_prefix("author-mail ") {
+                let email = mail.trim_matches(['<', '>']);
+                let listed = reviewers.iter().any(|reviewer| reviewer.email.as_deref() == Some(email));
+                if !excluded(name, email) && !listed {
+                    let author = authors.entry(email.to_string()).or_insert_with(|| Author {
+                        name: name.to_string(),
+                        count: 0,
+                    });
+                    author.count += 1;
+                }
+            }
+        }
+    }
+    let blamed = push_authors(&mut reviewers, authors, MAX_REVIEWERS, |lines| {
+        format!("wrote {} of the changed line{}", lines, if lines == 1 { "" } else { "s" })
+    });
+
+    // New files and thin blame: whoever has been committing to the same paths and directories
+    if blamed < MAX_REVIEWERS {
+        let mut paths: Vec<String> = files
+            .iter()
+            .map(|file| match file.path.rsplit_once('/') {
+                Some((dir, _)) if file.is_new => dir.to_string(),
+                _ => file.moved_from.clone().unwrap_or(file.path.clone()),
+            })
+            .collect();
+        paths.sort();
+        paths.dedup();
+
+        let mut args = vec!["log", "--format=%an%x1f%ae", "-n", HISTORY_COMMITS, base, "--"];
+        args.extend(paths.iter().map(String::as_str));
+        let mut authors: HashMap<String, Author> = HashMap::new();
+        for line in git::output(&args).unwrap_or_default().lines() {
+            let Some((name, email)) = line.split_once('\x1f') else {
+                continue;
+            };
+            if excluded(name, email) || reviewers.iter().any(|reviewer| reviewer.email.as_deref() == Some(email)) {
+                continue;
+            }
+            let author = authors.entry(email.to_string()).or_insert_with(|| Author {
+                name: name.to_string(),
+                count: 0,
+            });
+            author.count += 1;
+        }
+        push_authors(&mut reviewers, authors, MAX_REVIEWERS - blamed, |commits| {
+            format!("{} recent commit{} to the changed paths", commits, if commits == 1 { "" } else { "s" })
+        });
+    }
+
+    reviewers
+}
+
+// Add up to limit authors, those accounting for the most lines or commits first; returns how many were added
+fn push_authors(
+    reviewers: &mut Vec<Reviewer>,
+    authors: HashMap<String, Author>,
+    limit: usize,
+    reason: impl Fn(usize) -> String,
+) -> usize {
+    let mut authors: Vec<(String, Author)> = authors.into_iter().collect();
+    authors.sort_by(|(a_email, a), (b_email, b)| b.count.cmp(&a.count).then(a_email.cmp(b_email)));
+
+    let added = authors.len().min(limit);
+    for (email, author) in authors.into_iter().take(limit) {
+        reviewers.push(Reviewer {
+            name: author.name,
+            username: None,
+            email: Some(email),
+            reason: reason(author.count),
+        });
+    }
+    added
+}
@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+
+use crate::diff::{split_files, FileDiff};
+
+// Test files that lose at least this many lines (and gain none) count as removed test code
+const SHRUNK_TEST_LINES: usize = 10;
+
+// Instructions appended to the prompt for --risk
+pub const INSTRUCTIONS: &str = "Also add a \"## Risk & Impact:\" section after the Review Checklist. Start it with \
+\"Risk: low\", \"Risk: medium\" or \"Risk: high\" and the main reason on the same line, then list what could break \
+and who or what is affected. Base the rating on the risk signals given with the diff (database migrations, deleted \
+tests, configuration and infrastructure changes, areas touched) and on the diff itself.";
+
+// Facts about the diff that make a change riskier, one line each, for the model to rate
+pub fn signals(diff: &str) -> Vec<String> {
+    let (_, files) = split_files(diff);
+    if files.is_empty() {
+        return Vec::new();
+    }
+
+    let mut added = 0;
+    let mut removed = 0;
+    let mut areas: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut migrations = Vec::new();
+    let mut deleted_tests = Vec::new();
+    let mut shrunk_tests = Vec::new();
+    let mut changed_tests = 0;
+    let mut ci = Vec::new();
+    let mut infrastructure = Vec::new();
+    let mut configuration = Vec::new();
+
+    for file in &files {
+        let (plus, minus) = line_counts(file);
+        added += plus;
+        removed += minus;
+        let area = file.path.split_once('/').map_or("(root)", |(dir, _)| dir);
+        *areas.entry(area).or_default() += 1;
+
+        let path = file.path.as_str();
+        if is_test(path) {
+            changed_tests += 1;
+            if file.is_deleted {
+                deleted_tests.push(path);
+            } else if plus == 0 && minus >= SHRUNK_TEST_LINES {
+                shrunk_tests.push(format!("{} (-{} lines)", path, minus));
+            }
+        } else if is_migration(path) {
+            migrations.push(path);
+        } else if is_ci(path) {
+            ci.push(path);
+        } else if is_infrastructure(path) {
+            infrastructure.push(path);
+        } else if is_configuration(path) {
+            configuration.push(path);
+        }
+    }
+
+    let mut areas: Vec<(&str, usize)> = areas.into_iter().collect();
+    areas.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    let areas: Vec<String> = areas
+        .iter()
+        .map(|(area, count)| format!("{} ({} file{})", area, count, if *count == 1 { "" } else { "s" }))
+        .collect();
+
+    let mut signals = vec![
+        format!("Size: {} files changed, +{} -{} lines", files.len(), added, removed),
+        format!("Areas touched: {}", areas.join(", ")),
+    ];
+    let mut list = |label: &str, paths: &[&str]| {
+        if !paths.is_empty() {
+            signals.push(format!("{}: {}", label, paths.join(", ")));
+        }
+    };
+    list("Database migrations", &migrations);
+    list("Deleted test files", &deleted_tests);
+    list("Test code removed", &shrunk_tests.iter().map(String::as_str).collect::<Vec<_>>());
+    list("CI configuration changed", &ci);
+    list("Infrastructure changed", &infrastructure);
+    list("Configuration changed", &configuration);
+    if changed_tests == 0 {
+        signals.push("No test files changed".to_string());
+    }
+    signals
+}
+
+// Lines added and removed in the file's hunks
+fn line_counts(file: &FileDiff) -> (usize, usize) {
+    let mut added = 0;
+    let mut removed = 0;
+    for line in &file.lines {
+        if line.starts_with('+') && !line.starts_with("+++") {
+            added += 1;
+        } else if line.starts_with('-') && !line.starts_with("---") {
+            removed += 1;
+        }
+    }
+    (added, removed)
+}
+
+// Lowercased names of the directories the file is in
+fn directories(path: &str) -> Vec<String> {
+    let mut components: Vec<String> = path.split('/').map(str::to_lowercase).collect();
+    components.pop();
+    components
+}
+
+fn file_name(path: &str) -> String {
+    path.rsplit('/').next().unwrap_or(path).to_lowercase()
+}
+
+pub fn is_test(path: &str) -> bool {
+    let name = file_name(path);
+    let in_test_dir = directories(path)
+        .iter()
+        .any(|dir| ["test", "tests", "spec", "specs", "__tests__", "testdata"].contains(&dir.as_str()));
+    in_test_dir
+        || name.starts_with("test_")
+        || ["_test.", ".test.", "_spec.", ".spec.", "test.java", "tests.cs"]
+            .iter()
+            .any(|marker| name.contains(marker))
+}
+
+pub fn is_migration(path: &str) -> bool {
+    let name = file_name(path);
+    let in_migration_dir = directories(path)
+        .iter()
+        .any(|dir| ["migrations", "migrate", "alembic", "flyway", "liquibase"].contains(&dir.as_str()));
+    in_migration_dir || ["schema.rb", "structure.sql"].contains(&name.as_str())
+}
+
+pub fn is_ci(path: &str) -> bool {
+    let lower = path.to_lowercase();
+    let name = file_name(path);
+    [".gitlab-ci.yml", "jenkinsfile", ".travis.yml", "azure-pipelines.yml", "bitbucket-pipelines.yml"]
+        .contains(&name.as_str())
+        || [".github/workflows/", ".circleci/", ".buildkite/", ".gitlab/ci/"]
+            .iter()
+            .any(|dir| lower.starts_with(dir) || lower.contains(&format!("/{}", dir)))
+}
+
+// Terraform, Helm, Kubernetes and container definitions
+pub fn is_infrastructure(path: &str) -> bool {
+    let name = file_name(path);
+    let extension = name.rsplit_once('.').map(|(_, extension)| extension).unwrap_or_default();
+    let in_infrastructure_dir = directories(path).iter().any(|dir| {
+        ["k8s", "kubernetes", "helm", "charts", "terraform", "deploy", "deployment"].contains(&dir.as_str())
+    });
+    in_infrastructure_dir
+        || ["tf", "tfvars", "hcl"].contains(&extension)
+        || name.starts_with("dockerfile")
+        || name.starts_with("docker-compose")
+        || ["chart.yaml", "kustomization.yaml", "kustomization.yml", "helmfile.yaml"].contains(&name.as_str())
+}
+
+pub fn is_configuration(path: &str) -> bool {
+    let name = file_name(path);
+    let extension = name.rsplit_once('.').map(|(_, extension)| extension).unwrap_or_default();
+    let in_config_dir = directories(path)
+        .iter()
+        .any(|dir| ["config", "configs", "conf", "settings", "environments"].contains(&dir.as_str()));
+    name.starts_with(".env")
+        || (["yaml", "yml", "toml", "ini", "json", "conf", "cfg", "properties", "env"].contains(&extension)
+            && (in_config_dir || name.starts_with("config.") || name.starts_with("settings.")))
+}
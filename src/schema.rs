@@ -0,0 +1,352 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::provenance::Provenance;
+use crate::{comment, compliance, versioning};
+
+// Above this many changed files the schema doesn't enumerate the paths --evidence may cite; the
+// reply is checked against them either way
+const MAX_EVIDENCE_ENUM: usize = 200;
+
+// JSON schema sent to the provider (OpenAI structured outputs / Claude tool input)
+pub struct Schema {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub definition: Value,
+}
+
+// Structured MR/PR comment returned with --format json
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MrComment {
+    pub title: String,
+    pub summary: String,
+    pub key_changes: Vec<String>,
+    pub why: String,
+    pub checklist: Vec<String>,
+    pub notes: String,
+    // Only with --risk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub risk: Option<RiskAssessment>,
+    // Requested when the diff touches migrations, infrastructure or CI; filled from the detected changes otherwise
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deployment_notes: Vec<String>,
+    // Results of the configured compliance checks, rendered after the checklist
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub compliance: Vec<compliance::Check>,
+    // Directories the change touches that churned most recently, for --hotspots; computed locally
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub careful_review: Vec<String>,
+    // Computed locally from the manifest diffs, never requested from the model
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependency_changes: Vec<String>,
+    // Submodule pointer changes and their commits, computed locally like the dependency changes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub submodule_updates: Vec<String>,
+    // Semver bump computed from the diff and commits, for --suggest-version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub versioning: Option<versioning::Recommendation>,
+    // Code owners and authors of the changed lines, with the reason, for --suggest-reviewers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suggested_reviewers: Vec<String>,
+    // Kinds of the change as GitLab labels, for --suggest-labels
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suggested_labels: Vec<String>,
+    // Contributors footer for --attribution, from the commits
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub attribution: String,
+    // Added locally from the quick_actions config, never requested from the model
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quick_actions: Vec<String>,
+    // Tool, model and prompt template hash that wrote the comment, rendered as a hidden footer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+}
+
+// Risk rating and impact for --risk
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RiskAssessment {
+    pub level: String,
+    pub impact: Vec<String>,
+}
+
+pub fn mr_comment(risk: bool, deployment_notes: bool, evidence: Option<&[String]>) -> Schema {
+    // OpenAI strict mode requires every property to be listed as required and no extra properties
+    let mut definition = json!({
+        "type": "object",
+        "properties": {
+            "title": { "type": "string", "description": "One-sentence title" },
+            "summary": { "type": "string", "description": "Brief overview of the change" },
+            "key_changes": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "Major updates, one per entry, without bullet markers"
+            },
+            "why": { "type": "string", "description": "Motivation for the changes" },
+            "checklist": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "Review checklist items, one per entry, without checkbox markers"
+            },
+            "notes": { "type": "string", "description": "Additional context, empty if none" }
+        },
+        "required": ["title", "summary", "key_changes", "why", "checklist", "notes"],
+        "additionalProperties": false
+    });
+    if risk {
+        definition["properties"]["risk"] = json!({
+            "type": "object",
+            "properties": {
+                "level": { "type": "string", "enum": ["low", "medium", "high"] },
+                "impact": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Main reason for the rating, then what could break and who or what is affected"
+                }
+            },
+            "required": ["level", "impact"],
+            "additionalProperties": false
+        });
+        definition["required"].as_array_mut().unwrap().push(json!("risk"));
+    }
+    if let Some(changed_files) = evidence {
+        let mut path = json!({ "type": "string" });
+        if changed_files.len() <= MAX_EVIDENCE_ENUM {
+            path["enum"] = json!(changed_files);
+        }
+        definition["properties"]["key_changes"] = json!({
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "change": { "type": "string", "description": "The change, without bullet markers" },
+                    "files": {
+                        "type": "array",
+                        "items": path,
+                        "description": "Changed files of the diff the change comes from"
+                    }
+                },
+                "required": ["change", "files"],
+                "additionalProperties": false
+            },
+            "description": "Major updates, one per entry, each with the files it comes from"
+        });
+    }
+    if deployment_notes {
+        definition["properties"]["deployment_notes"] = json!({
+            "type": "array",
+            "items": { "type": "string" },
+            "description": "What deploying the change requires (migrations, infrastructure, CI, order, rollback), one per entry"
+        });
+        definition["required"].as_array_mut().unwrap().push(json!("deployment_notes"));
+    }
+
+    Schema {
+        name: "mr_comment",
+        description: "Record the merge request / pull request comment for the diff",
+        definition,
+    }
+}
+
+impl MrComment {
+    // Build from a markdown comment (e.g. the offline summary) by its sections
+    pub fn from_markdown(text: &str) -> Self {
+        let parsed = comment::parse(text);
+        let section = |name: &str| comment::find_section(text, name).unwrap_or_default();
+        let items = |name: &str| -> Vec<String> {
+            section(name)
+                .lines()
+                .map(|line| {
+                    let line = line.trim_start();
+                    let line = line.strip_prefix("- [ ]").or_else(|| line.strip_prefix("- [x]")).unwrap_or(line);
+                    line.trim_start_matches(['-', '*', '•']).trim().to_string()
+                })
+                .filter(|line| !line.is_empty())
+                .collect()
+        };
+
+        let summary = parsed
+            .description
+            .lines()
+            .take_while(|line| !line.trim_start().starts_with('#'))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim()
+            .to_string();
+
+        MrComment {
+            title: parsed.title,
+            summary,
+            key_changes: items("key changes"),
+            why: section("why these changes"),
+            checklist: items("review checklist"),
+            notes: section("notes"),
+            risk: comment::find_section(text, "risk & impact").and_then(|section| parse_risk(&section)),
+            deployment_notes: items("deployment notes"),
+            careful_review: items("areas needing careful review"),
+            compliance: Vec::new(),
+            dependency_changes: items("dependency changes"),
+            submodule_updates: items("submodule updates"),
+            versioning: None,
+            suggested_reviewers: items("suggested reviewers"),
+            suggested_labels: items("suggested labels"),
+            attribution: String::new(),
+            quick_actions: Vec::new(),
+            provenance: None,
+        }
+    }
+
+    // Markdown in the same shape as the text format, for --split-output
+    pub fn to_markdown(&self) -> String {
+        let bullets = |items: &[String], marker: &str| {
+            items
+                .iter()
+                .map(|item| format!("{} {}", marker, item))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+
+        let mut checklist = bullets(&self.checklist, "- [ ]");
+        for check in &self.compliance {
+            checklist += &format!("\n{} {}", if check.passed { "- [x]" } else { "- [ ]" }, check.text);
+        }
+
+        let mut text = format!(
+            "{}\n\n{}\n\n## Key Changes\n\n{}\n\n## Why These Changes\n\n{}\n\n## Review Checklist\n\n{}",
+            self.title,
+            self.summary,
+            bullets(&self.key_changes, "-"),
+            self.why,
+            checklist.trim_start(),
+        );
+        if !self.careful_review.is_empty() {
+            text += &format!("\n\n## Areas Needing Careful Review\n\n{}", bullets(&self.careful_review, "-"));
+        }
+        if let Some(risk) = &self.risk {
+            let mut impact = risk.impact.iter();
+            let reason = impact.next().map(|reason| format!(" - {}", reason)).unwrap_or_default();
+            let rest: Vec<&String> = impact.collect();
+            text += &format!("\n\n## Risk & Impact\n\nRisk: {}{}", risk.level, reason);
+            if !rest.is_empty() {
+                let items: Vec<String> = rest.iter().map(|item| format!("- {}", item)).collect();
+                text += &format!("\n\n{}", items.join("\n"));
+            }
+        }
+        if !self.deployment_notes.is_empty() {
+            text += &format!("\n\n## Deployment Notes\n\n{}", bullets(&self.deployment_notes, "-"));
+        }
+        if !self.notes.trim().is_empty() {
+            text += &format!("\n\n## Notes\n\n{}", self.notes);
+        }
+        if !self.dependency_changes.is_empty() {
+            text += &format!("\n\n## Dependency Changes\n\n{}", bullets(&self.dependency_changes, "-"));
+        }
+        if !self.submodule_updates.is_empty() {
+            text += &format!("\n\n## Submodule Updates\n\n{}", bullets(&self.submodule_updates, "-"));
+        }
+        if let Some(versioning) = &self.versioning {
+            text += &format!("\n\n## Versioning\n\n{}", versioning.markdown());
+        }
+        if !self.suggested_reviewers.is_empty() {
+            text += &format!("\n\n## Suggested Reviewers\n\n{}", bullets(&self.suggested_reviewers, "-"));
+        }
+        if !self.suggested_labels.is_empty() {
+            text += &format!("\n\n## Suggested Labels\n\n{}", bullets(&self.suggested_labels, "-"));
+        }
+        if !self.attribution.is_empty() {
+            text += &format!("\n\n---\n\n_{}_", self.attribution);
+        }
+        if !self.quick_actions.is_empty() {
+            text += &format!("\n\n{}\n", self.quick_actions.join("\n"));
+        }
+        if let Some(provenance) = &self.provenance {
+            text = format!("{}\n\n{}", text.trim_end(), provenance.footer());
+        }
+        text
+    }
+
+    // Apply a text transformation (e.g. terminology) to every model-written field
+    pub fn map_text(self, mut f: impl FnMut(&str) -> Result<String>) -> Result<Self> {
+        Ok(MrComment {
+            title: f(&self.title)?,
+            summary: f(&self.summary)?,
+            key_changes: self.key_changes.iter().map(|item| f(item)).collect::<Result<_>>()?,
+            why: f(&self.why)?,
+            checklist: self.checklist.iter().map(|item| f(item)).collect::<Result<_>>()?,
+            notes: f(&self.notes)?,
+            risk: match self.risk {
+                Some(risk) => Some(RiskAssessment {
+                    level: risk.level,
+                    impact: risk.impact.iter().map(|item| f(item)).collect::<Result<_>>()?,
+                }),
+                None => None,
+            },
+            deployment_notes: self.deployment_notes.iter().map(|item| f(item)).collect::<Result<_>>()?,
+            careful_review: self.careful_review.iter().map(|item| f(item)).collect::<Result<_>>()?,
+            compliance: self.compliance,
+            dependency_changes: self.dependency_changes,
+            submodule_updates: self.submodule_updates,
+            versioning: self.versioning,
+            suggested_reviewers: self.suggested_reviewers,
+            suggested_labels: self.suggested_labels,
+            attribution: self.attribution,
+            quick_actions: self.quick_actions,
+            provenance: self.provenance,
+        })
+    }
+}
+
+// "Risk: medium - reason" followed by impact bullets, as the --risk prompt asks for
+// Turn the cited key changes of an --evidence reply into "change (`path`)" entries, keeping only
+// paths of the diff; changes left citing none are dropped and returned
+pub fn cite_evidence(reply: &mut Value, changed_files: &[String]) -> Result<Vec<String>> {
+    #[derive(Deserialize)]
+    struct Cited {
+        change: String,
+        files: Vec<String>,
+    }
+
+    let cited: Vec<Cited> = serde_json::from_value(reply["key_changes"].take())
+        .context("Structured response has key changes without cited files")?;
+    let mut key_changes = Vec::new();
+    let mut dropped = Vec::new();
+    for Cited { change, files } in cited {
+        let mut paths: Vec<&str> = Vec::new();
+        for file in &files {
+            // Models sometimes keep the a/ and b/ prefixes of the diff headers
+            let file = file.trim().trim_start_matches("./");
+            let found = changed_files.iter().find(|path| {
+                *path == file || file.strip_prefix("a/").or_else(|| file.strip_prefix("b/")) == Some(path.as_str())
+            });
+            if let Some(path) = found.filter(|path| !paths.contains(&path.as_str())) {
+                paths.push(path);
+            }
+        }
+        if paths.is_empty() {
+            dropped.push(change);
+            continue;
+        }
+        let paths: Vec<String> = paths.iter().map(|path| format!("`{}`", path)).collect();
+        key_changes.push(format!("{} ({})", change.trim(), paths.join(", ")));
+    }
+    reply["key_changes"] = json!(key_changes);
+    Ok(dropped)
+}
+
+fn parse_risk(section: &str) -> Option<RiskAssessment> {
+    let re = Regex::new(r"(?i)^\W*risk\W*(low|medium|high)\b\W*(.*)$").unwrap();
+    let mut lines = section.lines().map(str::trim).filter(|line| !line.is_empty());
+    let caps = re.captures(lines.next()?)?;
+
+    let mut impact = Vec::new();
+    let reason = caps[2].trim();
+    if !reason.is_empty() {
+        impact.push(reason.to_string());
+    }
+    impact.extend(lines.map(|line| line.trim_start_matches(['-', '*', '•']).trim().to_string()));
+    Some(RiskAssessment {
+        level: caps[1].to_lowercase(),
+        impact,
+    })
+}
@@ -0,0 +1,159 @@
+use anyhow::{Context, Result};
+use clap::{Args, Subcommand, ValueEnum};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::{get_config_path, Config, Failure};
+
+// Config fields holding credentials, the ones `config encrypt` encrypts
+const SECRET_FIELDS: &[&str] = &["openai_api_key", "claude_api_key", "gitlab_token", "github_token"];
+
+// Armor headers of encrypted values; anything else is a plain-text value
+const AGE_HEADER: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+const PGP_HEADER: &str = "-----BEGIN PGP MESSAGE-----";
+
+// Identity file for age-encrypted values, overriding "age_identity" in the config
+const AGE_IDENTITY_VAR: &str = "MR_COMMENT_AGE_IDENTITY";
+
+#[derive(Args, Clone)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    command: ConfigCommand,
+}
+
+#[derive(Subcommand, Clone)]
+enum ConfigCommand {
+    /// Encrypt the API keys and GitLab token in the config file with age or GPG
+    Encrypt {
+        /// Tool to encrypt with; mr-comment runs the same tool to decrypt
+        #[arg(long, value_enum, default_value_t = Tool::Age)]
+        tool: Tool,
+        /// Recipient: an age public key (required for age), or a GPG key ID or email (default: your own key)
+        #[arg(short, long)]
+        recipient: Vec<String>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum Tool {
+    Age,
+    Gpg,
+}
+
+// Encrypt the plain-text credentials of the config file in place; other fields are left as they are
+pub fn run(args: &ConfigArgs) -> Result<()> {
+    let ConfigCommand::Encrypt { tool, recipient } = &args.command;
+    if let (Tool::Age, []) = (tool, recipient.as_slice()) {
+        anyhow::bail!("age needs --recipient, the public key of your identity (`age-keygen -y <identity file>` prints it)");
+    }
+
+    let config_path = get_config_path()?;
+    let content = fs::read_to_string(&config_path)
+        .with_context(|| format!("Failed to read config file: {}", config_path.display()))?;
+    let Value::Object(mut config) = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse config file: {}", config_path.display()))?
+    else {
+        anyhow::bail!("Config is not a JSON object: {}", config_path.display());
+    };
+
+    let mut encrypted = Vec::new();
+    for field in SECRET_FIELDS {
+        let Some(Value::String(value)) = config.get(*field) else {
+            continue;
+        };
+        if is_encrypted(value) {
+            continue;
+        }
+        let armored = encrypt(*tool, recipient, value).with_context(|| format!("Failed to encrypt {}", field))?;
+        config.insert(field.to_string(), Value::String(armored));
+        encrypted.push(*field);
+    }
+    if encrypted.is_empty() {
+        println!("No plain-text API keys or tokens in {}", config_path.display());
+        return Ok(());
+    }
+
+    let content = serde_json::to_string_pretty(&Value::Object(config)).context("Failed to serialize config")?;
+    fs::write(&config_path, content + "\n")
+        .with_context(|| format!("Failed to write to file: {}", config_path.display()))?;
+    println!("Encrypted {} in {}", encrypted.join(", "), config_path.display());
+    Ok(())
+}
+
+// Plain text of a credential from the config, decrypted if it is encrypted. Credentials are decrypted
+// when a run uses them, so the others don't ask for a passphrase or fail the run.
+pub fn reveal(config: &Config, field: &str, value: &Option<String>) -> Result<Option<String>> {
+    let Some(text) = value.as_deref().filter(|text| is_encrypted(text)) else {
+        return Ok(value.clone());
+    };
+    let identity = env::var(AGE_IDENTITY_VAR).ok().or_else(|| config.age_identity.clone());
+    let plain = decrypt(text, identity.as_deref())
+        .with_context(|| format!("Failed to decrypt {} from the config", field))
+        .context(Failure::Auth)?;
+    Ok(Some(plain))
+}
+
+fn is_encrypted(value: &str) -> bool {
+    let value = value.trim_start();
+    value.starts_with(AGE_HEADER) || value.starts_with(PGP_HEADER)
+}
+
+fn encrypt(tool: Tool, recipients: &[String], plain: &str) -> Result<String> {
+    let mut args: Vec<&str> = match tool {
+        Tool::Age => vec!["--encrypt", "--armor"],
+        Tool::Gpg => vec!["--batch", "--yes", "--armor", "--encrypt"],
+    };
+    for recipient in recipients {
+        args.extend(["--recipient", recipient.as_str()]);
+    }
+    if let (Tool::Gpg, []) = (tool, recipients) {
+        args.push("--default-recipient-self");
+    }
+    let program = match tool {
+        Tool::Age => "age",
+        Tool::Gpg => "gpg",
+    };
+    pipe(program, &args, plain)
+}
+
+// age decrypts with an identity file; GPG asks its agent for the key
+fn decrypt(armored: &str, age_identity: Option<&str>) -> Result<String> {
+    let plain = if armored.trim_start().starts_with(AGE_HEADER) {
+        let identity = age_identity.with_context(|| {
+            format!("age-encrypted values need an identity file: set {} or \"age_identity\" in the config", AGE_IDENTITY_VAR)
+        })?;
+        pipe("age", &["--decrypt", "--identity", identity], armored)?
+    } else {
+        pipe("gpg", &["--batch", "--quiet", "--decrypt"], armored)?
+    };
+    Ok(plain.trim_end_matches(['\r', '\n']).to_string())
+}
+
+// Run the tool with input on stdin and return its stdout
+fn pipe(program: &str, args: &[&str], input: &str) -> Result<String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}; is it installed?", program))?;
+
+    // Secrets are a few hundred bytes at most, well within the pipe buffer
+    child
+        .stdin
+        .take()
+        .context("Failed to open stdin")?
+        .write_all(input.as_bytes())
+        .with_context(|| format!("Failed to write to {}", program))?;
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("Failed to read {} output", program))?;
+    if !output.status.success() {
+        anyhow::bail!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    String::from_utf8(output.stdout).with_context(|| format!("{} output is not UTF-8", program))
+}
@@ -0,0 +1,80 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::comment;
+
+// One output section from the "sections" config, replacing the built-in ones; the list order is the output order
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SectionDefinition {
+    pub name: String,
+    // What the section should contain, passed to the model as written
+    description: Option<String>,
+    // Required sections are always written; optional ones are left out when the diff gives nothing for them
+    // (default: true)
+    required: Option<bool>,
+}
+
+impl SectionDefinition {
+    fn required(&self) -> bool {
+        self.required.unwrap_or(true)
+    }
+}
+
+// Names must be set and unique, since they become the headings the comment is checked against
+pub fn validate(sections: &[SectionDefinition]) -> Result<()> {
+    if sections.is_empty() {
+        anyhow::bail!("\"sections\" in the config is empty; remove it to use the built-in sections");
+    }
+    for (index, section) in sections.iter().enumerate() {
+        let name = section.name.trim();
+        if name.is_empty() {
+            anyhow::bail!("Section {} in the config has no name", index + 1);
+        }
+        if sections[..index].iter().any(|other| other.name.trim().eq_ignore_ascii_case(name)) {
+            anyhow::bail!("Section \"{}\" is defined twice in the config", name);
+        }
+    }
+    Ok(())
+}
+
+// Prompt instructions asking for the title line followed by the configured sections
+pub fn instructions(platform: &str, artifact: &str, sections: &[SectionDefinition]) -> String {
+    let format: Vec<String> = sections
+        .iter()
+        .map(|section| {
+            let description = section.description.as_deref().unwrap_or("content based on the diff");
+            let optional = if section.required() { "" } else { " (optional)" };
+            format!("## {}:{} [{}]", section.name.trim(), optional, description)
+        })
+        .collect();
+
+    format!(
+        r#"Carefully review the provided git diff and generate a professional {artifact} comment. Use this format:
+
+{artifact} Title: [1-sentence summary]
+{}
+
+Formatting rules:
+- Write the sections in this order, with exactly these headings
+- Never add sections that aren't listed
+- Always write the sections not marked optional, with "N/A" if the diff gives nothing for one
+- Leave out an optional section when the diff gives nothing for it
+- Add blank lines after headings using '\n\n'
+- Never include section headers in the title
+- Use standard {platform} markdown syntax
+
+Example {artifact} Title: Add user authentication middleware
+
+The git diff may be truncated - focus analysis on visible changes."#,
+        format.join("\n")
+    )
+}
+
+// Required sections the comment has no heading for
+pub fn missing(comment: &str, sections: &[SectionDefinition]) -> Vec<String> {
+    sections
+        .iter()
+        .filter(|section| section.required() && comment::find_section(comment, section.name.trim()).is_none())
+        .map(|section| section.name.trim().to_string())
+        .collect()
+}
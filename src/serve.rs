@@ -0,0 +1,437 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{
+    build_prompt, comment_for, composed_sections, diff_message, finish_comment, finish_structured, generate_text,
+    prepare_diff, read_diff, restrict_diff, schema, stream_completion, Cli, Config, Failure, Generated, ModelSettings,
+    PromptTemplate,
+};
+
+#[derive(Args, Clone)]
+pub struct ServeArgs {
+    /// Port to listen on (localhost only)
+    #[arg(long, default_value_t = 8731)]
+    port: u16,
+
+    /// Token clients send as "Authorization: Bearer <TOKEN>" (default: a random one, printed at startup)
+    #[arg(long)]
+    token: Option<String>,
+}
+
+// Larger requests are rejected instead of being read into memory
+const MAX_BODY_BYTES: usize = 32 * 1024 * 1024;
+
+// Request line and headers together; clients send a few hundred bytes
+const MAX_HEAD_BYTES: u64 = 16 * 1024;
+
+// Time a client gets to send its request, so one that never finishes doesn't hold a thread forever
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+// POST /comment body; without a diff the working tree (or --commit etc.) is described
+#[derive(Deserialize, Default)]
+struct CommentRequest {
+    diff: Option<String>,
+    #[serde(default)]
+    stream: bool,
+}
+
+// Receives each piece of a streamed reply
+type OnText<'a> = dyn FnMut(&str) -> Result<()> + 'a;
+
+// The statuses the server answers with, each with its reason phrase
+#[derive(Clone, Copy)]
+enum Status {
+    Ok,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    RequestTimeout,
+    PayloadTooLarge,
+    UnprocessableEntity,
+    HeaderFieldsTooLarge,
+    InternalServerError,
+}
+
+impl Status {
+    fn code(self) -> u16 {
+        match self {
+            Status::Ok => 200,
+            Status::BadRequest => 400,
+            Status::Unauthorized => 401,
+            Status::Forbidden => 403,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::RequestTimeout => 408,
+            Status::PayloadTooLarge => 413,
+            Status::UnprocessableEntity => 422,
+            Status::HeaderFieldsTooLarge => 431,
+            Status::InternalServerError => 500,
+        }
+    }
+
+    fn reason(self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::BadRequest => "Bad Request",
+            Status::Unauthorized => "Unauthorized",
+            Status::Forbidden => "Forbidden",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::RequestTimeout => "Request Timeout",
+            Status::PayloadTooLarge => "Payload Too Large",
+            Status::UnprocessableEntity => "Unprocessable Entity",
+            Status::HeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Status::InternalServerError => "Internal Server Error",
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    host: Option<String>,
+    origin: Option<String>,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Server<'a> {
+    cli: &'a Cli,
+    config: &'a Config,
+    settings: &'a ModelSettings,
+    prompt: PromptTemplate,
+    port: u16,
+    token: String,
+}
+
+pub fn run(cli: &Cli, config: &Config, settings: &ModelSettings, args: &ServeArgs) -> Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, args.port))
+        .with_context(|| format!("Failed to listen on 127.0.0.1:{}", args.port))?;
+
+    // The prompt only depends on the repository, so it is built (and any style guide distilled) once
+    let server = Server {
+        cli,
+        config,
+        settings,
+        prompt: build_prompt(cli, config, settings)?,
+        port: args.port,
+        token: args.token.clone().unwrap_or_else(random_token),
+    };
+    eprintln!("Listening on http://127.0.0.1:{}", args.port);
+    if args.token.is_none() {
+        eprintln!("Token: {}", server.token);
+    }
+
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("Failed to accept connection: {}", err);
+                    continue;
+                }
+            };
+            let server = &server;
+            scope.spawn(move || {
+                if let Err(err) = server.handle(stream) {
+                    eprintln!("Error: {:#}", err);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+impl Server<'_> {
+    fn handle(&self, mut stream: TcpStream) -> Result<()> {
+        let request = match read_request(&mut stream)? {
+            Ok(request) => request,
+            Err((status, message)) => {
+                respond(&mut stream, status, &json!({ "error": message }))?;
+                // Closing with unread input resets the connection, which can lose the response on the way
+                let _ = stream.shutdown(Shutdown::Write);
+                let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+                let _ = std::io::copy(&mut (&mut stream).take(MAX_HEAD_BYTES), &mut std::io::sink());
+                return Ok(());
+            }
+        };
+        if let Err((status, message)) = self.authorize(&request) {
+            return respond(&mut stream, status, &json!({ "error": message }));
+        }
+
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/health") => respond(
+                &mut stream,
+                Status::Ok,
+                &json!({ "status": "ok", "version": env!("CARGO_PKG_VERSION") }),
+            ),
+            ("POST", "/comment") => {
+                let body: CommentRequest = if request.body.is_empty() {
+                    CommentRequest::default()
+                } else {
+                    match serde_json::from_slice(&request.body) {
+                        Ok(body) => body,
+                        Err(err) => {
+                            let error = json!({ "error": format!("Invalid request body: {}", err) });
+                            return respond(&mut stream, Status::BadRequest, &error);
+                        }
+                    }
+                };
+                if body.stream {
+                    self.stream_comment(&mut stream, body.diff)
+                } else {
+                    match self.comment(body.diff, None) {
+                        Ok(result) => respond(&mut stream, Status::Ok, &result),
+                        Err(err) => {
+                            respond(&mut stream, error_status(&err), &error_body(&err))
+                        }
+                    }
+                }
+            }
+            (_, "/health" | "/comment") => respond(&mut stream, Status::MethodNotAllowed, &json!({ "error": "Method not allowed" })),
+            _ => respond(&mut stream, Status::NotFound, &json!({ "error": "Not found" })),
+        }
+    }
+
+    // Any web page the developer opens can send requests to localhost, and through DNS rebinding read the
+    // replies, so browsers (which send Origin) and other host names are turned away before the token is
+    // checked. Everything else needs the token.
+    fn authorize(&self, request: &Request) -> std::result::Result<(), (Status, &'static str)> {
+        if request.origin.is_some() {
+            return Err((Status::Forbidden, "Requests from browsers are not accepted"));
+        }
+        let hosts = [format!("localhost:{}", self.port), format!("127.0.0.1:{}", self.port)];
+        if !request.host.as_ref().is_some_and(|host| hosts.iter().any(|allowed| host.eq_ignore_ascii_case(allowed))) {
+            return Err((Status::Forbidden, "Unexpected Host header"));
+        }
+        let token = request.authorization.as_deref().and_then(|value| value.strip_prefix("Bearer "));
+        if !token.is_some_and(|token| constant_time_eq(token.trim().as_bytes(), self.token.as_bytes())) {
+            return Err((Status::Unauthorized, "Missing or wrong token; send \"Authorization: Bearer <token>\""));
+        }
+        Ok(())
+    }
+
+    // Server-sent events: "token" events while the model writes, then "done" with the result or "error"
+    fn stream_comment(&self, stream: &mut TcpStream, diff: Option<String>) -> Result<()> {
+        stream.write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        )?;
+
+        let mut on_text = |text: &str| send_event(stream, "token", &json!({ "text": text }));
+        let result = self.comment(diff, Some(&mut on_text));
+        match result {
+            Ok(result) => send_event(stream, "done", &result),
+            Err(err) => send_event(stream, "error", &error_body(&err)),
+        }
+    }
+
+    // Markdown and structured comment for the submitted diff. on_text receives the reply as it is
+    // streamed; composed comments are requested section by section and aren't streamed.
+    fn comment(
+        &self,
+        diff: Option<String>,
+        on_text: Option<&mut OnText>,
+    ) -> Result<Value> {
+        let (cli, raw_diff) = match diff {
+            // A submitted diff is filtered like a git diff, without a commit range to check, and gets
+            // --scope, .mrcommentignore and --exclude like one read from git
+            Some(diff) => {
+                let cli = Cli {
+                    commit: None,
+                    file: None,
+                    stash: None,
+                    ..self.cli.clone()
+                };
+                let diff = restrict_diff(&cli, diff)?;
+                (cli, diff)
+            }
+            None => (self.cli.clone(), read_diff(self.cli, self.config)?),
+        };
+
+        let prepared = prepare_diff(&cli, self.config, raw_diff)?;
+        let prompt = self.prompt.for_diff(&cli, &prepared.diff, &prepared.changed_files)?;
+        // Streamed replies can't be given up on for a fallback model, so with --deadline they aren't streamed
+        let streamed = composed_sections(&cli).is_none() && self.settings.deadline.is_none();
+        let mut on_text = on_text.filter(|_| streamed);
+        let mut retry = false;
+        let comment = comment_for(&cli, self.config, self.settings, &prompt, &prepared, false, |prompt, budget| {
+            // A retry without forbidden terms isn't streamed after the draft; the done event has it
+            let comment = match on_text.as_mut().filter(|_| !retry) {
+                Some(on_text) => stream_completion(
+                    self.settings,
+                    &prompt.system_message(),
+                    &diff_message(&prepared.diff, budget),
+                    &mut **on_text,
+                )?,
+                None => generate_text(&cli, &prepared.diff, self.settings, prompt, budget)?.text,
+            };
+            retry = true;
+            Ok(Generated::Text(comment))
+        })?
+        .into_markdown();
+
+        let structured = schema::MrComment::from_markdown(&comment);
+        let structured = finish_structured(&cli, self.config, structured, &prepared)?;
+        let markdown = finish_comment(&cli, self.config, comment, &prepared)?;
+        Ok(json!({ "markdown": markdown, "comment": structured }))
+    }
+}
+
+// Request line, headers and Content-Length body; Err((status, message)) for requests that are malformed,
+// too large or too slow to arrive
+fn read_request(stream: &mut TcpStream) -> Result<std::result::Result<Request, (Status, String)>> {
+    stream.set_read_timeout(Some(READ_TIMEOUT)).context("Failed to set the read timeout")?;
+    let started = Instant::now();
+    let mut reader = BufReader::new(stream);
+    let too_slow = || (Status::RequestTimeout, "Timed out reading the request".to_string());
+    let too_large = || (Status::HeaderFieldsTooLarge, format!("Request line and headers are larger than {} bytes", MAX_HEAD_BYTES));
+
+    // Reads a line of the request head, None when the head ran out of bytes or time
+    let mut head = (&mut reader).take(MAX_HEAD_BYTES);
+    let mut read_line = |line: &mut String| -> Result<std::result::Result<usize, (Status, String)>> {
+        let read = match head.read_line(line) {
+            Ok(read) => read,
+            Err(err) if is_timeout(&err) => return Ok(Err(too_slow())),
+            Err(err) => return Err(err).context("Failed to read request"),
+        };
+        if started.elapsed() > READ_TIMEOUT {
+            return Ok(Err(too_slow()));
+        }
+        if head.limit() == 0 && !line.ends_with('\n') {
+            return Ok(Err(too_large()));
+        }
+        Ok(Ok(read))
+    };
+
+    let mut line = String::new();
+    if let Err(err) = read_line(&mut line)? {
+        return Ok(Err(err));
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(Err((Status::BadRequest, "Malformed request line".to_string())));
+    };
+    let method = method.to_string();
+    let path = target.split('?').next().unwrap_or(target).to_string();
+
+    let mut content_length = 0;
+    let (mut host, mut origin, mut authorization) = (None, None, None);
+    loop {
+        let mut header = String::new();
+        match read_line(&mut header)? {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => return Ok(Err(err)),
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let (name, value) = (name.trim().to_ascii_lowercase(), value.trim());
+            match name.as_str() {
+                "content-length" => match value.parse() {
+                    Ok(length) => content_length = length,
+                    Err(_) => return Ok(Err((Status::BadRequest, "Invalid Content-Length".to_string()))),
+                },
+                "host" => host = Some(value.to_string()),
+                "origin" => origin = Some(value.to_string()),
+                "authorization" => authorization = Some(value.to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_BYTES {
+        return Ok(Err((Status::PayloadTooLarge, format!("Request body is larger than {} bytes", MAX_BODY_BYTES))));
+    }
+    let mut body = vec![0; content_length];
+    match reader.read_exact(&mut body) {
+        Ok(()) => {}
+        Err(err) if is_timeout(&err) => return Ok(Err(too_slow())),
+        Err(err) => return Err(err).context("Failed to read request body"),
+    }
+
+    Ok(Ok(Request {
+        method,
+        path,
+        host,
+        origin,
+        authorization,
+        body,
+    }))
+}
+
+// A read that hit the timeout; which of the two kinds it is depends on the platform
+fn is_timeout(err: &std::io::Error) -> bool {
+    matches!(err.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut)
+}
+
+fn respond(stream: &mut TcpStream, status: Status, body: &Value) -> Result<()> {
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status.code(),
+        status.reason(),
+        body.len(),
+        body
+    )
+    .context("Failed to write response")
+}
+
+// 256 bits from the OS-seeded keys of the standard library's hash maps, as no RNG crate is used
+fn random_token() -> String {
+    let mut hasher = Sha256::new();
+    for round in 0..4u64 {
+        let mut keyed = RandomState::new().build_hasher();
+        keyed.write_u64(round);
+        hasher.update(keyed.finish().to_le_bytes());
+    }
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    hasher.update(now.as_nanos().to_le_bytes());
+    hasher.update(process::id().to_le_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+// Comparing every byte, so the time taken doesn't tell how much of a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn send_event(stream: &mut TcpStream, event: &str, data: &Value) -> Result<()> {
+    write!(stream, "event: {}\ndata: {}\n\n", event, data).context("Failed to write event")?;
+    stream.flush().context("Failed to write event")
+}
+
+// Nothing to describe, or too much, is the client's problem; anything else (provider, git) is ours
+fn error_status(err: &anyhow::Error) -> Status {
+    match err.downcast_ref::<Failure>() {
+        Some(Failure::NoDiff) => Status::UnprocessableEntity,
+        Some(Failure::ContextTooLarge) => Status::PayloadTooLarge,
+        _ => Status::InternalServerError,
+    }
+}
+
+// The error with its kind, so plugins can tell failures apart without matching messages
+fn error_body(err: &anyhow::Error) -> Value {
+    let failure = err.downcast_ref::<Failure>();
+    json!({
+        "error": format!("{:#}", err),
+        "kind": failure.map_or("other", Failure::kind),
+        "remediation": failure.map(Failure::remediation)
+    })
+}
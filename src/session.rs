@@ -0,0 +1,209 @@
+use anyhow::{Context, Result};
+use clap::{Args, Subcommand};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::api_client::{self, ChatMessage, ChatRequest, Reply, Role};
+use crate::{check_cost, history, note, Cli, Config, ModelSettings};
+
+#[derive(Args, Clone)]
+pub struct SessionArgs {
+    #[command(subcommand)]
+    command: SessionCommand,
+}
+
+#[derive(Subcommand, Clone)]
+enum SessionCommand {
+    /// List the saved sessions, most recently used first
+    List,
+    /// Print the conversation of a session
+    Show { name: String },
+    /// Delete a session
+    Delete { name: String },
+}
+
+// A conversation with the model about one change, saved under <data dir>/mr-comment/sessions/<name>.json
+// after every reply so a later run, in another terminal or on another day, continues it. The messages
+// are provider-neutral, so a session can be continued with another model or provider.
+#[derive(Serialize, Deserialize)]
+struct Session {
+    // UTC, e.g. 2025-03-14T09:26:53Z, of the last reply
+    updated: String,
+    model: String,
+    // Of the diff message the conversation is about, to notice when the changes moved on
+    diff_sha256: String,
+    messages: Vec<ChatMessage>,
+}
+
+// The comment of the --session: the reply to a new conversation about the diff, the last reply again
+// when there is nothing new to ask, or the reply to --refine and, if the diff changed since, the new diff
+pub fn converse(
+    cli: &Cli,
+    config: &Config,
+    settings: &ModelSettings,
+    name: &str,
+    system: &str,
+    diff_message: &str,
+) -> Result<Reply> {
+    let diff_sha256 = format!("{:x}", Sha256::digest(diff_message.as_bytes()));
+    let refinement = cli.refine.as_ref().map(|instruction| {
+        format!("Revise the comment: {}\n\nReply with the whole comment in the same format.", instruction.trim())
+    });
+
+    let mut messages = match load(name)? {
+        None => vec![message(Role::User, diff_message.to_string())],
+        Some(session) if session.diff_sha256 == diff_sha256 => {
+            let Some(refinement) = refinement else {
+                note(&format!("Nothing new for session {}; using its last comment", name));
+                let last = session.messages.last().context("The session is empty")?;
+                return Ok(Reply {
+                    text: last.content.clone(),
+                    model: session.model,
+                });
+            };
+            let mut messages = session.messages;
+            messages.push(message(Role::User, refinement));
+            messages
+        }
+        Some(session) => {
+            note(&format!("The diff changed since session {} was saved; sending the new one", name));
+            let mut text = format!(
+                "The changes were updated. Write the comment again for the new diff, keeping what was asked for \
+                 so far.\n\n{}",
+                diff_message
+            );
+            if let Some(refinement) = refinement {
+                text = format!("{}\n\n{}", text, refinement);
+            }
+            let mut messages = session.messages;
+            messages.push(message(Role::User, text));
+            messages
+        }
+    };
+
+    // The whole conversation is sent again with every request
+    let transcript: Vec<&str> = messages.iter().map(|message| message.content.as_str()).collect();
+    check_cost(cli, config, settings, system, &transcript.join("\n\n"), 1)?;
+    let request = ChatRequest {
+        messages: messages.clone(),
+        ..ChatRequest::new(system, "")
+    };
+    let reply = api_client::send(settings, &request)?;
+
+    messages.push(message(Role::Assistant, reply.text.clone()));
+    let session = Session {
+        updated: history::utc_timestamp(SystemTime::now()),
+        model: reply.model.clone(),
+        diff_sha256,
+        messages,
+    };
+    save(name, &session)?;
+    Ok(reply)
+}
+
+// --refine continues a conversation, so it fails before anything is generated when there is none
+pub fn check(cli: &Cli) -> Result<()> {
+    if let (Some(name), Some(_)) = (&cli.session, &cli.refine) {
+        if load(name)?.is_none() {
+            anyhow::bail!("No session named {}; start it with --session {} before refining", name, name);
+        }
+    }
+    Ok(())
+}
+
+pub fn run(args: &SessionArgs) -> Result<()> {
+    match &args.command {
+        SessionCommand::List => {
+            let dir = dir()?;
+            let mut sessions: Vec<(String, Session)> = fs::read_dir(&dir)
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| {
+                    let path = entry.ok()?.path();
+                    let name = path.file_name()?.to_str()?.strip_suffix(".json")?.to_string();
+                    let session = serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+                    Some((name, session))
+                })
+                .collect();
+            if sessions.is_empty() {
+                println!("No sessions saved yet. Start one with --session <NAME>.");
+                return Ok(());
+            }
+            sessions.sort_by(|a, b| b.1.updated.cmp(&a.1.updated));
+            for (name, session) in sessions {
+                let refinements = session.messages.iter().filter(|message| message.role == Role::User).count().saturating_sub(1);
+                println!(
+                    "{}  {}  {}  {} refinement{}",
+                    session.updated,
+                    name,
+                    session.model,
+                    refinements,
+                    if refinements == 1 { "" } else { "s" }
+                );
+            }
+        }
+        SessionCommand::Show { name } => {
+            let session = load(name)?.with_context(|| format!("No session named {}", name))?;
+            for message in &session.messages {
+                let role = match message.role {
+                    Role::User => "user",
+                    Role::Assistant => "assistant",
+                };
+                println!("=== {} ===\n{}\n", role, message.content.trim_end());
+            }
+        }
+        SessionCommand::Delete { name } => {
+            let path = path(name)?;
+            if !path.exists() {
+                anyhow::bail!("No session named {}", name);
+            }
+            fs::remove_file(&path).with_context(|| format!("Failed to delete file: {}", path.display()))?;
+            println!("Session {} deleted", name);
+        }
+    }
+    Ok(())
+}
+
+fn message(role: Role, content: String) -> ChatMessage {
+    ChatMessage { role, content }
+}
+
+fn load(name: &str) -> Result<Option<Session>> {
+    let path = path(name)?;
+    let Ok(content) = fs::read_to_string(&path) else {
+        return Ok(None);
+    };
+    serde_json::from_str(&content)
+        .map(Some)
+        .with_context(|| format!("Failed to parse session file: {}", path.display()))
+}
+
+fn save(name: &str, session: &Session) -> Result<()> {
+    let dir = dir()?;
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create data directory: {}", dir.display()))?;
+    let path = path(name)?;
+    let json = serde_json::to_string_pretty(session).context("Failed to serialize the session")?;
+    fs::write(&path, json).with_context(|| format!("Failed to write to file: {}", path.display()))
+}
+
+fn dir() -> Result<PathBuf> {
+    let dir = dirs::data_dir().context("Could not find data directory")?;
+    Ok(dir.join("mr-comment").join("sessions"))
+}
+
+// Names become file names, so they can't reach outside the sessions directory
+pub fn parse_name(name: &str) -> Result<String, String> {
+    let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if name.is_empty() || name.starts_with('.') || !valid {
+        return Err(format!("invalid session name '{}' (use letters, digits, '-', '_' and '.')", name));
+    }
+    Ok(name.to_string())
+}
+
+fn path(name: &str) -> Result<PathBuf> {
+    let name = parse_name(name).map_err(|err| anyhow::anyhow!(err))?;
+    Ok(dir()?.join(format!("{}.json", name)))
+}
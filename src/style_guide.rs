@@ -0,0 +1,54 @@
+use anyhow::{Context, Result};
+use reqwest::blocking::Client;
+use sha2::{Digest, Sha256};
+use std::fs;
+
+use crate::api_client::provider_name;
+use crate::{complete, get_cache_dir, ModelSettings};
+
+const DISTILL_PROMPT: &str = "You condense writing style guides into rules for merge request descriptions. \
+Reply with a concise bulleted list of concrete, checkable rules (tone, terminology, formatting, structure). \
+Leave out anything unrelated to writing MR/PR descriptions. Output only the list.";
+
+// Read the style guide from a local file or URL
+fn read_source(source: &str) -> Result<String> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let response = Client::new()
+            .get(source)
+            .send()
+            .with_context(|| format!("Failed to fetch style guide: {}", source))?;
+
+        if !response.status().is_success() {
+            anyhow::bail!("Fetching style guide {} failed: {}", source, response.status());
+        }
+
+        response
+            .text()
+            .with_context(|| format!("Failed to read style guide: {}", source))
+    } else {
+        fs::read_to_string(source)
+            .with_context(|| format!("Failed to read style guide: {}", source))
+    }
+}
+
+// Distilled rules for the style guide, cached by content, prompt and model so the model is only asked
+// once per version
+pub fn load_rules(source: &str, settings: &ModelSettings) -> Result<String> {
+    let guide = read_source(source)?;
+
+    let key = [guide.as_str(), DISTILL_PROMPT, provider_name(settings), &settings.model].join("\0");
+    let hash = Sha256::digest(key.as_bytes());
+    let cache_path = get_cache_dir()?.join(format!("style-guide-{:x}.md", hash));
+
+    if let Ok(rules) = fs::read_to_string(&cache_path) {
+        return Ok(rules);
+    }
+
+    let rules = complete(settings, DISTILL_PROMPT, &guide)
+        .context("Failed to distill style guide")?;
+
+    fs::write(&cache_path, &rules)
+        .with_context(|| format!("Failed to write to file: {}", cache_path.display()))?;
+
+    Ok(rules)
+}
@@ -0,0 +1,102 @@
+use std::path::Path;
+
+use crate::diff::split_files;
+use crate::git;
+
+// Commit subjects listed per submodule; the rest are counted
+const MAX_COMMITS: usize = 5;
+
+// A submodule whose recorded commit changed, was added or was removed
+struct Update {
+    path: String,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+// One line per submodule pointer change: the old and new commits and, where the submodule is checked out
+// under root, the subjects of the commits in between. With fetch, a checkout that lacks the new commit
+// is fetched first.
+pub fn describe(raw_diff: &str, root: &Path, fetch: bool) -> Vec<String> {
+    updates(raw_diff)
+        .into_iter()
+        .map(|update| {
+            let short = |sha: &str| sha.chars().take(7).collect::<String>();
+            let (old, new) = match (&update.old, &update.new) {
+                (Some(old), Some(new)) => (old, new),
+                (None, Some(new)) => return format!("`{}` added at {}", update.path, short(new)),
+                (Some(old), None) => return format!("`{}` removed (was at {})", update.path, short(old)),
+                (None, None) => return format!("`{}` changed", update.path),
+            };
+
+            // An uninitialized submodule is an empty directory, where git would find the superproject
+            let checkout = root.join(&update.path);
+            let checked_out = checkout.join(".git").exists();
+            let mut log = checked_out.then(|| commit_log(&checkout, old, new)).flatten();
+            if log.is_none() && checked_out && fetch {
+                let fetched = git::command_in(&checkout).args(["fetch", "--quiet"]).status();
+                if fetched.is_ok_and(|status| status.success()) {
+                    log = commit_log(&checkout, old, new);
+                }
+            }
+
+            let range = format!("`{}` {} → {}", update.path, short(old), short(new));
+            match log {
+                Some((commits, backwards)) if !commits.is_empty() => {
+                    let direction = if backwards { "rolled back" } else { "new" };
+                    let mut subjects: Vec<String> = commits.iter().take(MAX_COMMITS).cloned().collect();
+                    if commits.len() > MAX_COMMITS {
+                        subjects.push(format!("and {} more", commits.len() - MAX_COMMITS));
+                    }
+                    format!(
+                        "{}, {} {} commit{}: {}",
+                        range,
+                        commits.len(),
+                        direction,
+                        if commits.len() == 1 { "" } else { "s" },
+                        subjects.join("; ")
+                    )
+                }
+                _ => range,
+            }
+        })
+        .collect()
+}
+
+// "Subproject commit <sha>" lines are all a submodule's diff has; a checkout with local changes adds "-dirty"
+fn updates(raw_diff: &str) -> Vec<Update> {
+    let (_, files) = split_files(raw_diff);
+    files
+        .iter()
+        .filter_map(|file| {
+            let commit = |prefix: char| {
+                file.lines.iter().find_map(|line| {
+                    let sha = line.strip_prefix(prefix)?.strip_prefix("Subproject commit ")?;
+                    Some(sha.trim().trim_end_matches("-dirty").to_string())
+                })
+            };
+            let (old, new) = (commit('-'), commit('+'));
+            (old.is_some() || new.is_some()).then(|| Update {
+                path: file.path.clone(),
+                old,
+                new,
+            })
+        })
+        .collect()
+}
+
+// Subjects of the commits between old and new, newest first, and whether the pointer moved backwards;
+// None when the checkout doesn't have both commits
+fn commit_log(checkout: &Path, old: &str, new: &str) -> Option<(Vec<String>, bool)> {
+    let log = |range: String| -> Option<Vec<String>> {
+        let output = git::command_in(checkout).args(["log", "--format=%s", &range]).output().ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect())
+    };
+    let forward = log(format!("{}..{}", old, new))?;
+    if !forward.is_empty() {
+        return Some((forward, false));
+    }
+    Some((log(format!("{}..{}", new, old))?, true))
+}
@@ -0,0 +1,160 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{check_cost, comment, complete_allowed, git, Cli, Config, ModelSettings};
+
+#[derive(Args, Clone)]
+pub struct SummaryArgs {
+    /// Start of the period, in any format git log accepts (e.g. "yesterday", "2025-03-01", "2 weeks ago")
+    #[arg(long, value_name = "DATE", default_value = "1 week ago")]
+    since: String,
+
+    /// End of the period (default: now)
+    #[arg(long, value_name = "DATE")]
+    until: Option<String>,
+
+    /// Commits by this author (name or email pattern; default: user.email of each repository)
+    #[arg(long, value_name = "PATTERN")]
+    author: Option<String>,
+
+    /// Repositories to include (comma-separated or repeated; default: the current one)
+    #[arg(long = "repo", value_name = "PATH", value_delimiter = ',')]
+    repos: Vec<PathBuf>,
+}
+
+// Commits read per repository, so a long period can't blow up the prompt
+const MAX_COMMITS: &str = "500";
+
+// Body lines kept per commit; the subject usually says enough
+const MAX_BODY_LINES: usize = 5;
+
+const SUMMARY_PROMPT: &str = "You are writing a developer's status update from the commits they made in the \
+period below, across one or more repositories and all of their branches. Write a narrative \"what I shipped\" \
+report as markdown with these sections:
+
+## Summary: [two or three sentences on the main themes of the period]
+## Shipped: [what was delivered, grouped under a ### heading per repository; describe outcomes, and merge \
+related commits into one bullet instead of listing them one by one]
+## In Progress: [work on branches that the commits show is not finished]
+## Next Steps: [follow-ups the commit messages mention]
+
+Write in the first person. Leave out sections with nothing to list. Don't invent work that isn't in the commits. \
+Output only the report.";
+
+// One repository's commits in the period, as given to the model
+struct RepoLog {
+    name: String,
+    commits: Vec<String>,
+}
+
+pub fn run(cli: &Cli, config: &Config, settings: &ModelSettings, args: &SummaryArgs) -> Result<()> {
+    let repos = if args.repos.is_empty() { vec![PathBuf::from(".")] } else { args.repos.clone() };
+
+    let mut logs = Vec::new();
+    for repo in &repos {
+        let log = read_log(repo, args).with_context(|| format!("Failed to read the log of {}", repo.display()))?;
+        if !log.commits.is_empty() {
+            logs.push(log);
+        }
+    }
+    if logs.is_empty() {
+        anyhow::bail!(
+            "No commits by {} since {} in {}",
+            args.author.as_deref().unwrap_or("you"),
+            args.since,
+            repos.iter().map(|repo| repo.display().to_string()).collect::<Vec<_>>().join(", ")
+        );
+    }
+
+    let period = match &args.until {
+        Some(until) => format!("Period: {} until {}", args.since, until),
+        None => format!("Period: since {}", args.since),
+    };
+    let repositories: Vec<String> = logs
+        .iter()
+        .map(|log| format!("Repository {} ({} commits):\n{}", log.name, log.commits.len(), log.commits.join("\n")))
+        .collect();
+    let user = format!("{}\n\n{}", period, repositories.join("\n\n"));
+
+    check_cost(cli, config, settings, SUMMARY_PROMPT, &user, 1)?;
+    let report = complete_allowed(config, settings, SUMMARY_PROMPT, &user)?;
+    let report = match &config.terminology {
+        Some(terms) => comment::apply_terminology(&report, terms)?,
+        None => report,
+    };
+
+    if let Some(path) = &cli.output {
+        fs::write(path, &report).with_context(|| format!("Failed to write to file: {}", path.display()))?;
+    } else {
+        println!("{}", report);
+    }
+    Ok(())
+}
+
+// Non-merge commits by the author on every local and remote branch, oldest first, with the branches
+// they are the tip of
+fn read_log(repo: &Path, args: &SummaryArgs) -> Result<RepoLog> {
+    let git = |git_args: &[&str]| -> Result<String> {
+        let output = git::command()
+            .arg("-C")
+            .arg(repo)
+            .args(git_args)
+            .output()
+            .context("Failed to execute git command")?;
+        if !output.status.success() {
+            return Err(git::error(git_args[0], &output.stderr));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+
+    let root = git(&["rev-parse", "--show-toplevel"])?;
+    let name = Path::new(&root)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or(root.clone());
+    let author = match &args.author {
+        Some(author) => author.clone(),
+        None => git(&["config", "user.email"]).context("No --author given and user.email is not set")?,
+    };
+
+    let since = format!("--since={}", args.since);
+    let author = format!("--author={}", author);
+    let mut log_args = vec![
+        "log",
+        "--branches",
+        "--remotes",
+        "--no-merges",
+        "--reverse",
+        "--date=short",
+        "--max-count",
+        MAX_COMMITS,
+        "--format=%x1e%h%x1f%ad%x1f%s%x1f%D%x1f%b",
+        &since,
+        &author,
+    ];
+    let until = args.until.as_ref().map(|until| format!("--until={}", until));
+    log_args.extend(until.as_deref());
+    let log = git(&log_args)?;
+
+    let commits = log
+        .split('\x1e')
+        .filter(|record| !record.trim().is_empty())
+        .map(|record| {
+            let fields: Vec<&str> = record.splitn(5, '\x1f').collect();
+            let field = |index: usize| fields.get(index).map_or("", |field| field.trim());
+            let refs = field(3);
+            let mut line = format!("- {} {} {}", field(1), field(0), field(2));
+            if !refs.is_empty() {
+                line += &format!(" [{}]", refs);
+            }
+            for body_line in field(4).lines().filter(|line| !line.trim().is_empty()).take(MAX_BODY_LINES) {
+                line += &format!("\n  {}", body_line.trim());
+            }
+            line
+        })
+        .collect();
+
+    Ok(RepoLog { name, commits })
+}
@@ -0,0 +1,421 @@
+use anyhow::{Context, Result};
+use clap::{Args, Subcommand};
+use minijinja::{Environment, UndefinedBehavior};
+use regex::Regex;
+use reqwest::blocking::Client;
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::borrow::Cow;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{detect_git_host, estimate_tokens, get_cache_dir, git, host_terms, warn, GitHost, PromptTemplate};
+
+#[derive(Args, Clone)]
+pub struct TemplateArgs {
+    #[command(subcommand)]
+    command: TemplateCommand,
+}
+
+#[derive(Subcommand, Clone)]
+enum TemplateCommand {
+    /// Check a prompt template for syntax errors, unknown variables and missing sections, and estimate its size
+    Check {
+        /// Template file or URL, as for "template" in the config
+        #[arg(value_name = "FILE")]
+        source: String,
+    },
+}
+
+// Sections of the built-in format that --format json reads into its fields
+const SCHEMA_SECTIONS: &[&str] = &["Key Changes", "Why These Changes", "Review Checklist", "Notes"];
+
+// Where GitLab looks for merge request description templates
+const MR_TEMPLATE_DIR: &str = ".gitlab/merge_request_templates";
+
+// GitHub pull request templates, used by "auto" when there is no GitLab template
+const PR_TEMPLATE_PATHS: &[&str] = &[
+    ".github/pull_request_template.md",
+    ".github/PULL_REQUEST_TEMPLATE.md",
+    "docs/pull_request_template.md",
+    "pull_request_template.md",
+];
+
+// Prompt template from a local file or a team-shared URL
+pub fn load(source: &str) -> Result<String> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        fetch_cached(source)
+    } else {
+        fs::read_to_string(source)
+            .with_context(|| format!("Failed to read template: {}", source))
+    }
+}
+
+// Revalidate the cached copy with its ETag so an unchanged template isn't downloaded again.
+// The cached copy is also used when the server can't be reached.
+fn fetch_cached(url: &str) -> Result<String> {
+    let hash = Sha256::digest(url.as_bytes());
+    let cache_dir = get_cache_dir()?;
+    let body_path = cache_dir.join(format!("template-{:x}.md", hash));
+    let etag_path = cache_dir.join(format!("template-{:x}.etag", hash));
+
+    let cached = fs::read_to_string(&body_path).ok();
+    let etag = fs::read_to_string(&etag_path).ok();
+
+    let mut request = Client::new().get(url);
+    if let (Some(_), Some(etag)) = (&cached, &etag) {
+        request = request.header(IF_NONE_MATCH, etag.trim());
+    }
+
+    let response = match request.send() {
+        Ok(response) => response,
+        Err(err) => {
+            let cached = cached.with_context(|| format!("Failed to fetch template: {}", url))?;
+            warn(&format!("could not fetch template {}, using cached copy: {}", url, err));
+            return Ok(cached);
+        }
+    };
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        if let Some(cached) = cached {
+            return Ok(cached);
+        }
+    }
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let cached = cached
+            .with_context(|| format!("Fetching template {} failed: {}", url, status))?;
+        warn(&format!("fetching template {} failed ({}), using cached copy", url, status));
+        return Ok(cached);
+    }
+
+    let new_etag = response
+        .headers()
+        .get(ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    let template = response
+        .text()
+        .with_context(|| format!("Failed to read template: {}", url))?;
+
+    fs::write(&body_path, &template)
+        .with_context(|| format!("Failed to write to file: {}", body_path.display()))?;
+    match new_etag {
+        Some(etag) => fs::write(&etag_path, etag)
+            .with_context(|| format!("Failed to write to file: {}", etag_path.display()))?,
+        None => {
+            let _ = fs::remove_file(&etag_path);
+        }
+    }
+
+    Ok(template)
+}
+
+// Repository MR template from a path, a template name (e.g. "Bug" for Bug.md), or "auto"
+pub fn load_mr_template(spec: &str) -> Result<String> {
+    let path = if spec != "auto" && Path::new(spec).is_file() {
+        PathBuf::from(spec)
+    } else {
+        find_mr_template(spec)?
+    };
+
+    fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read MR template: {}", path.display()))
+}
+
+// "auto" picks Default.md or the only template; names match template files case-insensitively
+fn find_mr_template(spec: &str) -> Result<PathBuf> {
+    let root = git::repo_root()?;
+
+    let mut templates: Vec<PathBuf> = fs::read_dir(root.join(MR_TEMPLATE_DIR))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("md")))
+                .collect()
+        })
+        .unwrap_or_default();
+    templates.sort();
+
+    let stem = |path: &PathBuf| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let named = |name: &str| templates.iter().find(|path| stem(path).eq_ignore_ascii_case(name)).cloned();
+    let names = || templates.iter().map(stem).collect::<Vec<_>>().join(", ");
+
+    if spec != "auto" {
+        return named(spec).with_context(|| {
+            format!("MR template not found: {} (templates in {}: {})", spec, MR_TEMPLATE_DIR, names())
+        });
+    }
+
+    if let Some(path) = named("default") {
+        return Ok(path);
+    }
+    if templates.len() == 1 {
+        return Ok(templates.remove(0));
+    }
+    if templates.len() > 1 {
+        anyhow::bail!(
+            "Several MR templates found ({}); choose one with --mr-template <name>",
+            names()
+        );
+    }
+
+    PR_TEMPLATE_PATHS
+        .iter()
+        .map(|path| root.join(path))
+        .find(|path| path.is_file())
+        .with_context(|| format!("No MR template found in {} or .github", MR_TEMPLATE_DIR))
+}
+
+// Values a prompt template can use, e.g. {{ branch }} or {% for file in files_changed %}
+#[derive(Serialize)]
+pub struct Variables {
+    pub platform: &'static str,
+    pub artifact: &'static str,
+    pub branch: String,
+    pub ticket: String,
+    pub author: String,
+    pub files_changed: Vec<String>,
+    pub language_breakdown: String,
+}
+
+// Languages by file extension for the language breakdown
+const LANGUAGES: &[(&str, &[&str])] = &[
+    ("Rust", &["rs"]),
+    ("TypeScript", &["ts", "tsx", "mts", "cts"]),
+    ("JavaScript", &["js", "jsx", "mjs", "cjs"]),
+    ("Python", &["py", "pyi"]),
+    ("Go", &["go"]),
+    ("Java", &["java"]),
+    ("Kotlin", &["kt", "kts"]),
+    ("Swift", &["swift"]),
+    ("C", &["c", "h"]),
+    ("C++", &["cc", "cpp", "cxx", "hpp", "hh"]),
+    ("C#", &["cs"]),
+    ("Ruby", &["rb"]),
+    ("PHP", &["php"]),
+    ("Shell", &["sh", "bash", "zsh"]),
+    ("SQL", &["sql"]),
+    ("HTML", &["html", "htm"]),
+    ("CSS", &["css", "scss", "sass", "less"]),
+    ("Markdown", &["md", "markdown"]),
+    ("YAML", &["yml", "yaml"]),
+    ("JSON", &["json"]),
+    ("TOML", &["toml"]),
+];
+
+// Render a prompt template with minijinja; unknown variables are an error rather than empty text
+pub fn render(template: &str, variables: &Variables) -> Result<String> {
+    let template = expand_legacy(template, variables);
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.render_str(&template, variables)
+        .map_err(|err| anyhow::anyhow!("Failed to render prompt template: {:#}", err))
+}
+
+// {platform} and {artifact} predate the template engine and keep working
+fn expand_legacy<'a>(template: &'a str, variables: &Variables) -> Cow<'a, str> {
+    let legacy = Regex::new(r"\{+(platform|artifact)\}+").unwrap();
+    legacy.replace_all(template, |caps: &regex::Captures| {
+        let text = &caps[0];
+        if text.starts_with("{{") {
+            return text.to_string();
+        }
+        match &caps[1] {
+            "platform" => variables.platform.to_string(),
+            _ => variables.artifact.to_string(),
+        }
+    })
+}
+
+// Findings of `mr-comment template check`: errors make the template unusable, warnings degrade some output
+pub struct Lint {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+    // The template rendered with the current repository's variables, when it renders
+    pub rendered: Option<String>,
+}
+
+// Problems of a prompt template that would otherwise only show as odd model output: syntax errors,
+// variables that don't exist, and sections the rest of the tool reads from the reply
+pub fn lint(template: &str, variables: &Variables) -> Lint {
+    let mut lint = Lint {
+        errors: Vec::new(),
+        warnings: Vec::new(),
+        rendered: None,
+    };
+    let expanded = expand_legacy(template, variables);
+    let env = Environment::new();
+    let parsed = match env.template_from_str(&expanded) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            lint.errors.push(err.to_string());
+            return lint;
+        }
+    };
+
+    let known: Vec<String> = match serde_json::to_value(variables) {
+        Ok(serde_json::Value::Object(fields)) => fields.keys().cloned().collect(),
+        _ => Vec::new(),
+    };
+    let globals: Vec<&str> = env.globals().map(|(name, _)| name).collect();
+    let mut unknown: Vec<String> = parsed
+        .undeclared_variables(false)
+        .into_iter()
+        .filter(|name| !known.contains(name) && !globals.contains(&name.as_str()))
+        .collect();
+    unknown.sort();
+    lint.errors.extend(
+        unknown
+            .iter()
+            .map(|name| format!("unknown variable `{}` (available: {})", name, known.join(", "))),
+    );
+    if !lint.errors.is_empty() {
+        return lint;
+    }
+
+    let rendered = match render(template, variables) {
+        Ok(rendered) => rendered,
+        Err(err) => {
+            lint.errors.push(format!("{:#}", err));
+            return lint;
+        }
+    };
+    // The comment parser finds the title by its label; without one the first line of the reply is used
+    let title_label = Regex::new(r"(?i)\b(?:MR/PR|MR|PR) Title\b").unwrap();
+    if !title_label.is_match(&rendered) {
+        lint.errors.push(format!(
+            "the output format has no \"{} Title:\" line, so the first line of the reply would become the title",
+            variables.artifact
+        ));
+    }
+    let lowercase = rendered.to_lowercase();
+    for section in SCHEMA_SECTIONS {
+        if !lowercase.contains(&section.to_lowercase()) {
+            lint.warnings.push(format!("no \"{}\" section; --format json would leave it empty", section));
+        }
+    }
+    lint.rendered = Some(rendered);
+    lint
+}
+
+pub fn run(args: &TemplateArgs) -> Result<()> {
+    let TemplateCommand::Check { source } = &args.command;
+    let template = load(source)?;
+    let host = detect_git_host().unwrap_or(GitHost::Unknown);
+    let (_, platform, artifact) = host_terms(host);
+    let lint = lint(&template, &variables(platform, artifact, None, "", &[]));
+
+    if lint.errors.is_empty() && lint.warnings.is_empty() {
+        println!("{}: ok", source);
+    } else {
+        println!("{}: {}, {}", source, count(lint.errors.len(), "error"), count(lint.warnings.len(), "warning"));
+    }
+    for error in &lint.errors {
+        println!("  error: {}", error);
+    }
+    for warning in &lint.warnings {
+        println!("  warning: {}", warning);
+    }
+    if lint.rendered.is_some() {
+        let prompt = PromptTemplate::from_template(host, &template)?.system_message();
+        let built_in = PromptTemplate::new(host).system_message();
+        println!(
+            "  system prompt: ~{} tokens before the diff (built-in prompt: ~{})",
+            estimate_tokens(&prompt),
+            estimate_tokens(&built_in)
+        );
+    }
+
+    if !lint.errors.is_empty() {
+        anyhow::bail!("Template {} has {}", source, count(lint.errors.len(), "error"));
+    }
+    Ok(())
+}
+
+fn count(n: usize, noun: &str) -> String {
+    format!("{} {}{}", n, noun, if n == 1 { "" } else { "s" })
+}
+
+// Variables for the diff being described; revision is the commit the branch and author are taken
+// from, None for uncommitted changes
+pub fn variables(
+    platform: &'static str,
+    artifact: &'static str,
+    revision: Option<&str>,
+    diff: &str,
+    changed_files: &[String],
+) -> Variables {
+    let branch = git::output(&["rev-parse", "--abbrev-ref", revision.unwrap_or("HEAD")]).unwrap_or_default();
+    let author = match revision {
+        Some(revision) => git::output(&["log", "-1", "--format=%an", revision]),
+        None => git::output(&["config", "user.name"]),
+    }
+    .unwrap_or_default();
+
+    Variables {
+        platform,
+        artifact,
+        ticket: ticket(&branch).unwrap_or_default(),
+        branch,
+        author,
+        files_changed: changed_files.to_vec(),
+        language_breakdown: language_breakdown(diff),
+    }
+}
+
+// Issue key in a branch name: "feature/PAY-123-refunds" -> "PAY-123", "42-fix-login" -> "#42"
+fn ticket(branch: &str) -> Option<String> {
+    let key = Regex::new(r"\b([A-Z][A-Z0-9]+-\d+)\b").unwrap();
+    let number = Regex::new(r"(?:^|/)(\d+)(?:[-_]|$)").unwrap();
+    key.captures(branch)
+        .map(|caps| caps[1].to_string())
+        .or_else(|| number.captures(branch).map(|caps| format!("#{}", &caps[1])))
+}
+
+// Share of changed lines per language, e.g. "Rust 80%, TOML 20%". Files are followed by their
+// "+++" lines, which the filtered diff keeps.
+fn language_breakdown(diff: &str) -> String {
+    let mut lines: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut language = None;
+    let mut in_hunk = false;
+    for line in diff.lines() {
+        if let Some(path) = line.strip_prefix("+++ ") {
+            let path = path.strip_prefix("b/").unwrap_or(path);
+            let extension = path.rsplit_once('.').map(|(_, ext)| ext.to_lowercase()).unwrap_or_default();
+            language = LANGUAGES
+                .iter()
+                .find(|(_, extensions)| extensions.contains(&extension.as_str()))
+                .map(|(language, _)| *language);
+            in_hunk = false;
+        } else if line.starts_with("@@") {
+            in_hunk = true;
+        } else if line.starts_with("diff --git ") || line.starts_with("--- ") {
+            in_hunk = false;
+        } else if let (true, Some(language)) = (in_hunk, language) {
+            if line.starts_with('+') || line.starts_with('-') {
+                *lines.entry(language).or_default() += 1;
+            }
+        }
+    }
+
+    let total: usize = lines.values().sum();
+    if total == 0 {
+        return String::new();
+    }
+    let mut shares: Vec<(&str, usize)> = lines.into_iter().filter(|(_, count)| *count > 0).collect();
+    shares.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    shares
+        .iter()
+        .map(|(language, count)| format!("{} {}%", language, (count * 100 + total / 2) / total))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,171 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{ACCEPT, USER_AGENT};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const RELEASES_URL: &str = "https://api.github.com/repos/RobertKozak/mr-comment/releases/latest";
+
+// Published next to the binaries, one "<sha256>  <asset name>" line per asset
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+
+#[derive(Args, Clone)]
+pub struct UpdateArgs {
+    /// Only report whether a newer release is available
+    #[arg(long)]
+    check: bool,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+// Replace the running executable with the latest GitHub release for this platform
+pub fn run(args: &UpdateArgs) -> Result<()> {
+    let client = Client::new();
+    // Mirrors (e.g. GitHub Enterprise) can serve the same releases API
+    let url = env::var("MR_COMMENT_RELEASES_URL").unwrap_or_else(|_| RELEASES_URL.to_string());
+
+    let release: Release = get(&client, &url)?
+        .json()
+        .context("Failed to parse release information")?;
+
+    let current = env!("CARGO_PKG_VERSION");
+    let latest = release.tag_name.trim_start_matches('v');
+    if version(latest) <= version(current) {
+        println!("mr-comment {} is up to date.", current);
+        return Ok(());
+    }
+
+    if args.check {
+        println!("mr-comment {} is available (installed: {}).", latest, current);
+        return Ok(());
+    }
+
+    let name = asset_name();
+    let binary = find_asset(&release, &name)?;
+    let checksums = find_asset(&release, CHECKSUMS_ASSET)?;
+
+    let checksums = get(&client, &checksums.browser_download_url)?
+        .text()
+        .context("Failed to read checksums")?;
+    let expected = checksums
+        .lines()
+        .find_map(|line| {
+            let (hash, file) = line.split_once(char::is_whitespace)?;
+            // sha256sum marks binary-mode entries with a leading '*'
+            (file.trim().trim_start_matches('*') == name).then(|| hash.to_lowercase())
+        })
+        .with_context(|| format!("No checksum for {} in {}", name, CHECKSUMS_ASSET))?;
+
+    println!("Downloading mr-comment {} ({})...", latest, name);
+    let bytes = get(&client, &binary.browser_download_url)?
+        .bytes()
+        .context("Failed to download release binary")?;
+
+    let actual = format!("{:x}", Sha256::digest(&bytes));
+    if actual != expected {
+        anyhow::bail!(
+            "Checksum mismatch for {}: expected {}, got {}. The executable was not replaced",
+            name,
+            expected,
+            actual
+        );
+    }
+
+    let exe = env::current_exe().context("Failed to locate the running executable")?;
+    let exe = exe.canonicalize().unwrap_or(exe);
+    replace_executable(&exe, &bytes)?;
+
+    println!("Updated mr-comment {} -> {} ({}).", current, latest, exe.display());
+    Ok(())
+}
+
+fn get(client: &Client, url: &str) -> Result<Response> {
+    let response = client
+        .get(url)
+        // The GitHub API rejects requests without a user agent
+        .header(USER_AGENT, concat!("mr-comment/", env!("CARGO_PKG_VERSION")))
+        .header(ACCEPT, "application/vnd.github+json, application/octet-stream")
+        .send()
+        .with_context(|| format!("Failed to fetch {}", url))?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("Fetching {} failed: {}", url, response.status());
+    }
+
+    Ok(response)
+}
+
+fn find_asset<'a>(release: &'a Release, name: &str) -> Result<&'a Asset> {
+    release
+        .assets
+        .iter()
+        .find(|asset| asset.name == name)
+        .with_context(|| format!("Release {} has no {} asset", release.tag_name, name))
+}
+
+// e.g. mr-comment-x86_64-linux, mr-comment-aarch64-macos, mr-comment-x86_64-windows.exe
+fn asset_name() -> String {
+    format!(
+        "mr-comment-{}-{}{}",
+        env::consts::ARCH,
+        env::consts::OS,
+        env::consts::EXE_SUFFIX
+    )
+}
+
+// Numeric components of "1.2.3"; anything after '-' (pre-release) is ignored
+fn version(text: &str) -> Vec<u64> {
+    text.split('-')
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+// Write next to the executable and rename over it, so a failed download never leaves a partial binary
+fn replace_executable(exe: &Path, bytes: &[u8]) -> Result<()> {
+    let staged = sibling(exe, "new");
+    fs::write(&staged, bytes)
+        .with_context(|| format!("Failed to write to file: {}", staged.display()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("Failed to make {} executable", staged.display()))?;
+    }
+
+    // Windows can't overwrite a running executable, but it can rename it out of the way
+    if cfg!(windows) {
+        let old = sibling(exe, "old");
+        let _ = fs::remove_file(&old);
+        fs::rename(exe, &old)
+            .with_context(|| format!("Failed to move {} aside", exe.display()))?;
+    }
+
+    fs::rename(&staged, exe).with_context(|| {
+        let _ = fs::remove_file(&staged);
+        format!("Failed to replace {}", exe.display())
+    })
+}
+
+fn sibling(exe: &Path, extension: &str) -> PathBuf {
+    let mut name = exe.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}", extension));
+    exe.with_file_name(name)
+}
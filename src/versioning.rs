@@ -0,0 +1,182 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::diff;
+use crate::labels::{self, Kind};
+use crate::metadata::Metadata;
+use crate::risk;
+use crate::git;
+
+// Reasons listed in the Versioning section, most significant first; the rest are counted
+const MAX_REASONS: usize = 8;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Bump {
+    Patch,
+    Minor,
+    Major,
+}
+
+// Semver bump for --suggest-version, with what calls for it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Recommendation {
+    pub bump: Bump,
+    // Latest version tag before the change, and the version the bump leads to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_version: Option<String>,
+    pub reasons: Vec<String>,
+}
+
+// A public item as declared on a changed line
+struct Definition {
+    name: String,
+    path: String,
+    line: String,
+}
+
+// Breaking changes the commits declare and removed or changed public items make a major bump,
+// feature commits and new public items a minor one, anything else a patch
+pub fn recommend(raw_diff: &str, metadata: Option<&Metadata>, base: &str) -> Recommendation {
+    let mut reasons: Vec<(Bump, String)> = Vec::new();
+    if let Some(metadata) = metadata {
+        for change in &metadata.breaking {
+            reasons.push((Bump::Major, format!("Breaking change: {}", change)));
+        }
+        for subject in &metadata.subjects {
+            match labels::subject_kind(subject) {
+                Some(Kind::Feature) => reasons.push((Bump::Minor, format!("Feature commit: {}", subject))),
+                Some(Kind::Bugfix) => reasons.push((Bump::Patch, format!("Fix commit: {}", subject))),
+                _ => {}
+            }
+        }
+    }
+
+    let (removed, added) = public_definitions(raw_diff);
+    for old in &removed {
+        match added.iter().find(|new| new.name == old.name) {
+            None => reasons.push((Bump::Major, format!("Removes public `{}` ({})", old.name, old.path))),
+            Some(new) if squash(&new.line) != squash(&old.line) => {
+                reasons.push((Bump::Major, format!("Changes the signature of `{}` ({})", old.name, new.path)))
+            }
+            Some(_) => {}
+        }
+    }
+    for new in added.iter().filter(|new| !removed.iter().any(|old| old.name == new.name)) {
+        reasons.push((Bump::Minor, format!("Adds public `{}` ({})", new.name, new.path)));
+    }
+
+    let bump = reasons.iter().map(|(bump, _)| *bump).max().unwrap_or(Bump::Patch);
+    if reasons.is_empty() {
+        reasons.push((Bump::Patch, "No breaking changes, new public items or feature commits".to_string()));
+    }
+    // Stable: reasons of one bump keep the order they were found in
+    reasons.sort_by_key(|(bump, _)| std::cmp::Reverse(*bump));
+    let mut reasons: Vec<String> = reasons.into_iter().map(|(_, reason)| reason).collect();
+    if reasons.len() > MAX_REASONS {
+        let rest = reasons.len() - MAX_REASONS + 1;
+        reasons.truncate(MAX_REASONS - 1);
+        reasons.push(format!("{} more", rest));
+    }
+
+    let versions = git::output(&["describe", "--tags", "--abbrev=0", base])
+        .and_then(|tag| next_version(&tag, bump).map(|next| (tag, next)));
+    Recommendation {
+        bump,
+        current_version: versions.as_ref().map(|(current, _)| current.clone()),
+        next_version: versions.map(|(_, next)| next),
+        reasons,
+    }
+}
+
+impl Recommendation {
+    // Body of the Versioning section
+    pub fn markdown(&self) -> String {
+        let bump = match self.bump {
+            Bump::Major => "major",
+            Bump::Minor => "minor",
+            Bump::Patch => "patch",
+        };
+        let versions = match (&self.current_version, &self.next_version) {
+            (Some(current), Some(next)) => format!(" ({} → {})", current, next),
+            _ => String::new(),
+        };
+        let reasons: Vec<String> = self.reasons.iter().map(|reason| format!("- {}", reason)).collect();
+        format!("Recommended bump: {}{}\n\n{}", bump, versions, reasons.join("\n"))
+    }
+}
+
+// The version tag bumped, keeping its prefix ("v1.4.2" to "v1.5.0"); before 1.0.0 breaking changes
+// bump the minor version, as Cargo and npm read 0.x versions. None for tags that aren't versions.
+fn next_version(tag: &str, bump: Bump) -> Option<String> {
+    let caps = Regex::new(r"^(.*?)(\d+)\.(\d+)\.(\d+)$").unwrap().captures(tag)?;
+    let part = |index: usize| caps[index].parse::<u64>().ok();
+    let (major, minor, patch) = (part(2)?, part(3)?, part(4)?);
+    let (major, minor, patch) = match bump {
+        Bump::Major if major == 0 => (0, minor + 1, 0),
+        Bump::Major => (major + 1, 0, 0),
+        Bump::Minor => (major, minor + 1, 0),
+        Bump::Patch => (major, minor, patch + 1),
+    };
+    Some(format!("{}{}.{}.{}", &caps[1], major, minor, patch))
+}
+
+// Public items declared on the removed and the added lines of source files, tests left out
+fn public_definitions(raw_diff: &str) -> (Vec<Definition>, Vec<Definition>) {
+    let patterns: [(&[&str], &str); 5] = [
+        (
+            &["rs"],
+            r#"^\s*pub\s+(?:(?:async|unsafe|const|extern(?:\s+"[^"]*")?)\s+)*(?:fn|struct|enum|trait|type|const|static|mod|union)\s+([A-Za-z_]\w*)"#,
+        ),
+        (
+            &["js", "jsx", "mjs", "ts", "tsx"],
+            r"^\s*export\s+(?:default\s+)?(?:declare\s+)?(?:abstract\s+)?(?:async\s+)?(?:function\*?|class|const|let|var|interface|type|enum)\s+([A-Za-z_$][\w$]*)",
+        ),
+        (&["go"], r"^(?:func\s+(?:\([^)]*\)\s*)?|type\s+)([A-Z]\w*)"),
+        (&["py"], r"^(?:async\s+)?(?:def|class)\s+([A-Za-z]\w*)"),
+        (
+            &["java", "kt", "cs"],
+            r"^\s*public\s+(?:(?:static|final|abstract|sealed|async|override|virtual|readonly)\s+)*(?:(?:class|interface|enum|record|struct)\s+(\w+)|[\w<>\[\],.?]+\s+(\w+)\s*\()",
+        ),
+    ];
+    let patterns: Vec<(&[&str], Regex)> = patterns
+        .into_iter()
+        .map(|(extensions, pattern)| (extensions, Regex::new(pattern).unwrap()))
+        .collect();
+
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    for file in diff::split_files(raw_diff).1 {
+        let extension = file.path.rsplit_once('.').map(|(_, extension)| extension).unwrap_or_default();
+        let Some((_, pattern)) = patterns.iter().find(|(extensions, _)| extensions.contains(&extension)) else {
+            continue;
+        };
+        if risk::is_test(&file.path) {
+            continue;
+        }
+        for line in &file.lines {
+            let (definitions, text) = match line.as_bytes().first() {
+                Some(b'-') if !line.starts_with("---") => (&mut removed, &line[1..]),
+                Some(b'+') if !line.starts_with("+++") => (&mut added, &line[1..]),
+                _ => continue,
+            };
+            let Some(caps) = pattern.captures(text) else {
+                continue;
+            };
+            if let Some(name) = caps.iter().skip(1).flatten().next() {
+                definitions.push(Definition {
+                    name: name.as_str().to_string(),
+                    path: file.path.clone(),
+                    line: text.to_string(),
+                });
+            }
+        }
+    }
+    (removed, added)
+}
+
+// Line without whitespace, so reformatting a signature doesn't count as changing it
+fn squash(line: &str) -> String {
+    line.chars().filter(|char| !char.is_whitespace()).collect()
+}
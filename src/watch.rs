@@ -0,0 +1,66 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{describe, read_diff, Cli, Config, ModelSettings};
+
+// How often the working tree diff is read
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// How long the diff has to stay the same before it's described, so a burst of saves costs one request
+const DEBOUNCE: Duration = Duration::from_secs(3);
+
+// Describe the working tree now and again whenever its diff changes; runs until interrupted
+pub fn run(cli: &Cli, config: &Config, settings: &ModelSettings) -> Result<()> {
+    // Hash of the diff last handled, and of the last one sent to the model, whose comment is still
+    // the current output; returning to it (e.g. after an undo) isn't billed again
+    let mut described: Option<Vec<u8>> = None;
+    let mut generated: Option<Vec<u8>> = None;
+    // Hash of a changed diff and when it was first seen
+    let mut pending: Option<(Vec<u8>, Instant)> = None;
+
+    loop {
+        let diff = read_diff(cli, config)?;
+        let hash = Sha256::digest(diff.as_bytes()).to_vec();
+
+        if described.as_ref() == Some(&hash) {
+            pending = None;
+        } else {
+            let settled = match &pending {
+                Some((pending_hash, since)) if *pending_hash == hash => since.elapsed() >= DEBOUNCE,
+                _ => {
+                    pending = Some((hash.clone(), Instant::now()));
+                    // Nothing described yet: don't make the first run wait
+                    described.is_none()
+                }
+            };
+
+            if settled {
+                if diff.trim().is_empty() {
+                    status(cli, "No changes, waiting for edits");
+                } else if generated.as_ref() == Some(&hash) {
+                    status(cli, "Back to the last described changes, keeping that comment");
+                } else {
+                    status(cli, "Describing the working tree changes");
+                    // A failed run is reported and retried on the next change, not fatal
+                    match describe(cli, config, settings, diff) {
+                        Ok(()) => generated = Some(hash.clone()),
+                        Err(err) => eprintln!("Error: {:#}", err),
+                    }
+                }
+                described = Some(hash);
+                pending = None;
+            }
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Progress goes to stderr so it doesn't mix with a comment printed to stdout
+fn status(cli: &Cli, message: &str) {
+    if !cli.quiet {
+        eprintln!("[watch] {}", message);
+    }
+}
@@ -0,0 +1,65 @@
+// Leads a word diff, so the model knows how to read the changed lines
+pub const NOTE: &str = "The diff is a word diff: lines starting with \"~\" were changed in place, with removed words \
+                        in [-...-] and added words in {+...+}; lines starting with \"+\" or \"-\" were added or removed whole.";
+
+// Rewrite `git diff --word-diff=porcelain` output, which has one line per run of words, as a unified
+// diff with one line per line of text. Lines changed in place start with "~" and carry the removed and
+// added words inline; whole added, removed and unchanged lines keep their usual prefix, so the rest of
+// the pipeline still recognizes new files, context and manifest changes.
+pub fn compact(porcelain: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut words: Vec<(char, &str)> = Vec::new();
+    let mut in_hunk = false;
+
+    for line in porcelain.lines() {
+        if line.starts_with("diff --git ") {
+            in_hunk = false;
+        } else if line.starts_with("@@") {
+            in_hunk = true;
+        } else if in_hunk {
+            match line.chars().next() {
+                // End of a line of text
+                Some('~') => {
+                    lines.push(join(&words));
+                    words.clear();
+                    continue;
+                }
+                Some(kind @ (' ' | '+' | '-')) => {
+                    words.push((kind, &line[1..]));
+                    continue;
+                }
+                // "\ No newline at end of file"
+                _ => {}
+            }
+        }
+        if !words.is_empty() {
+            lines.push(join(&words));
+            words.clear();
+        }
+        lines.push(line.to_string());
+    }
+    if !words.is_empty() {
+        lines.push(join(&words));
+    }
+
+    lines.join("\n")
+}
+
+fn join(words: &[(char, &str)]) -> String {
+    let text: String = words.iter().map(|(_, text)| *text).collect();
+    match words.first() {
+        None => " ".to_string(),
+        Some((kind, _)) if words.iter().all(|(other, _)| other == kind) => format!("{}{}", kind, text),
+        Some(_) => {
+            let marked: String = words
+                .iter()
+                .map(|(kind, text)| match kind {
+                    '-' => format!("[-{}-]", text),
+                    '+' => format!("{{+{}+}}", text),
+                    _ => text.to_string(),
+                })
+                .collect();
+            format!("~{}", marked)
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::BTreeMap;
+
+use crate::diff::VENDORED_DIRS;
+use crate::git;
+
+// Manifests marking a package: Cargo crates, npm/pnpm/yarn workspace packages and Go modules
+const MANIFESTS: &[&str] = &["Cargo.toml", "package.json", "go.mod"];
+
+// A package touched by the diff and the changed files that belong to it
+struct Package {
+    name: String,
+    dir: String,
+    files: usize,
+}
+
+// "Packages touched" section for repositories with more than one package, or None
+pub fn describe(changed_files: &[String]) -> Result<Option<String>> {
+    let Some(manifests) = tracked_manifests()? else {
+        return Ok(None);
+    };
+
+    let dirs: Vec<&str> = manifests.keys().map(String::as_str).collect();
+    if dirs.len() < 2 {
+        return Ok(None);
+    }
+
+    // Each file belongs to the package with the deepest directory containing it
+    let mut touched: BTreeMap<&str, usize> = BTreeMap::new();
+    for file in changed_files {
+        let dir = dirs
+            .iter()
+            .filter(|dir| dir.is_empty() || file.starts_with(&format!("{}/", dir)))
+            .max_by_key(|dir| dir.len());
+        if let Some(dir) = dir {
+            *touched.entry(dir).or_default() += 1;
+        }
+    }
+
+    if touched.is_empty() {
+        return Ok(None);
+    }
+
+    let packages: Vec<Package> = touched
+        .into_iter()
+        .map(|(dir, files)| Package {
+            name: package_name(dir, &manifests[dir]),
+            dir: dir.to_string(),
+            files,
+        })
+        .collect();
+
+    let mut section = if packages.len() == 1 {
+        "Package touched (monorepo):\n".to_string()
+    } else {
+        "Packages touched (monorepo). Group the Key Changes by package, with a `### <package>` \
+        subheading for each:\n"
+            .to_string()
+    };
+    for package in packages {
+        let location = if package.dir.is_empty() { "repository root" } else { &package.dir };
+        let label = if package.name == location {
+            package.name
+        } else {
+            format!("{} ({})", package.name, location)
+        };
+        section += &format!(
+            "• {}: {} changed file{}\n",
+            label,
+            package.files,
+            if package.files == 1 { "" } else { "s" }
+        );
+    }
+
+    Ok(Some(section))
+}
+
+// Package directories and their manifest paths, or None outside a repository
+fn tracked_manifests() -> Result<Option<BTreeMap<String, Vec<String>>>> {
+    let mut cmd = git::command();
+    cmd.args(["ls-files", "-z", "--full-name", "--"]);
+    for manifest in MANIFESTS {
+        cmd.arg(format!(":(top,glob)**/{}", manifest));
+    }
+    let output = cmd.output().context("Failed to execute git ls-files command")?;
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    let mut manifests: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for path in String::from_utf8_lossy(&output.stdout).split('\0').filter(|path| !path.is_empty()) {
+        // Manifests of vendored dependencies aren't packages of this repository
+        if path.split('/').any(|component| VENDORED_DIRS.contains(&component)) {
+            continue;
+        }
+        let dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        manifests.entry(dir.to_string()).or_default().push(path.to_string());
+    }
+
+    Ok(Some(manifests))
+}
+
+// Name declared in the package's manifest, falling back to its directory
+fn package_name(dir: &str, manifests: &[String]) -> String {
+    let cargo = Regex::new(r#"(?m)^\s*name\s*=\s*"([^"]+)""#).unwrap();
+    let go = Regex::new(r"(?m)^module\s+(\S+)").unwrap();
+
+    for path in manifests {
+        let Some(content) = read_indexed(path) else {
+            continue;
+        };
+        let name = if path.ends_with("Cargo.toml") {
+            // Only [package] names a crate; a virtual workspace manifest has none
+            content
+                .split_once("[package]")
+                .and_then(|(_, package)| cargo.captures(package.split("\n[").next().unwrap_or_default()))
+                .map(|caps| caps[1].to_string())
+        } else if path.ends_with("package.json") {
+            serde_json::from_str::<serde_json::Value>(&content)
+                .ok()
+                .and_then(|json| json.get("name")?.as_str().map(str::to_string))
+        } else {
+            go.captures(&content).map(|caps| caps[1].to_string())
+        };
+        if let Some(name) = name {
+            return name;
+        }
+    }
+
+    match dir.rsplit('/').next() {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => "repository root".to_string(),
+    }
+}
+
+// Manifest content from the index (paths are relative to the repository root, not the working directory)
+fn read_indexed(path: &str) -> Option<String> {
+    let output = git::command().args(["show", &format!(":{}", path)]).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
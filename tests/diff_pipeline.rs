@@ -0,0 +1,262 @@
+// The diff pipeline on curated `git diff -M -C` output, without git or a provider: how each file is
+// recognized and what the model is sent for it
+use mr_comment::diff::{self, FilterOptions};
+use mr_comment::encoding;
+
+// A fixture decoded the way the binary decodes git output
+fn fixture(bytes: &[u8]) -> String {
+    encoding::decode(bytes.to_vec()).0
+}
+
+fn options() -> FilterOptions {
+    FilterOptions {
+        max_new_file_bytes: 100,
+        ..Default::default()
+    }
+}
+
+fn process(diff: &str) -> String {
+    diff::process(diff, &options(), 3)
+}
+
+fn paths(diff: &str) -> Vec<String> {
+    diff::split_files(diff).1.into_iter().map(|file| file.path).collect()
+}
+
+#[test]
+fn renames_are_summarized_with_their_content_changes() {
+    let diff = fixture(include_bytes!("fixtures/renames.diff"));
+
+    let (_, files) = diff::split_files(&diff);
+    let moves: Vec<_> = files
+        .iter()
+        .map(|file| (file.moved_from.as_deref(), file.path.as_str(), file.similarity))
+        .collect();
+    assert_eq!(
+        moves,
+        [
+            (Some("docs/old.md"), "docs/new.md", Some(50)),
+            (Some("src/util.rs"), "src/helpers.rs", Some(100)),
+        ]
+    );
+
+    // The pure rename has nothing left to show and is only listed
+    assert_eq!(
+        process(&diff),
+        "index ce01362..94954ab 100644
+--- a/docs/old.md
++++ b/docs/new.md
+@@ -1 +1,2 @@
+ hello
++world
+
+Moved files:
+• docs/old.md → docs/new.md (50% similar)
+• src/util.rs → src/helpers.rs (100% similar)"
+    );
+}
+
+#[test]
+fn binary_files_are_listed_not_shown() {
+    let diff = fixture(include_bytes!("fixtures/binary.diff"));
+
+    assert_eq!(paths(&diff), ["assets/icon.bin", "assets/logo.png"]);
+    assert_eq!(
+        process(&diff),
+        "
+New files:
+• assets/icon.bin
+
+Binary files changed (not shown):
+• assets/logo.png"
+    );
+}
+
+#[test]
+fn new_and_deleted_files() {
+    let diff = fixture(include_bytes!("fixtures/new_deleted.diff"));
+
+    let (_, files) = diff::split_files(&diff);
+    let kinds: Vec<_> = files
+        .iter()
+        .map(|file| (file.path.as_str(), file.is_new, file.is_deleted))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            ("added.txt", true, false),
+            ("big.txt", true, false),
+            ("empty.txt", true, false),
+            ("gone.txt", false, true),
+            // git ends the +++ line with a tab when the path has a space
+            ("with space.txt", true, false),
+        ]
+    );
+
+    // Small new files are shown; large and empty ones are listed like deletions
+    assert_eq!(
+        process(&diff),
+        "new file mode 100644
+index 0000000..d0be0ff
+--- /dev/null
++++ b/added.txt
+@@ -0,0 +1 @@
++new small file
+new file mode 100644
+index 0000000..0679b7a
+--- /dev/null
++++ b/with space.txt\t
+@@ -0,0 +1 @@
++spaced out
+
+New files:
+• big.txt (1011 B, not shown)
+• empty.txt
+
+Deleted files:
+• gone.txt"
+    );
+}
+
+#[test]
+fn submodule_pointer_changes_are_kept() {
+    let diff = fixture(include_bytes!("fixtures/submodules.diff"));
+
+    assert_eq!(paths(&diff), ["libs/sub"]);
+    assert!(process(&diff).ends_with(
+        "-Subproject commit bf8947c3e70eb5f66fa22b5f42589ac860236301
++Subproject commit d89bdc4f30f54ca226f5a717f58f781aa6e65c7e"
+    ));
+}
+
+#[test]
+fn quoted_and_legacy_encoded_paths() {
+    let bytes = include_bytes!("fixtures/unicode.diff");
+    let (diff, transcoded) = encoding::decode(bytes.to_vec());
+
+    // The Latin-1 file is transcoded line by line; the rest of the diff is UTF-8 already
+    let transcoded: Vec<_> = transcoded.iter().map(|file| file.path.as_str()).collect();
+    assert_eq!(transcoded, ["latin1.txt"]);
+    assert!(diff.contains("+café crème\n"));
+
+    // Non-ASCII paths are C-quoted with octal escapes in the headers
+    assert_eq!(paths(&diff), ["latin1.txt", "ünï cödé.md"]);
+}
+
+#[test]
+fn legacy_encoded_files_are_named_like_the_diff_names_them() {
+    let bytes = include_bytes!("fixtures/legacy_paths.diff");
+    let (diff, transcoded) = encoding::decode(bytes.to_vec());
+
+    // A path with " b/" in it, and a C-quoted one
+    let transcoded: Vec<_> = transcoded.iter().map(|file| file.path.as_str()).collect();
+    assert_eq!(transcoded, ["notes b/caf.txt", "menü.txt"]);
+    assert_eq!(paths(&diff), transcoded);
+}
+
+#[test]
+fn unquoted_paths() {
+    // As git writes them with core.quotepath=false, which mr-comment sets
+    let diff = fixture(include_bytes!("fixtures/unquoted.diff"));
+
+    assert_eq!(paths(&diff), ["with space.txt", "ünï cödé.md"]);
+}
+
+#[test]
+fn crlf_line_endings_are_normalized() {
+    let diff = fixture(include_bytes!("fixtures/crlf.diff"));
+
+    assert!(!diff.contains('\r'));
+    assert_eq!(
+        process(&diff),
+        "index cf9b2a8..17866ec 100644
+--- a/crlf.txt
++++ b/crlf.txt
+@@ -1,2 +1,2 @@
+ line one
+-line two
++line 2"
+    );
+}
+
+#[test]
+fn mode_changes_keep_the_file_name() {
+    let diff = fixture(include_bytes!("fixtures/mode.diff"));
+
+    // Without ---/+++ lines only the "diff --git" line says which file changed mode
+    assert_eq!(
+        process(&diff),
+        "diff --git a/build.sh b/build.sh
+old mode 100644
+new mode 100755
+diff --git a/tools/run.sh b/scripts/run.sh
+old mode 100644
+new mode 100755
+
+Moved files:
+• tools/run.sh → scripts/run.sh (100% similar)"
+    );
+}
+
+#[test]
+fn grouped_by_area() {
+    let diff = fixture(include_bytes!("fixtures/renames.diff")) + &fixture(include_bytes!("fixtures/submodules.diff"));
+    let options = FilterOptions {
+        group: true,
+        ..options()
+    };
+
+    let processed = diff::process(&diff, &options, 3);
+    let headers: Vec<_> = processed.lines().filter(|line| line.starts_with("## Area:")).collect();
+    assert_eq!(headers, ["## Area: docs/new.md (1 file)", "## Area: libs/sub (1 file)"]);
+    assert!(processed.ends_with("Moved files:\n• docs/old.md → docs/new.md (50% similar)\n• src/util.rs → src/helpers.rs (100% similar)"));
+}
+
+#[test]
+fn only_whitespace_between_tokens_is_trivial() {
+    // Re-indented with tabs, and `a+b` spaced out
+    let changes = diff::trivial_changes(&fixture(include_bytes!("fixtures/whitespace.diff"))).unwrap();
+    assert_eq!(changes.len(), 1);
+    assert!(changes[0].whitespace);
+
+    // The same lines in another order, and two words joined, change the content
+    assert!(diff::trivial_changes(&fixture(include_bytes!("fixtures/swapped.diff"))).is_none());
+    assert!(diff::trivial_changes(&fixture(include_bytes!("fixtures/joined_words.diff"))).is_none());
+}
+
+#[test]
+fn empty_diff() {
+    assert_eq!(process(""), "");
+}
+
+#[test]
+fn header_lookalikes_inside_hunks_are_content() {
+    // A removed "-- /dev/null" and added "++ /dev/null" SQL comment, and added lines quoting patch headers
+    let diff = fixture(include_bytes!("fixtures/hunk_headers.diff"));
+
+    let (_, files) = diff::split_files(&diff);
+    let kinds: Vec<_> = files
+        .iter()
+        .map(|file| (file.path.as_str(), file.is_new, file.is_deleted))
+        .collect();
+    assert_eq!(kinds, [("db/schema.sql", false, false), ("docs/patches.md", false, false)]);
+    // Both are shown as changed files, whole
+    assert_eq!(
+        process(&diff),
+        "index 1111111..2222222 100644
+--- a/db/schema.sql
++++ b/db/schema.sql
+@@ -1,3 +1,3 @@
+ CREATE TABLE users (id int);
+--- /dev/null
++++ /dev/null
+ CREATE TABLE posts (id int);
+index 3333333..4444444 100644
+--- a/docs/patches.md
++++ b/docs/patches.md
+@@ -1 +1,3 @@
+ A patch starts with the paths of the file:
++-- a/example.txt
++++ b/example.txt"
+    );
+}
@@ -0,0 +1,135 @@
+// Forbidden terms outside of the describe command: a --stdin-json job run by the binary against a
+// local stand-in for the OpenAI API, in a scratch repository with its own config
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const DIFF: &str = "diff --git a/src/widget.rs b/src/widget.rs
+new file mode 100644
+index 0000000..1111111
+--- /dev/null
++++ b/src/widget.rs
+@@ -0,0 +1 @@
++pub fn widget() {}
+";
+
+const CONFIG: &str = r#"{ "forbidden": { "terms": ["Bluebird"], "replacements": { "Acme Corp": "a customer" } } }"#;
+
+// Answers the chat completion requests with the replies in turn, the last one over and over, and
+// returns the request bodies it was sent
+fn provider(replies: &[&str]) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}/v1/chat/completions", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let replies: Vec<String> = replies.iter().map(|reply| reply.to_string()).collect();
+
+    let received = Arc::clone(&requests);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(&mut stream);
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim_end().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let mut received = received.lock().unwrap();
+            received.push(String::from_utf8(body).unwrap());
+            let reply = &replies[(received.len() - 1).min(replies.len() - 1)];
+            let body = serde_json::json!({
+                "choices": [{ "message": { "content": reply }, "finish_reason": "stop" }],
+                "usage": { "prompt_tokens": 100, "completion_tokens": 50 }
+            })
+            .to_string();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+    (endpoint, requests)
+}
+
+// A repository with the config in its legacy place, which is found first
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mr-comment-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join(".mr-comment"), CONFIG).unwrap();
+    let status = Command::new("git").args(["init", "-q"]).current_dir(&dir).status().unwrap();
+    assert!(status.success());
+    dir
+}
+
+fn run_job(dir: &PathBuf, endpoint: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mr-comment"))
+        .args(["--stdin-json", "--provider", "openai", "--endpoint", endpoint])
+        .current_dir(dir)
+        .env("HOME", dir)
+        .env("OPENAI_API_KEY", "test")
+        .env_remove("XDG_CONFIG_HOME")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let job = serde_json::json!({ "diff": DIFF }).to_string();
+    child.stdin.take().unwrap().write_all(job.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn a_term_the_retry_keeps_fails_the_job() {
+    let dir = scratch("forbidden-kept");
+    let (endpoint, requests) = provider(&["MR Title: Add the Bluebird widget\n\nMR Summary: For Bluebird."]);
+
+    let output = run_job(&dir, &endpoint);
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(output.status.code(), Some(12));
+    assert_eq!(result["kind"], "forbidden");
+
+    // One retry, told which term to leave out; the term isn't named in the warning
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert!(!requests[0].contains("must never appear"));
+    assert!(requests[1].contains(r#"contained \"Bluebird\", which must never appear"#));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("Bluebird"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn the_retry_without_the_term_is_used() {
+    let dir = scratch("forbidden-retried");
+    let (endpoint, requests) = provider(&[
+        "MR Title: Add the Bluebird widget\n\nMR Summary: For Bluebird.",
+        "MR Title: Add a widget\n\nMR Summary: Asked for by Acme Corp.",
+    ]);
+
+    let output = run_job(&dir, &endpoint);
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(requests.lock().unwrap().len(), 2);
+
+    let markdown = result["markdown"].as_str().unwrap();
+    assert!(markdown.contains("Asked for by a customer."));
+    assert!(!markdown.contains("Bluebird"));
+    let _ = fs::remove_dir_all(&dir);
+}
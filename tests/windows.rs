@@ -0,0 +1,158 @@
+// What Windows checkouts and shells hand the tool: UTF-16 and Windows-1252 diffs, CRLF line endings,
+// paths with backslashes and drive letters, and git found through PATHEXT
+use std::fs;
+use std::path::PathBuf;
+
+use mr_comment::diff::{self, FilterOptions};
+use mr_comment::{encoding, paths};
+
+const CRLF_DIFF: &str = "diff --git a/src/app.rs b/src/app.rs\r
+index cf9b2a8..17866ec 100644\r
+--- a/src/app.rs\r
++++ b/src/app.rs\r
+@@ -1,2 +1,2 @@\r
+ fn main() {\r
+-    run();\r
++    run_all();\r
+diff --git a/libs/extern/zlib/inflate.c b/libs/extern/zlib/inflate.c\r
+index 1111111..2222222 100644\r
+--- a/libs/extern/zlib/inflate.c\r
++++ b/libs/extern/zlib/inflate.c\r
+@@ -1 +1 @@\r
+-int inflate;\r
++long inflate;\r
+diff --git a/old.txt b/old.txt\r
+deleted file mode 100644\r
+index 3333333..0000000\r
+--- a/old.txt\r
++++ /dev/null\r
+@@ -1 +0,0 @@\r
+-gone\r
+";
+
+// The diff as PowerShell's > writes it: UTF-16LE with a byte order mark
+fn utf16le(text: &str, bom: bool) -> Vec<u8> {
+    let bom = if bom { vec![0xFF, 0xFE] } else { Vec::new() };
+    bom.into_iter().chain(text.encode_utf16().flat_map(u16::to_le_bytes)).collect()
+}
+
+fn utf16be(text: &str) -> Vec<u8> {
+    [0xFE, 0xFF].into_iter().chain(text.encode_utf16().flat_map(u16::to_be_bytes)).collect()
+}
+
+#[test]
+fn utf16_diffs_are_transcoded_as_a_whole() {
+    let expected = CRLF_DIFF.replace("\r\n", "\n");
+
+    for bytes in [utf16le(CRLF_DIFF, true), utf16le(CRLF_DIFF, false), utf16be(CRLF_DIFF)] {
+        let (diff, transcoded) = encoding::decode(bytes);
+        assert_eq!(diff, expected);
+        // Nothing to warn about: the whole diff was UTF-16, not a file in it
+        assert!(transcoded.is_empty());
+    }
+}
+
+#[test]
+fn windows_1252_lines_are_transcoded_per_file() {
+    let mut bytes = b"diff --git a/README.md b/README.md\r\n--- a/README.md\r\n+++ b/README.md\r\n@@ -1 +1 @@\r\n-caf\xc3\xa9\r\n+caf\xc3\xa9 cr\xc3\xa8me\r\n".to_vec();
+    bytes.extend(b"diff --git a/notes.txt b/notes.txt\r\n--- a/notes.txt\r\n+++ b/notes.txt\r\n@@ -1 +1 @@\r\n-price\r\n+\x93price\x94 \x80 5\r\n");
+
+    let (diff, transcoded) = encoding::decode(bytes);
+    assert!(diff.contains("+café crème\n"));
+    assert!(diff.contains("+“price” € 5\n"));
+    assert!(!diff.contains('\r'));
+
+    let transcoded: Vec<_> = transcoded.iter().map(|file| (file.path.as_str(), file.encoding)).collect();
+    assert_eq!(transcoded, [("notes.txt", "Windows-1252")]);
+}
+
+#[test]
+fn crlf_diffs_are_filtered_like_lf_diffs() {
+    let options = FilterOptions {
+        vendored_dirs: vec![paths::repo_relative(r"libs\extern\", None)],
+        ..Default::default()
+    };
+    let expected = "index cf9b2a8..17866ec 100644
+--- a/src/app.rs
++++ b/src/app.rs
+@@ -1,2 +1,2 @@
+ fn main() {
+-    run();
++    run_all();
+
+Deleted files:
+• old.txt
+
+Vendored dependencies:
+• vendored dependency zlib updated
+";
+
+    // Decoded, as the binary reads git output, and as it is
+    let (decoded, _) = encoding::decode(CRLF_DIFF.as_bytes().to_vec());
+    assert_eq!(diff::filter_diff(&decoded, &options), expected);
+    assert_eq!(diff::filter_diff(CRLF_DIFF, &options), expected);
+
+    let (_, files) = diff::split_files(CRLF_DIFF);
+    let paths: Vec<_> = files.iter().map(|file| (file.path.as_str(), file.is_deleted)).collect();
+    assert_eq!(paths, [("src/app.rs", false), ("libs/extern/zlib/inflate.c", false), ("old.txt", true)]);
+}
+
+#[test]
+fn backslash_and_drive_letter_paths() {
+    // Relative paths only change their separators
+    assert_eq!(paths::repo_relative(r"services\payments\", None), "services/payments/");
+    assert_eq!(paths::repo_relative("services/payments", Some("C:/src/repo")), "services/payments");
+
+    // Absolute paths inside the repository become relative to it, whatever the case of the drive
+    assert_eq!(paths::repo_relative(r"C:\src\repo\vendor\lib", Some("C:/src/repo")), "vendor/lib");
+    assert_eq!(paths::repo_relative(r"c:\Src\Repo\vendor\lib", Some("C:/src/repo")), "vendor/lib");
+    assert_eq!(paths::repo_relative(r"C:\src\repo\vendor", Some(r"C:\src\repo\")), "vendor");
+
+    // Outside of it, or only sharing a prefix with it, they are kept
+    assert_eq!(paths::repo_relative(r"D:\src\repo\vendor", Some("C:/src/repo")), "D:/src/repo/vendor");
+    assert_eq!(paths::repo_relative(r"C:\src\repository\vendor", Some("C:/src/repo")), "C:/src/repository/vendor");
+
+    // Other systems' paths are case-sensitive
+    assert_eq!(paths::repo_relative("/home/dev/repo/vendor", Some("/home/dev/repo")), "vendor");
+    assert_eq!(paths::repo_relative("/home/dev/Repo/vendor", Some("/home/dev/repo")), "/home/dev/Repo/vendor");
+}
+
+// A scratch directory per test, as PATH entries
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mr-comment-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn git_is_found_through_pathext() {
+    let (empty, shims, installed) = (scratch("path-empty"), scratch("path-shims"), scratch("path-installed"));
+    fs::write(shims.join("git.cmd"), "").unwrap();
+    fs::write(installed.join("git.exe"), "").unwrap();
+    fs::write(installed.join("git.cmd"), "").unwrap();
+    let pathext = ".COM;.EXE;.BAT;.CMD";
+
+    // The first directory with a match wins, and in it the first extension in PATHEXT
+    let path = std::env::join_paths([&empty, &shims, &installed]).unwrap();
+    assert_eq!(paths::find_executable("git", &path, pathext), Some(shims.join("git.cmd")));
+    let path = std::env::join_paths([&empty, &installed, &shims]).unwrap();
+    assert_eq!(paths::find_executable("git", &path, pathext), Some(installed.join("git.exe")));
+
+    // Only the extensions PATHEXT lists count
+    let path = std::env::join_paths([&empty, &shims]).unwrap();
+    assert_eq!(paths::find_executable("git", &path, ".COM;.EXE"), None);
+
+    for dir in [empty, shims, installed] {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+#[cfg(windows)]
+#[test]
+fn the_shell_is_found_through_the_real_pathext() {
+    let path = std::env::var_os("PATH").unwrap();
+    let pathext = std::env::var("PATHEXT").unwrap();
+    let found = paths::find_executable("cmd", &path, &pathext).unwrap();
+    assert!(found.to_string_lossy().to_lowercase().ends_with(r"\cmd.exe"));
+}